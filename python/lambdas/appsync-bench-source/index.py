@@ -1,7 +1,12 @@
 # This Python lambda function is minimaly commented
 # Please refer the the Rust version of this lambda, which you may find more readable
+import base64
+import hashlib
+import hmac
 import json
 import os
+import time
+from collections import OrderedDict
 from decimal import Decimal
 
 import boto3
@@ -31,43 +36,173 @@ class AppSyncError(Exception):
         self.error_type = error_type
         self.error_message = error_message
 
-# Get the current game status from DynamoDB using partition key 'GAME_STATUS'
-def get_game_status():
-    return (backend_table.get_item(Key={'PK':'GAME_STATUS'})
-            .get('Item', {})
-            .get('game_status'))
+# Time spent by the request being handled, reported in the `server_timing` of the player
+# like the Rust lambda does. Requests are handled one at a time, so a global is enough.
+timings = {}
+
+# Send a DynamoDB request, adding its duration to the DynamoDB time of the request
+def timed(request, **kwargs):
+    start = time.perf_counter()
+    try:
+        return request(**kwargs)
+    finally:
+        timings['dynamodb'] += time.perf_counter() - start
+
+# Secrets are stored hashed in the PHC string format produced by the Rust `player_secret` lib:
+# '$pbkdf2-sha256$i=<iterations>[,l=<length>]$<salt>$<hash>', salt and hash in unpadded base64
+SECRET_HASH_ALGORITHM = 'pbkdf2-sha256'
+SECRET_HASH_ITERATIONS = 100000
+SECRET_SALT_LEN = 16
+
+def b64encode_unpadded(data):
+    return base64.b64encode(data).decode().rstrip('=')
+
+def b64decode_unpadded(data):
+    return base64.b64decode(data + '=' * (-len(data) % 4))
+
+def hash_secret(secret):
+    salt = os.urandom(SECRET_SALT_LEN)
+    derived = hashlib.pbkdf2_hmac('sha256', secret.encode(), salt, SECRET_HASH_ITERATIONS)
+    return f"${SECRET_HASH_ALGORITHM}$i={SECRET_HASH_ITERATIONS},l={len(derived)}${b64encode_unpadded(salt)}${b64encode_unpadded(derived)}"
+
+def verify_hashed_secret(secret, phc):
+    try:
+        empty, algorithm, params, salt, expected = phc.split('$')
+        params = dict(param.split('=') for param in params.split(','))
+        iterations = int(params['i'])
+        salt = b64decode_unpadded(salt)
+        expected = b64decode_unpadded(expected)
+    except (ValueError, KeyError):
+        return False
+    if empty != '' or algorithm != SECRET_HASH_ALGORITHM or iterations <= 0:
+        return False
+    derived = hashlib.pbkdf2_hmac('sha256', secret.encode(), salt, iterations, len(expected))
+    return hmac.compare_digest(derived, expected)
+
+# Secrets already verified by this lambda instance, by player, least recently used first
+# Verifying a hashed secret is deliberately slow, so it is only done on the first use of a secret
+# by an instance. Only a digest of the secret is kept, along with the stored secret it was
+# verified against: updates are conditioned on the latter being unchanged.
+MAX_CACHED_SECRETS = 10000
+verified_secrets = OrderedDict()
+
+# Returns the name and the value of the attribute storing the secret of the player, provided
+# `secret` was already verified against it by this instance
+def cached_secret(player_id, secret):
+    cached = verified_secrets.get(player_id)
+    if cached is None or not hmac.compare_digest(cached[0], hashlib.sha256(secret.encode()).digest()):
+        return None
+    verified_secrets.move_to_end(player_id)
+    return cached[1]
+
+def remember_secret(player_id, secret, stored_secret):
+    verified_secrets[player_id] = (hashlib.sha256(secret.encode()).digest(), stored_secret)
+    verified_secrets.move_to_end(player_id)
+    if len(verified_secrets) > MAX_CACHED_SECRETS:
+        verified_secrets.popitem(last=False)
+
+def forget_secret(player_id):
+    verified_secrets.pop(player_id, None)
+
+# Returns the name and the value of the attribute storing the secret of a player item
+def stored_secret(player):
+    if 'secret_hash' in player:
+        return ('secret_hash', player['secret_hash'])
+    if 'secret' in player:
+        return ('secret', player['secret'])
+    return None
+
+# Verify the secret of a player item, unless this instance already did, migrating a plaintext
+# secret to its hash
+# Returns the name and the value of the attribute storing the secret, to condition updates on
+def verify_player_secret(player, secret):
+    stored = stored_secret(player)
+    if stored is not None and cached_secret(player['id'], secret) == stored:
+        return stored
+    start = time.perf_counter()
+    if stored is None:
+        valid = False
+    elif stored[0] == 'secret_hash':
+        valid = verify_hashed_secret(secret, stored[1])
+    else:
+        valid = hmac.compare_digest(stored[1].encode(), secret.encode())
+    secret_hash = hash_secret(secret) if valid and stored[0] == 'secret' else None
+    timings['secret'] += time.perf_counter() - start
+    if not valid:
+        forget_secret(player['id'])
+        raise AppSyncError('InvalidSecret', 'Player secret is invalid')
+    if secret_hash is not None:
+        try:
+            timed(backend_table.update_item,
+                Key={'PK':f"PLAYER#{player['id']}"},
+                UpdateExpression="SET secret_hash = :secret_hash REMOVE secret",
+                ExpressionAttributeValues={
+                    ':secret' : secret,
+                    ':secret_hash' : secret_hash
+                },
+                ConditionExpression="attribute_exists(PK) AND secret = :secret"
+            )
+            stored = ('secret_hash', secret_hash)
+        except backend_table.meta.client.exceptions.ConditionalCheckFailedException:
+            # Migrated by another request meanwhile, the update will tell whether it still matches
+            pass
+    remember_secret(player['id'], secret, stored)
+    return stored
+
+# Get the current status of a game from DynamoDB using partition key 'GAME_STATUS#<game_id>'
+# A COUNTDOWN game is reported as STARTED once its 'starts_at' time is reached
+def get_game_status(game_id):
+    game_state = (timed(backend_table.get_item, Key={'PK':f'GAME_STATUS#{game_id}'})
+            .get('Item', {}))
+    game_status = game_state.get('game_status')
+    starts_at = game_state.get('starts_at')
+    if game_status == 'COUNTDOWN' and starts_at is not None and starts_at <= time.time():
+        return 'STARTED'
+    return game_status
 
 # Fetch a player record from DynamoDB using player ID
 def get_player(player_id):
-    player = (backend_table
-            .get_item(Key={'PK':f'PLAYER#{player_id}'})
+    player = (timed(backend_table.get_item, Key={'PK':f'PLAYER#{player_id}'})
             .get('Item'))
     if player is None:
         return None
     del player['PK']
     return player
 
-# Atomically increment a player's click counter in DynamoDB after verifying their secret
-def update_click(player_id, secret):
-    player = backend_table.update_item(
-        Key={'PK':f'PLAYER#{player_id}'},
-        UpdateExpression="SET #clicks = if_not_exists(#clicks, :zero) + :one",
-        ExpressionAttributeNames={
-            '#clicks' : 'clicks'
-        },
-        ExpressionAttributeValues={
-            ':zero' : 0,
-            ':one' : 1,
-            ':secret' : secret
-        },
-        ConditionExpression="attribute_exists(PK) AND secret = :secret",
-        ReturnValues='ALL_NEW'
-    ).get('Attributes')
+# Atomically increment a player's click counter in DynamoDB, provided their secret is still the
+# verified one, they are in the game and they are not banned
+def update_click(game_id, player_id, stored):
+    try:
+        player = timed(backend_table.update_item,
+            Key={'PK':f'PLAYER#{player_id}'},
+            UpdateExpression="SET #clicks = if_not_exists(#clicks, :zero) + :one",
+            ExpressionAttributeNames={
+                '#clicks' : 'clicks',
+                '#secret' : stored[0]
+            },
+            ExpressionAttributeValues={
+                ':zero' : 0,
+                ':one' : 1,
+                ':secret' : stored[1],
+                ':game_id' : game_id
+            },
+            ConditionExpression="attribute_exists(PK) AND #secret = :secret AND game_id = :game_id AND attribute_not_exists(banned)",
+            ReturnValues='ALL_NEW',
+            ReturnValuesOnConditionCheckFailure='ALL_OLD'
+        ).get('Attributes')
+    except backend_table.meta.client.exceptions.ConditionalCheckFailedException as e:
+        # The verified secret may be stale, it is verified again on the next click
+        forget_secret(player_id)
+        # The item as it was tells whether the condition failed because the player is banned
+        old_player = e.response.get('Item', {})
+        if 'banned' in old_player and old_player.get(stored[0], {}).get('S') == stored[1]:
+            raise AppSyncError('PlayerBanned', 'Player is banned from the game')
+        raise
     del player['PK']
     return player
 
 # Update player's latency statistics using optimistic locking to prevent concurrent updates
-def update_latency(player, report, secret):
+def update_latency(game_id, player, report, stored):
     player_id = player['id']
     clicks = Decimal(report['clicks'])
     avg_latency = Decimal(report['avg_latency'])
@@ -91,26 +226,28 @@ def update_latency(player, report, secret):
     expression_attribute_value={
         ':new_avg_latency' : new_avg_latency,
         ':new_avg_latency_clicks' : new_avg_latency_clicks,
-        ':secret' : secret
+        ':secret' : stored[1],
+        ':game_id' : game_id
     }
 
     # Use different conditions for first update vs subsequent updates
     condition = (
-        "attribute_exists(PK) AND secret = :secret AND #avg_latency = :old_avg_latency AND #avg_latency_clicks = :old_avg_latency_clicks"
+        "attribute_exists(PK) AND #secret = :secret AND game_id = :game_id AND attribute_not_exists(banned) AND #avg_latency = :old_avg_latency AND #avg_latency_clicks = :old_avg_latency_clicks"
         if has_previous_values
-        else "attribute_exists(PK) AND secret = :secret AND attribute_not_exists(#avg_latency) AND attribute_not_exists(#avg_latency_clicks)"
+        else "attribute_exists(PK) AND secret = :secret AND game_id = :game_id AND attribute_not_exists(banned) AND attribute_not_exists(#avg_latency) AND attribute_not_exists(#avg_latency_clicks)"
     )
     if has_previous_values:
         expression_attribute_value[':old_avg_latency'] = old_avg_latency
         expression_attribute_value[':old_avg_latency_clicks'] = old_avg_latency_clicks
 
     # Perform conditional update in DynamoDB
-    player = backend_table.update_item(
+    player = timed(backend_table.update_item,
         Key={'PK':f'PLAYER#{player_id}'},
         UpdateExpression="SET #avg_latency = :new_avg_latency, #avg_latency_clicks = :new_avg_latency_clicks",
         ExpressionAttributeNames={
             '#avg_latency' : 'avg_latency',
-            '#avg_latency_clicks' : 'avg_latency_clicks'
+            '#avg_latency_clicks' : 'avg_latency_clicks',
+            '#secret' : stored[0]
         },
         ExpressionAttributeValues=expression_attribute_value,
         ConditionExpression=condition,
@@ -120,20 +257,32 @@ def update_latency(player, report, secret):
     return player
 
 # Handle click mutation after verifying game is in progress
-def mutation_click(player_id, secret):
-    game_status = get_game_status()
+def mutation_click(game_id, player_id, secret):
+    game_status = get_game_status(game_id)
     if game_status is None or game_status != 'STARTED':
         raise AppSyncError('InvalidGameStatus', 'Game is not started')
-    return update_click(player_id, secret)
+    # The player is only read when this instance did not verify their secret yet
+    stored = cached_secret(player_id, secret)
+    if stored is None:
+        player = get_player(player_id)
+        if player is None or player.get('game_id') != game_id:
+            raise AppSyncError('PlayerNotFound', 'Player does not exist')
+        stored = verify_player_secret(player, secret)
+    return update_click(game_id, player_id, stored)
 
 # Handle latency report mutation after verifying game is in progress
-def mutation_report_latency(player_id, report, secret):
-    game_status = get_game_status()
+def mutation_report_latency(game_id, player_id, report, secret):
+    game_status = get_game_status(game_id)
     if game_status is None or game_status != 'STARTED':
         raise AppSyncError('InvalidGameStatus', 'Game is not started')
     player = get_player(player_id)
+    if player is None or player.get('game_id') != game_id:
+        raise AppSyncError('PlayerNotFound', 'Player does not exist')
+    stored = verify_player_secret(player, secret)
+    if player.get('banned'):
+        raise AppSyncError('PlayerBanned', 'Player is banned from the game')
 
-    return update_latency(player, report, secret)
+    return update_latency(game_id, player, report, stored)
 
 # Process AppSync event by extracting operation type and arguments
 def handle_appsync_event(event):
@@ -143,9 +292,9 @@ def handle_appsync_event(event):
 
     if op_type == 'Mutation':
         if op == 'clickPython':
-            return mutation_click(args['player_id'], args['secret'])
+            return mutation_click(args['game_id'], args['player_id'], args['secret'])
         elif op == 'reportLatencyPython':
-            return mutation_report_latency(args['player_id'], args['report'], args['secret'])
+            return mutation_report_latency(args['game_id'], args['player_id'], args['report'], args['secret'])
         else:
             Exception('Unknown operation')
 
@@ -154,8 +303,16 @@ def lambda_handler(event, context):
     print(json.dumps(event, default=str))
     results=[]
     for appsync_event in event:
+        start = time.perf_counter()
+        timings['dynamodb'] = 0
+        timings['secret'] = 0
         try:
             res = handle_appsync_event(appsync_event)
+            res['server_timing'] = {
+                'handler_ms': (time.perf_counter() - start) * 1000,
+                'dynamodb_ms': timings['dynamodb'] * 1000,
+                'secret_ms': timings['secret'] * 1000,
+            }
             results.append({
                 'data': res
             })
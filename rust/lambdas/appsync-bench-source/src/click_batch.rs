@@ -0,0 +1,141 @@
+use lambda_appsync::AWSTimestamp;
+
+use crate::rate_limit::RateLimit;
+
+/// Largest number of clicks a player can send in a single batch, whatever their burst
+pub const MAX_CLICK_BATCH_SIZE: i32 = 50;
+/// Longest time a batch of clicks can span on the client, in milliseconds
+pub const MAX_CLICK_BATCH_WINDOW_MS: i32 = 10_000;
+
+/// Clicks of a player recorded at once, by a single update of the player item
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClickBatch {
+    /// Number of clicks
+    pub count: u32,
+    /// Time the clicks are received, against which the game status is checked
+    pub at: AWSTimestamp,
+}
+
+impl ClickBatch {
+    /// A single click received at `at`
+    pub fn single(at: AWSTimestamp) -> Self {
+        ClickBatch { count: 1, at }
+    }
+
+    /// Largest number of clicks a batch can hold under `rate_limit`
+    ///
+    /// A batch is recorded by taking all its tokens from the bucket of the player at once, so it
+    /// can never hold more clicks than the bucket: [RateLimit::burst]. The window of the batch is
+    /// not credited, as it is only claimed by the client.
+    pub fn max_count(rate_limit: &RateLimit) -> i32 {
+        MAX_CLICK_BATCH_SIZE.min(rate_limit.burst.floor() as i32)
+    }
+
+    /// Checks that `count` clicks made over `client_window_ms` milliseconds are within the bounds
+    /// of a batch under `rate_limit`, see [ClickBatch::max_count]
+    ///
+    /// The token bucket of the player still has to hold `count` tokens for the batch to be
+    /// recorded.
+    ///
+    /// Returns the reason the batch is invalid, if any
+    pub fn validate(
+        count: i32,
+        client_window_ms: i32,
+        at: AWSTimestamp,
+        rate_limit: &RateLimit,
+    ) -> Result<Self, String> {
+        let max_count = Self::max_count(rate_limit);
+        if !(1..=max_count).contains(&count) {
+            return Err(format!(
+                "count must be between 1 and {max_count}, got {count}"
+            ));
+        }
+        if !(0..=MAX_CLICK_BATCH_WINDOW_MS).contains(&client_window_ms) {
+            return Err(format!(
+                "client_window_ms must be between 0 and {MAX_CLICK_BATCH_WINDOW_MS}, \
+                got {client_window_ms}"
+            ));
+        }
+        Ok(ClickBatch {
+            count: count as u32,
+            at,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rate_limit::TokenBucket;
+
+    const RATE_LIMIT: RateLimit = RateLimit {
+        max_clicks_per_second: 10.0,
+        burst: 5.0,
+    };
+
+    fn validate(count: i32, client_window_ms: i32) -> Result<ClickBatch, String> {
+        ClickBatch::validate(
+            count,
+            client_window_ms,
+            AWSTimestamp::from(1000),
+            &RATE_LIMIT,
+        )
+    }
+
+    /// The rate limit of the deployed Rust bench lambda, from the template
+    fn deployed_rate_limit() -> RateLimit {
+        let template = include_str!("../../../../templates/graphqlapi.yml");
+        let variable = |name: &str| -> f64 {
+            template
+                .lines()
+                .find_map(|line| line.trim().strip_prefix(name)?.strip_prefix(':'))
+                .unwrap_or_else(|| panic!("{name} is set in the template"))
+                .trim()
+                .parse()
+                .unwrap()
+        };
+        RateLimit {
+            max_clicks_per_second: variable("MAX_CLICKS_PER_SECOND"),
+            burst: variable("CLICK_BURST"),
+        }
+    }
+
+    #[test]
+    fn test_validate_bounds() {
+        assert!(validate(0, 100).is_err());
+        assert!(validate(-1, 100).is_err());
+        assert_eq!(validate(1, 0).map(|batch| batch.count), Ok(1));
+        assert!(validate(1, -1).is_err());
+        assert!(validate(1, MAX_CLICK_BATCH_WINDOW_MS).is_ok());
+        assert!(validate(1, MAX_CLICK_BATCH_WINDOW_MS + 1).is_err());
+    }
+
+    #[test]
+    fn test_validate_burst() {
+        // The burst can be sent at once
+        assert!(validate(5, 0).is_ok());
+        assert!(validate(6, 0).is_err());
+        // The window claimed by the client does not allow more
+        assert!(validate(6, MAX_CLICK_BATCH_WINDOW_MS).is_err());
+        // Nor does a burst larger than the largest batch
+        let rate_limit = RateLimit {
+            max_clicks_per_second: 100.0,
+            burst: 100.0,
+        };
+        assert_eq!(ClickBatch::max_count(&rate_limit), MAX_CLICK_BATCH_SIZE);
+    }
+
+    #[test]
+    fn test_largest_batch_fits_deployed_bucket() {
+        let rate_limit = deployed_rate_limit();
+        let max_count = ClickBatch::max_count(&rate_limit);
+        assert!(max_count >= 1);
+        let batch =
+            ClickBatch::validate(max_count, 0, AWSTimestamp::from(1000), &rate_limit).unwrap();
+        // An idle player can send the largest batch
+        assert!(TokenBucket::take(None, 1000, &rate_limit, batch.count).is_some());
+        assert!(
+            ClickBatch::validate(max_count + 1, 0, AWSTimestamp::from(1000), &rate_limit).is_err()
+        );
+    }
+}
@@ -1,31 +1,54 @@
 use std::collections::HashMap;
 
-use aws_sdk_dynamodb::types::{AttributeValue, ReturnValue};
-use dynamodb_utils::{DynamoDBItem, DynamoItem, PK, table_name};
+use aws_sdk_dynamodb::{
+    operation::update_item::builders::UpdateItemFluentBuilder,
+    types::{AttributeValue, ReturnValue, ReturnValuesOnConditionCheckFailure},
+};
+use dynamodb_utils::{
+    BANNED_ATTRIBUTE, CLICKS_OPEN_AT_ATTRIBUTE, DynamoDBItem, DynamoItem, PK,
+    STATE_VERSION_ATTRIBUTE, table_name,
+};
 
-use lambda_appsync::{ID, log};
+use lambda_appsync::{AWSTimestamp, ID, log};
+use latency_histogram::{
+    LatencyHistogram, SAMPLES_ATTRIBUTE, TOTAL_ATTRIBUTE, UNREPORTED_ATTRIBUTE,
+};
+use player_secret::{HASH_ATTRIBUTE, PLAINTEXT_ATTRIBUTE, StoredSecret};
 use serde_dynamo::to_attribute_value;
 
-use crate::{GameStatus, Player, dynamodb};
+use crate::{
+    GameState, LatencyPercentiles, Player, Team, click_batch::ClickBatch, dynamodb,
+    rate_limit::TokenBucket, server_timing::Timed,
+};
 
-impl GameStatus {
-    /// The partition key (PK) value used to store the game status in DynamoDB
+impl GameState {
+    /// The partition key (PK) value used to store the game state in DynamoDB
     const PK_TYPE: &'static str = "GAME_STATUS";
     /// The attribute name storing the actual game status value
-    const PROPERTY_NAME: &'static str = "game_status";
+    const STATUS_PROPERTY_NAME: &'static str = "game_status";
+    /// The name of the [GameState] field holding the game status
+    const STATUS_FIELD_NAME: &'static str = "status";
+
+    /// Generates the full partition key for the state of a game from its ID
+    fn pk_from_uuid(game_id: ID) -> String {
+        format!("{}#{}", Self::PK_TYPE, game_id)
+    }
 }
 
-/// DynamoDB storage implementation for [GameStatus]
-impl DynamoDBItem for GameStatus {
-    type Id = ();
+/// DynamoDB storage implementation for [GameState]
+impl DynamoDBItem for GameState {
+    type Id = ID;
 
     fn get_key(&self) -> DynamoItem {
-        Self::get_key_from_id(())
+        Self::get_key_from_id(self.game_id)
     }
 
-    fn get_key_from_id(_id: Self::Id) -> DynamoItem {
-        // GameStatus is a singleton, so we use a fixed PK
-        HashMap::from([(PK.to_owned(), AttributeValue::S(Self::PK_TYPE.to_owned()))])
+    fn get_key_from_id(game_id: Self::Id) -> DynamoItem {
+        // Each game has its own GameState
+        HashMap::from([(
+            PK.to_owned(),
+            AttributeValue::S(Self::pk_from_uuid(game_id)),
+        )])
     }
 
     fn get_type() -> &'static str {
@@ -34,37 +57,103 @@ impl DynamoDBItem for GameStatus {
 
     fn to_item(&self) -> DynamoItem {
         let mut item = self.to_item_core();
-        // Store the enum value under the PROPERTY_NAME attribute
-        item.insert(
-            Self::PROPERTY_NAME.to_owned(),
-            to_attribute_value(self).unwrap(),
-        );
+        let mut inner: DynamoItem = serde_dynamo::to_item(self).expect("valid schema");
+        // The status is stored under STATUS_PROPERTY_NAME, as expected by every backend
+        if let Some(status) = inner.remove(Self::STATUS_FIELD_NAME) {
+            item.insert(Self::STATUS_PROPERTY_NAME.to_owned(), status);
+        }
+        item.extend(inner);
         item
     }
 
     fn from_item(mut item: DynamoItem) -> Self {
-        // Extract and deserialize the enum value
-        item.remove(Self::PROPERTY_NAME)
-            .map(|a| serde_dynamo::from_attribute_value(a).expect("valid schema"))
-            .expect("valid schema")
+        if let Some(status) = item.remove(Self::STATUS_PROPERTY_NAME) {
+            item.insert(Self::STATUS_FIELD_NAME.to_owned(), status);
+        }
+        serde_dynamo::from_item(item).expect("valid schema")
     }
 }
 
-/// Retrieves the current [GameStatus] from DynamoDB
+/// Extracts the version of the game state from a game state or player item, if it has one
+fn state_version_from_item(item: &DynamoItem) -> Option<u64> {
+    item.get(STATE_VERSION_ATTRIBUTE).map(|state_version| {
+        serde_dynamo::from_attribute_value(state_version.clone()).expect("valid schema")
+    })
+}
+
+/// Retrieves the current [GameState] of a game from DynamoDB, along with its version (0 if it
+/// was written before states were versioned)
+///
+/// The read is strongly consistent, so the state is at least as recent as the time of the call.
 ///
 /// # Returns
-/// Returns  [Ok(None)] if no game status is set yet
-pub async fn dynamodb_get_game_status() -> Result<Option<GameStatus>, aws_sdk_dynamodb::Error> {
-    log::debug!("ENTER dynamodb_get_game_status");
+/// Returns  [Ok(None)] if the game does not exist
+pub async fn dynamodb_get_game_state(
+    game_id: ID,
+) -> Result<Option<(GameState, u64)>, aws_sdk_dynamodb::Error> {
+    log::debug!("ENTER dynamodb_get_game_state - game_id={game_id}");
 
     Ok(dynamodb()
         .get_item()
         .table_name(table_name())
-        .set_key(Some(GameStatus::get_key_from_id(())))
+        .set_key(Some(GameState::get_key_from_id(game_id)))
+        .consistent_read(true)
         .send()
+        .timed()
         .await?
         .item
-        .map(GameStatus::from_item))
+        .map(|item| {
+            let state_version = state_version_from_item(&item).unwrap_or_default();
+            (GameState::from_item(item), state_version)
+        }))
+}
+
+impl Team {
+    /// The partition key (PK) prefix used for team items
+    const PK_TYPE: &'static str = "TEAM";
+
+    /// Generates the full partition key for a team from its ID
+    fn pk_from_id(id: &str) -> String {
+        format!("{}#{}", Self::PK_TYPE, id)
+    }
+}
+
+/// DynamoDB storage implementation for Team
+impl DynamoDBItem for Team {
+    type Id = String;
+
+    fn get_key(&self) -> DynamoItem {
+        Self::get_key_from_id(self.id.clone())
+    }
+
+    fn get_key_from_id(id: Self::Id) -> DynamoItem {
+        HashMap::from([(PK.to_owned(), AttributeValue::S(Self::pk_from_id(&id)))])
+    }
+
+    fn get_type() -> &'static str {
+        Self::PK_TYPE
+    }
+}
+
+/// Retrieves a [Team] from DynamoDB by its ID
+///
+/// A [Team::builtin] team that was never stored is returned as is.
+///
+/// # Returns
+/// Returns [Ok(None)] if the team does not exist
+pub async fn dynamodb_get_team(team_id: String) -> Result<Option<Team>, aws_sdk_dynamodb::Error> {
+    log::debug!("ENTER dynamodb_get_team - team_id={team_id}");
+
+    let team = dynamodb()
+        .get_item()
+        .table_name(table_name())
+        .set_key(Some(Team::get_key_from_id(team_id.clone())))
+        .send()
+        .timed()
+        .await?
+        .item
+        .map(Team::from_item);
+    Ok(team.or_else(|| Team::builtin().into_iter().find(|team| team.id == team_id)))
 }
 
 impl Player {
@@ -77,6 +166,8 @@ impl Player {
     }
 }
 
+latency_histogram::impl_latency_statistics!(Player, LatencyPercentiles);
+
 /// DynamoDB storage implementation for Player
 impl DynamoDBItem for Player {
     type Id = ID;
@@ -92,13 +183,85 @@ impl DynamoDBItem for Player {
     fn get_type() -> &'static str {
         Self::PK_TYPE
     }
+
+    fn from_item(item: DynamoItem) -> Self {
+        Self::from_item_with_latency(item).0
+    }
 }
 
-/// Retrieves a [Player] from DynamoDB by their ID
+/// Adds the counts of `histogram` to the bucket attributes of the item updated by `update`
+///
+/// Returns the update along with the terms of the `ADD` clause of its update expression
+fn add_latency_histogram(
+    mut update: UpdateItemFluentBuilder,
+    histogram: &LatencyHistogram,
+) -> (UpdateItemFluentBuilder, Vec<String>) {
+    let mut terms = Vec::new();
+    for (bucket, count) in histogram.buckets() {
+        update = update
+            .expression_attribute_names(
+                format!("#latency_hist_{bucket}"),
+                LatencyHistogram::attribute_name(bucket),
+            )
+            .expression_attribute_values(
+                format!(":latency_hist_{bucket}"),
+                AttributeValue::N(count.to_string()),
+            );
+        terms.push(format!("#latency_hist_{bucket} :latency_hist_{bucket}"));
+    }
+    (update, terms)
+}
+
+/// A player item, along with its attributes that are not part of [Player]
+#[derive(Debug, Clone)]
+pub struct PlayerRecord {
+    pub player: Player,
+    /// Secret of the player, [None] if their item has none (e.g. it was edited by hand)
+    pub stored_secret: Option<StoredSecret>,
+    pub bucket: Option<TokenBucket>,
+    /// Whether an admin banned the player, whose clicks and reports must be rejected
+    pub banned: bool,
+    /// Number of clicks of the player whose latency is not reported yet
+    pub unreported_clicks: Option<i64>,
+    /// Time from which the game of the player accepts clicks, [None] if it does not
+    pub clicks_open_at: Option<AWSTimestamp>,
+    /// Version of the game state `clicks_open_at` was copied from, [None] if it was not copied
+    /// since the player registered
+    pub state_version: Option<u64>,
+}
+
+impl PlayerRecord {
+    /// Parses a player item
+    pub fn from_item(item: DynamoItem) -> Self {
+        PlayerRecord {
+            stored_secret: StoredSecret::from_item(&item),
+            bucket: TokenBucket::from_item(&item),
+            banned: item.contains_key(BANNED_ATTRIBUTE),
+            unreported_clicks: item.get(UNREPORTED_ATTRIBUTE).map(|clicks| {
+                serde_dynamo::from_attribute_value(clicks.clone()).expect("valid schema")
+            }),
+            clicks_open_at: item.get(CLICKS_OPEN_AT_ATTRIBUTE).map(|clicks_open_at| {
+                serde_dynamo::from_attribute_value(clicks_open_at.clone()).expect("valid schema")
+            }),
+            state_version: state_version_from_item(&item),
+            player: Player::from_item(item),
+        }
+    }
+
+    /// Returns whether the game of the player accepts clicks (and latency reports) at `now`
+    pub fn accepts_clicks(&self, now: AWSTimestamp) -> bool {
+        self.clicks_open_at
+            .is_some_and(|clicks_open_at| clicks_open_at <= now)
+    }
+}
+
+/// Retrieves a [Player] from DynamoDB by their ID, along with their stored secret and token bucket
 ///
 /// # Returns
 /// Returns [Ok(None)] if the player does not exist
-pub async fn dynamodb_get_player(player_id: ID) -> Result<Option<Player>, aws_sdk_dynamodb::Error> {
+pub async fn dynamodb_get_player(
+    player_id: ID,
+) -> Result<Option<PlayerRecord>, aws_sdk_dynamodb::Error> {
     log::debug!("ENTER dynamodb_get_player - player_id={player_id}");
 
     Ok(dynamodb()
@@ -106,113 +269,232 @@ pub async fn dynamodb_get_player(player_id: ID) -> Result<Option<Player>, aws_sd
         .table_name(table_name())
         .set_key(Some(Player::get_key_from_id(player_id)))
         .send()
+        .timed()
         .await?
         .item
-        .map(Player::from_item))
+        .map(PlayerRecord::from_item))
 }
 
-/// Increments a player's click counter atomically, after verifying their secret
+/// Replaces the plaintext secret of a player by its hash
 ///
-/// If the clicks attribute doesn't exist yet, it will be initialized to 1
-pub async fn dynamodb_update_player_click(
+/// The update is conditioned on the plaintext secret being unchanged
+pub async fn dynamodb_migrate_player_secret(
     player_id: ID,
-    secret: String,
-) -> Result<Player, aws_sdk_dynamodb::Error> {
-    log::debug!("ENTER dynamodb_player_click - player_id={player_id}");
-    Ok(dynamodb()
+    plaintext_secret: &StoredSecret,
+    secret_hash: String,
+) -> Result<(), aws_sdk_dynamodb::Error> {
+    log::debug!("ENTER dynamodb_migrate_player_secret - player_id={player_id}");
+
+    dynamodb()
         .update_item()
         .table_name(table_name())
         .set_key(Some(Player::get_key_from_id(player_id)))
-        .update_expression("SET #clicks = if_not_exists(#clicks, :zero) + :one")
-        .expression_attribute_names("#clicks", "clicks")
-        .expression_attribute_values(":zero", AttributeValue::N("0".to_owned()))
-        .expression_attribute_values(":one", AttributeValue::N("1".to_owned()))
-        .expression_attribute_values(":secret", AttributeValue::S(secret))
-        // Verify the player exists and the secret matches
-        .condition_expression(format!("attribute_exists({PK}) AND secret = :secret"))
-        .return_values(ReturnValue::AllNew)
+        .update_expression("SET #secret_hash = :secret_hash REMOVE #secret")
+        .expression_attribute_names("#secret_hash", HASH_ATTRIBUTE)
+        .expression_attribute_names("#secret", PLAINTEXT_ATTRIBUTE)
+        .expression_attribute_values(":secret_hash", AttributeValue::S(secret_hash))
+        .expression_attribute_values(":secret", plaintext_secret.to_attribute_value())
+        .condition_expression(format!("attribute_exists({PK}) AND #secret = :secret"))
         .send()
-        .await?
-        .attributes
-        .map(Player::from_item)
-        .expect("asked for them"))
+        .timed()
+        .await?;
+    Ok(())
 }
 
-/// Updates a player's latency statistics, using optimistic locking to prevent concurrent updates
+/// Adds a `batch` of clicks to a player's click counter atomically, after verifying their game
+/// accepts clicks at the time of the batch, their team, that they are not banned and that their
+/// stored secret is still the one the provided secret was verified against
+///
+/// Whether the game accepts clicks is checked against the copy of its state on the player item,
+/// so the click costs a single request.
 ///
-/// The old values are used as update conditions to ensure no concurrent update happened. They should either both be None
-/// (for first update) or both be Some.
-/// Note that concurrent updates should never happen because the frontend is set to send a report per second,
-/// which is plently enough to finish an update before the following one.
-pub async fn dynamodb_update_player_latency_stats(
+/// The token bucket of the player is replaced by `new_bucket`, provided it is still `old_bucket`.
+/// If any condition fails, the [aws_sdk_dynamodb::Error::ConditionalCheckFailedException] holds
+/// the player item, so the caller can tell which one.
+///
+/// If the clicks attribute doesn't exist yet, it will be initialized to the size of the batch. The
+/// clicks are also counted as not reported yet, for [dynamodb_add_player_latency].
+pub async fn dynamodb_update_player_click(
+    game_id: ID,
     player_id: ID,
-    secret: String,
-    old_avg_latency: Option<f64>,
-    old_avg_latency_clicks: Option<i32>,
-    new_avg_latency: f64,
-    new_avg_latency_clicks: i32,
-) -> Result<Player, aws_sdk_dynamodb::Error> {
+    team_id: &str,
+    stored_secret: &StoredSecret,
+    old_bucket: Option<TokenBucket>,
+    new_bucket: TokenBucket,
+    batch: ClickBatch,
+) -> Result<PlayerRecord, aws_sdk_dynamodb::Error> {
     log::debug!(
-        "ENTER dynamodb_update_player_latency_stats - \
-        player_id={player_id} \
-        old_avg_latency={old_avg_latency:?} old_avg_latency_clicks={old_avg_latency_clicks:?} \
-        new_avg_latency={new_avg_latency} new_avg_latency_clicks={new_avg_latency_clicks}"
+        "ENTER dynamodb_player_click - game_id={game_id} player_id={player_id} \
+        team_id={team_id} old_bucket={old_bucket:?} new_bucket={new_bucket:?} batch={batch:?}"
     );
-
-    // Start building the update operation with the new values
     let update = dynamodb()
         .update_item()
         .table_name(table_name())
         .set_key(Some(Player::get_key_from_id(player_id)))
         .update_expression(
-            "SET #avg_latency = :new_avg_latency, #avg_latency_clicks = :new_avg_latency_clicks",
+            "SET #clicks = if_not_exists(#clicks, :zero) + :count, \
+            #rate_tokens = :new_rate_tokens, #rate_updated_at = :new_rate_updated_at \
+            ADD #unreported_clicks :count",
         )
-        .expression_attribute_names("#avg_latency", "avg_latency")
-        .expression_attribute_names("#avg_latency_clicks", "avg_latency_clicks")
+        .expression_attribute_names("#clicks", "clicks")
+        .expression_attribute_names("#unreported_clicks", UNREPORTED_ATTRIBUTE)
+        .expression_attribute_names("#rate_tokens", TokenBucket::TOKENS_PROPERTY_NAME)
+        .expression_attribute_names("#rate_updated_at", TokenBucket::UPDATED_AT_PROPERTY_NAME)
         .expression_attribute_values(
-            ":new_avg_latency",
-            to_attribute_value(new_avg_latency).unwrap(),
+            ":new_rate_tokens",
+            to_attribute_value(new_bucket.tokens).unwrap(),
         )
         .expression_attribute_values(
-            ":new_avg_latency_clicks",
-            to_attribute_value(new_avg_latency_clicks).unwrap(),
+            ":new_rate_updated_at",
+            to_attribute_value(new_bucket.updated_at).unwrap(),
         )
-        .expression_attribute_values(":secret", AttributeValue::S(secret))
-        .condition_expression(format!("attribute_exists({PK}) AND secret = :secret"));
+        .expression_attribute_values(":zero", AttributeValue::N("0".to_owned()))
+        .expression_attribute_values(":count", to_attribute_value(batch.count).unwrap())
+        .expression_attribute_names("#stored_secret", stored_secret.attribute_name())
+        .expression_attribute_values(":stored_secret", stored_secret.to_attribute_value())
+        .expression_attribute_values(":game_id", to_attribute_value(game_id).unwrap())
+        .expression_attribute_values(":team", AttributeValue::S(team_id.to_owned()))
+        .expression_attribute_names("#banned", BANNED_ATTRIBUTE)
+        .expression_attribute_names("#clicks_open_at", CLICKS_OPEN_AT_ATTRIBUTE)
+        .expression_attribute_values(":now", to_attribute_value(batch.at).unwrap())
+        .return_values_on_condition_check_failure(ReturnValuesOnConditionCheckFailure::AllOld);
 
-    // Add optimistic locking condition based on old values
-    let update = match (old_avg_latency, old_avg_latency_clicks) {
-        // If we had previous values, ensure they haven't changed
-        (Some(old_avg_latency), Some(old_avg_latency_clicks)) => update
+    // Verify the player exists, the secret is unchanged, the player is enrolled in the game
+    // and the team, the game accepts clicks, the player is not banned, and no other click
+    // updated the token bucket meanwhile
+    let condition = format!(
+        "attribute_exists({PK}) AND #stored_secret = :stored_secret AND game_id = :game_id \
+        AND team = :team AND #clicks_open_at <= :now AND attribute_not_exists(#banned)"
+    );
+    let update = match old_bucket {
+        Some(old_bucket) => update
             .condition_expression(format!(
-                "attribute_exists({PK}) AND secret = :secret \
-                AND #avg_latency = :old_avg_latency \
-                AND #avg_latency_clicks = :old_avg_latency_clicks"
+                "{condition} AND #rate_tokens = :old_rate_tokens \
+                AND #rate_updated_at = :old_rate_updated_at"
             ))
             .expression_attribute_values(
-                ":old_avg_latency",
-                to_attribute_value(old_avg_latency).unwrap(),
+                ":old_rate_tokens",
+                to_attribute_value(old_bucket.tokens).unwrap(),
             )
             .expression_attribute_values(
-                ":old_avg_latency_clicks",
-                to_attribute_value(old_avg_latency_clicks).unwrap(),
+                ":old_rate_updated_at",
+                to_attribute_value(old_bucket.updated_at).unwrap(),
             ),
-        // For first update, ensure attributes don't exist yet
-        (None, None) => update.condition_expression(format!(
-            "attribute_exists({PK}) AND secret = :secret \
-            AND attribute_not_exists(#avg_latency) \
-            AND attribute_not_exists(#avg_latency_clicks)"
+        None => update.condition_expression(format!(
+            "{condition} AND attribute_not_exists(#rate_updated_at)"
         )),
-        _ => unreachable!(
-            "Functionnal error, old_avg_latency and old_avg_latency_clicks \
-            can only be both None or both Some"
-        ),
     };
+
+    Ok(update
+        .return_values(ReturnValue::AllNew)
+        .send()
+        .timed()
+        .await?
+        .attributes
+        .map(PlayerRecord::from_item)
+        .expect("asked for them"))
+}
+
+/// Counts a `batch` of clicks of a player rejected because of the rate limit
+///
+/// Like the clicks themselves, this is only done if the secret and game of the player are
+/// verified, and if the game accepts clicks at the time of the batch. If any condition fails,
+/// the [aws_sdk_dynamodb::Error::ConditionalCheckFailedException] holds the player item.
+pub async fn dynamodb_count_rate_limited_click(
+    game_id: ID,
+    player_id: ID,
+    stored_secret: &StoredSecret,
+    batch: ClickBatch,
+) -> Result<(), aws_sdk_dynamodb::Error> {
+    log::debug!(
+        "ENTER dynamodb_count_rate_limited_click - game_id={game_id} player_id={player_id} \
+        batch={batch:?}"
+    );
+    dynamodb()
+        .update_item()
+        .table_name(table_name())
+        .set_key(Some(Player::get_key_from_id(player_id)))
+        .update_expression("ADD #rate_limited_clicks :count")
+        .expression_attribute_names("#rate_limited_clicks", "rate_limited_clicks")
+        .expression_attribute_values(":count", to_attribute_value(batch.count).unwrap())
+        .expression_attribute_names("#stored_secret", stored_secret.attribute_name())
+        .expression_attribute_values(":stored_secret", stored_secret.to_attribute_value())
+        .expression_attribute_values(":game_id", to_attribute_value(game_id).unwrap())
+        .expression_attribute_names("#clicks_open_at", CLICKS_OPEN_AT_ATTRIBUTE)
+        .expression_attribute_values(":now", to_attribute_value(batch.at).unwrap())
+        .condition_expression(format!(
+            "attribute_exists({PK}) AND #stored_secret = :stored_secret AND game_id = :game_id \
+            AND #clicks_open_at <= :now"
+        ))
+        .return_values_on_condition_check_failure(ReturnValuesOnConditionCheckFailure::AllOld)
+        .send()
+        .timed()
+        .await?;
+    Ok(())
+}
+
+/// Adds the latencies of a report of `clicks` clicks, averaging `avg_latency`, to the latency
+/// counters and histogram of a player, after verifying their game, their team, that they are
+/// not banned, that their stored secret is still the one the provided secret was verified
+/// against and that they made enough clicks not reported yet
+///
+/// Only counters are updated, so concurrent reports never conflict. If any condition fails, the
+/// [aws_sdk_dynamodb::Error::ConditionalCheckFailedException] holds the player item, so the
+/// caller can tell which one.
+pub async fn dynamodb_add_player_latency(
+    game_id: ID,
+    player_id: ID,
+    team_id: &str,
+    stored_secret: &StoredSecret,
+    clicks: i32,
+    avg_latency: f64,
+    histogram: &LatencyHistogram,
+) -> Result<PlayerRecord, aws_sdk_dynamodb::Error> {
+    log::debug!(
+        "ENTER dynamodb_add_player_latency - game_id={game_id} player_id={player_id} \
+        team_id={team_id} clicks={clicks} avg_latency={avg_latency}"
+    );
+    let update = dynamodb()
+        .update_item()
+        .table_name(table_name())
+        .set_key(Some(Player::get_key_from_id(player_id)))
+        .expression_attribute_names("#total_latency", TOTAL_ATTRIBUTE)
+        .expression_attribute_names("#latency_samples", SAMPLES_ATTRIBUTE)
+        .expression_attribute_names("#unreported_clicks", UNREPORTED_ATTRIBUTE)
+        .expression_attribute_values(
+            ":total_latency",
+            to_attribute_value(avg_latency * clicks as f64).unwrap(),
+        )
+        .expression_attribute_values(":clicks", to_attribute_value(clicks).unwrap())
+        .expression_attribute_values(":minus_clicks", to_attribute_value(-clicks).unwrap())
+        .expression_attribute_names("#stored_secret", stored_secret.attribute_name())
+        .expression_attribute_values(":stored_secret", stored_secret.to_attribute_value())
+        .expression_attribute_values(":game_id", to_attribute_value(game_id).unwrap())
+        .expression_attribute_values(":team", AttributeValue::S(team_id.to_owned()))
+        .expression_attribute_names("#banned", BANNED_ATTRIBUTE)
+        .return_values_on_condition_check_failure(ReturnValuesOnConditionCheckFailure::AllOld);
+    let (update, histogram_terms) = add_latency_histogram(update, histogram);
+    let mut add_terms = vec![
+        "#total_latency :total_latency".to_owned(),
+        "#latency_samples :clicks".to_owned(),
+        "#unreported_clicks :minus_clicks".to_owned(),
+    ];
+    add_terms.extend(histogram_terms);
+
+    // Verify the player exists, the secret is unchanged, the player is enrolled in the game
+    // and the team, is not banned, and the report does not cover more clicks than they made
     Ok(update
+        .update_expression(format!("ADD {}", add_terms.join(", ")))
+        .condition_expression(format!(
+            "attribute_exists({PK}) AND #stored_secret = :stored_secret AND game_id = :game_id \
+            AND team = :team AND attribute_not_exists(#banned) \
+            AND #unreported_clicks >= :clicks"
+        ))
         .return_values(ReturnValue::AllNew)
         .send()
+        .timed()
         .await?
         .attributes
-        .map(Player::from_item)
+        .map(PlayerRecord::from_item)
         .expect("asked for them"))
 }
@@ -0,0 +1,180 @@
+use std::sync::{LazyLock, Mutex};
+
+use lambda_appsync::{AWSTimestamp, ID, log};
+use lru_cache::LruCache;
+
+use crate::{GameState, GameStatus};
+
+/// Largest number of games whose state is kept in memory
+const MAX_CACHED_GAME_STATES: usize = 100;
+
+/// How long a game state read from DynamoDB is used by this instance, in milliseconds, read
+/// from the environment
+///
+/// `GAME_STATE_CACHE_TTL_MS` defaults to 1000; 0 disables the cache.
+static GAME_STATE_CACHE_TTL_MS: LazyLock<u64> = LazyLock::new(|| {
+    let ttl = std::env::var("GAME_STATE_CACHE_TTL_MS")
+        .ok()
+        .map(|value| {
+            value
+                .parse::<u64>()
+                .expect("`GAME_STATE_CACHE_TTL_MS` must be a number of milliseconds")
+        })
+        .unwrap_or(1000);
+    log::debug!("GAME_STATE_CACHE_TTL_MS={ttl}");
+    ttl
+});
+
+/// A game state read from DynamoDB, along with its version and the time the read was sent, in
+/// milliseconds since the epoch
+struct CachedGameState {
+    state: GameState,
+    version: u64,
+    read_at: u64,
+}
+
+/// Game states read by this lambda instance, by game
+///
+/// Clicks do not need the game state, they are checked against its copy on the player item, but
+/// latency reports do. A cached state is used until [GAME_STATE_CACHE_TTL_MS] has elapsed since
+/// its read was sent, or until a write of this instance returns a player item stamped with
+/// another version of the state, see [observe_game_state_version].
+///
+/// Reads are strongly consistent, so a change of the state is seen by this instance at most
+/// [GAME_STATE_CACHE_TTL_MS] after it is written: a cached state read before the change expires
+/// by then. Until then, a report may be accepted after the game stopped, or rejected after it
+/// started, exactly as if it had reached the lambda that much earlier or later.
+static GAME_STATES: LazyLock<Mutex<LruCache<ID, CachedGameState>>> =
+    LazyLock::new(|| Mutex::new(LruCache::new(MAX_CACHED_GAME_STATES)));
+
+/// Returns the state of a game, if read from DynamoDB less than [GAME_STATE_CACHE_TTL_MS]
+/// before `now` (in milliseconds since the epoch)
+pub fn cached_game_state(game_id: ID, now: u64) -> Option<GameState> {
+    cached_game_state_with_ttl(game_id, now, *GAME_STATE_CACHE_TTL_MS)
+}
+
+fn cached_game_state_with_ttl(game_id: ID, now: u64, ttl: u64) -> Option<GameState> {
+    GAME_STATES
+        .lock()
+        .unwrap()
+        .get(&game_id)
+        .filter(|cached| now < cached.read_at.saturating_add(ttl))
+        .map(|cached| cached.state.clone())
+}
+
+/// Remembers the state of a game and its version, read from DynamoDB with a request sent at
+/// `read_at` (in milliseconds since the epoch)
+pub fn remember_game_state(game_id: ID, state: GameState, version: u64, read_at: u64) {
+    let mut game_states = GAME_STATES.lock().unwrap();
+    game_states.insert(
+        game_id,
+        CachedGameState {
+            state,
+            version,
+            read_at,
+        },
+    );
+}
+
+/// Forgets the cached state of a game if a player item returned by a write carries another
+/// `version` of it
+///
+/// Player items only carry the version of the state they were last updated for, which lags
+/// behind the game state item while the change is copied to every player, so any difference
+/// invalidates the cached state. At worst, the state is read again a few more times.
+pub fn observe_game_state_version(game_id: ID, version: Option<u64>) {
+    let Some(version) = version else {
+        return;
+    };
+    let mut game_states = GAME_STATES.lock().unwrap();
+    if game_states
+        .get(&game_id)
+        .is_some_and(|cached| cached.version != version)
+    {
+        game_states.remove(&game_id);
+    }
+}
+
+impl GameState {
+    /// Returns whether clicks (and latency reports) are accepted at the given time.
+    ///
+    /// A game accepts clicks when it is [GameStatus::Started], or when it is in
+    /// [GameStatus::Countdown] and its `starts_at` time has been reached.
+    pub fn accepts_clicks(&self, now: AWSTimestamp) -> bool {
+        match self.status {
+            GameStatus::Started => true,
+            GameStatus::Countdown => self.starts_at.is_some_and(|starts_at| starts_at <= now),
+            GameStatus::Paused | GameStatus::Stopped | GameStatus::Reset => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lambda_appsync::ID;
+
+    use super::*;
+
+    fn game_state(status: GameStatus, starts_at: Option<u64>) -> GameState {
+        GameState {
+            game_id: ID::new(),
+            status,
+            starts_at: starts_at.map(AWSTimestamp::from),
+            started_at: starts_at.map(AWSTimestamp::from),
+            paused_at: None,
+            paused_seconds: None,
+        }
+    }
+
+    #[test]
+    fn test_accepts_clicks() {
+        let now = AWSTimestamp::from(1000);
+        assert!(game_state(GameStatus::Started, None).accepts_clicks(now));
+        assert!(!game_state(GameStatus::Paused, None).accepts_clicks(now));
+        assert!(!game_state(GameStatus::Stopped, None).accepts_clicks(now));
+        assert!(!game_state(GameStatus::Reset, None).accepts_clicks(now));
+    }
+
+    #[test]
+    fn test_game_state_cache_staleness_window() {
+        let game_id = ID::new();
+        let state = game_state(GameStatus::Started, None);
+        assert!(cached_game_state_with_ttl(game_id, 1000, 500).is_none());
+
+        // A state read at 1000 is used until, but not at, 1000 + TTL
+        remember_game_state(game_id, state, 1, 1000);
+        assert!(cached_game_state_with_ttl(game_id, 1000, 500).is_some());
+        assert!(cached_game_state_with_ttl(game_id, 1499, 500).is_some());
+        assert!(cached_game_state_with_ttl(game_id, 1500, 500).is_none());
+        // A TTL of 0 disables the cache
+        assert!(cached_game_state_with_ttl(game_id, 1000, 0).is_none());
+        assert!(cached_game_state_with_ttl(ID::new(), 1000, 500).is_none());
+    }
+
+    #[test]
+    fn test_game_state_cache_version_invalidation() {
+        let game_id = ID::new();
+        remember_game_state(game_id, game_state(GameStatus::Started, None), 2, 1000);
+
+        // Players that were never stamped, or stamped with the cached version, keep the state
+        observe_game_state_version(game_id, None);
+        observe_game_state_version(game_id, Some(2));
+        assert!(cached_game_state_with_ttl(game_id, 1000, 500).is_some());
+
+        // Any other version, newer or older, invalidates it before the TTL elapses
+        observe_game_state_version(game_id, Some(3));
+        assert!(cached_game_state_with_ttl(game_id, 1000, 500).is_none());
+        remember_game_state(game_id, game_state(GameStatus::Stopped, None), 3, 1000);
+        observe_game_state_version(game_id, Some(2));
+        assert!(cached_game_state_with_ttl(game_id, 1000, 500).is_none());
+    }
+
+    #[test]
+    fn test_countdown_accepts_clicks_once_started() {
+        let now = AWSTimestamp::from(1000);
+        assert!(!game_state(GameStatus::Countdown, Some(1001)).accepts_clicks(now));
+        assert!(game_state(GameStatus::Countdown, Some(1000)).accepts_clicks(now));
+        assert!(game_state(GameStatus::Countdown, Some(999)).accepts_clicks(now));
+        assert!(!game_state(GameStatus::Countdown, None).accepts_clicks(now));
+    }
+}
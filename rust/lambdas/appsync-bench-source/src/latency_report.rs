@@ -0,0 +1,217 @@
+use lambda_appsync::ID;
+use latency_histogram::LatencyHistogram;
+use player_secret::StoredSecret;
+
+use crate::{LatencyReport, dynamodb_helpers::PlayerRecord};
+
+impl LatencyReport {
+    /// Highest average latency, in milliseconds, that a report can contain
+    ///
+    /// Clicks slower than this are not a latency measurement anymore, but a lost connection.
+    pub const MAX_AVG_LATENCY_MS: f64 = 30_000.0;
+
+    /// Checks the report is plausible, before it is merged into the statistics of a player
+    ///
+    /// Whether the player made the reported clicks is checked when the report is merged.
+    ///
+    /// Returns the histogram of the report, empty if it has none, or the reason the report is
+    /// invalid
+    pub fn validate(&self) -> Result<LatencyHistogram, String> {
+        let LatencyReport {
+            clicks,
+            avg_latency,
+            ref histogram,
+        } = *self;
+        if clicks < 1 {
+            return Err(format!("Reported clicks must be at least 1, got {clicks}"));
+        }
+        if !(avg_latency.is_finite()
+            && avg_latency > 0.0
+            && avg_latency <= Self::MAX_AVG_LATENCY_MS)
+        {
+            return Err(format!(
+                "Reported average latency must be greater than 0 and at most {} ms, got {avg_latency}",
+                Self::MAX_AVG_LATENCY_MS
+            ));
+        }
+        let Some(counts) = histogram else {
+            return Ok(LatencyHistogram::default());
+        };
+        let histogram = LatencyHistogram::from_counts(counts)?;
+        if histogram.total() != clicks as u64 {
+            return Err(format!(
+                "Latency histogram must count the {clicks} reported clicks, got {}",
+                histogram.total()
+            ));
+        }
+        Ok(histogram)
+    }
+}
+
+/// Why a latency report could not be added to the statistics of a player, diagnosed from the
+/// player item as it was when the update failed
+#[derive(Debug)]
+pub enum ReportConflict {
+    /// The player does not exist, or not in the game of the report
+    PlayerNotFound,
+    /// The player changed team since their secret was verified
+    NotInTeam,
+    /// The player was banned since their secret was verified
+    Banned,
+    /// The secret of the player was migrated or rotated since it was verified
+    ///
+    /// The only conflict worth retrying: reports add to counters, so they never conflict with
+    /// each other, and the update only needs the secret to be verified again.
+    SecretChanged(Box<PlayerRecord>),
+    /// More clicks were reported than the player made and did not report yet
+    ClicksNotMade {
+        /// Clicks of the player not reported yet
+        unreported: i64,
+    },
+}
+
+impl ReportConflict {
+    /// Diagnoses the failed update of the player `record` of a report for the game `game_id`
+    /// and the team `team_id`, made with the secret verified against `stored_secret`
+    pub fn diagnose(
+        record: Option<PlayerRecord>,
+        game_id: ID,
+        team_id: &str,
+        stored_secret: &StoredSecret,
+    ) -> Self {
+        let Some(record) = record.filter(|record| record.player.game_id == game_id) else {
+            return ReportConflict::PlayerNotFound;
+        };
+        if record.player.team != team_id {
+            ReportConflict::NotInTeam
+        } else if record.banned {
+            ReportConflict::Banned
+        } else if record.stored_secret.as_ref() != Some(stored_secret) {
+            ReportConflict::SecretChanged(Box::new(record))
+        } else {
+            ReportConflict::ClicksNotMade {
+                unreported: record.unreported_clicks.unwrap_or_default(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Player;
+
+    /// The record of `player` as it was when the update of a report failed
+    fn record(player: Player, stored_secret: &StoredSecret) -> PlayerRecord {
+        PlayerRecord {
+            player,
+            stored_secret: Some(stored_secret.clone()),
+            bucket: None,
+            banned: false,
+            unreported_clicks: Some(3),
+            clicks_open_at: None,
+            state_version: None,
+        }
+    }
+
+    fn report(clicks: i32, avg_latency: f64) -> LatencyReport {
+        LatencyReport {
+            clicks,
+            avg_latency,
+            histogram: None,
+        }
+    }
+
+    #[test]
+    fn test_validate_bounds() {
+        assert!(report(10, 42.0).validate().is_ok());
+        assert!(report(0, 42.0).validate().is_err());
+        assert!(report(-1, 42.0).validate().is_err());
+        assert!(report(1, 0.0).validate().is_err());
+        assert!(report(1, -5.0).validate().is_err());
+        assert!(report(1, f64::NAN).validate().is_err());
+        assert!(report(1, f64::INFINITY).validate().is_err());
+        assert!(
+            report(1, LatencyReport::MAX_AVG_LATENCY_MS)
+                .validate()
+                .is_ok()
+        );
+        assert!(
+            report(1, LatencyReport::MAX_AVG_LATENCY_MS + 1.0)
+                .validate()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_validate_histogram() {
+        let with_histogram = |histogram: Vec<i32>| LatencyReport {
+            histogram: Some(histogram),
+            ..report(5, 42.0)
+        };
+        assert!(report(5, 42.0).validate().unwrap().is_empty());
+        let histogram = with_histogram(vec![0, 0, 2, 3]).validate().unwrap();
+        assert_eq!(histogram.buckets(), [(2, 2), (3, 3)]);
+        // The counts must add up to the reported clicks
+        assert!(with_histogram(vec![0, 0, 2, 2]).validate().is_err());
+        assert!(with_histogram(vec![]).validate().is_err());
+        assert!(with_histogram(vec![6, -1]).validate().is_err());
+    }
+
+    #[test]
+    fn test_diagnose_report_conflict() {
+        let secret = StoredSecret::Hashed("hash".to_owned());
+        let rust_player = Player {
+            id: ID::new(),
+            game_id: ID::new(),
+            name: "Player".to_owned(),
+            team: "RUST".to_owned(),
+            clicks: Some(3),
+            avg_latency: None,
+            avg_latency_clicks: None,
+            rate_limited_clicks: None,
+            latency_percentiles: None,
+            server_timing: None,
+        };
+        let game_id = rust_player.game_id;
+        let diagnose = |record: Option<PlayerRecord>| {
+            ReportConflict::diagnose(record, game_id, "RUST", &secret)
+        };
+
+        assert!(matches!(diagnose(None), ReportConflict::PlayerNotFound));
+        let other_game = Player {
+            game_id: ID::new(),
+            ..rust_player.clone()
+        };
+        assert!(matches!(
+            diagnose(Some(record(other_game, &secret))),
+            ReportConflict::PlayerNotFound
+        ));
+        let js_player = Player {
+            team: "JS".to_owned(),
+            ..rust_player.clone()
+        };
+        assert!(matches!(
+            diagnose(Some(record(js_player, &secret))),
+            ReportConflict::NotInTeam
+        ));
+        let banned = PlayerRecord {
+            banned: true,
+            ..record(rust_player.clone(), &secret)
+        };
+        assert!(matches!(diagnose(Some(banned)), ReportConflict::Banned));
+        assert!(matches!(
+            diagnose(Some(record(rust_player.clone(), &secret))),
+            ReportConflict::ClicksNotMade { unreported: 3 }
+        ));
+
+        // Only a secret that changed since it was verified is worth retrying
+        let migrated = StoredSecret::Hashed("new hash".to_owned());
+        match diagnose(Some(record(rust_player, &migrated))) {
+            ReportConflict::SecretChanged(record) => {
+                assert_eq!(record.stored_secret, Some(migrated))
+            }
+            conflict => panic!("unexpected conflict {conflict:?}"),
+        }
+    }
+}
@@ -1,12 +1,26 @@
+use std::time::Instant;
+
 use crate::{
-    GameStatus, LatencyReport, Player,
+    Backend, ClickBatchInput, GameState, LatencyReport, Player, Team,
+    click_batch::ClickBatch,
     dynamodb_helpers::{
-        dynamodb_get_game_status, dynamodb_get_player, dynamodb_update_player_click,
-        dynamodb_update_player_latency_stats,
+        PlayerRecord, dynamodb_add_player_latency, dynamodb_count_rate_limited_click,
+        dynamodb_get_game_state, dynamodb_get_player, dynamodb_get_team,
+        dynamodb_migrate_player_secret, dynamodb_update_player_click,
+    },
+    game::{cached_game_state, observe_game_state_version, remember_game_state},
+    latency_report::ReportConflict,
+    rate_limit::{
+        RATE_LIMIT, TokenBucket, cached_bucket, now_millis, reject_clicks, remember_bucket,
     },
+    secret::{cached_secret, forget_secret, remember_secret},
+    server_timing::{Timed, with_server_timing},
+    team::{cached_team, remember_team},
 };
 
-use lambda_appsync::{AppsyncError, ID, appsync_operation};
+use lambda_appsync::{AWSTimestamp, AppsyncError, ID, appsync_operation};
+use latency_histogram::LatencyHistogram;
+use player_secret::{StoredSecret, hash_secret, run_blocking};
 
 fn player_not_found() -> AppsyncError {
     AppsyncError::new("PlayerNotFound", "Player does not exist")
@@ -14,49 +28,497 @@ fn player_not_found() -> AppsyncError {
 fn invalid_game_status() -> AppsyncError {
     AppsyncError::new("InvalidGameStatus", "Game is not started")
 }
+fn invalid_secret() -> AppsyncError {
+    AppsyncError::new("InvalidSecret", "Player secret is invalid")
+}
+fn team_not_found() -> AppsyncError {
+    AppsyncError::new("TeamNotFound", "Team does not exist")
+}
+fn not_in_team(team_id: &str) -> AppsyncError {
+    AppsyncError::new("InvalidTeam", format!("Player is not in team {team_id}"))
+}
+fn not_served_here(team: &Team) -> AppsyncError {
+    AppsyncError::new(
+        "InvalidTeam",
+        format!("Team {} is served by the {} backend", team.id, team.backend),
+    )
+}
+fn invalid_latency_report(reason: String) -> AppsyncError {
+    AppsyncError::new("InvalidLatencyReport", reason)
+}
+fn invalid_click_batch(reason: String) -> AppsyncError {
+    AppsyncError::new("InvalidClickBatch", reason)
+}
+fn player_banned() -> AppsyncError {
+    AppsyncError::new("PlayerBanned", "Player is banned from the game")
+}
+fn rate_limited() -> AppsyncError {
+    AppsyncError::new("RateLimited", "Player is clicking too fast")
+}
+fn concurrent_update() -> AppsyncError {
+    AppsyncError::new(
+        "ConcurrentUpdate",
+        "Player keeps being updated concurrently, try again later",
+    )
+}
+
+/// Number of times a click is attempted when racing other clicks of the same player
+const MAX_CLICK_ATTEMPTS: usize = 3;
+
+/// Number of times a latency report is attempted: once, and once more if the secret of the
+/// player changed since it was verified
+const MAX_REPORT_ATTEMPTS: usize = 2;
+
+/// Returns the team `team_id`, as read from DynamoDB by this instance not too long ago
+async fn get_team(team_id: &str) -> Result<Option<Team>, AppsyncError> {
+    let now = Instant::now();
+    if let Some(team) = cached_team(team_id, now) {
+        return Ok(team);
+    }
+    let team = dynamodb_get_team(team_id.to_owned()).await?;
+    remember_team(team_id, team.clone(), now);
+    Ok(team)
+}
+
+/// Returns the team `team_id`, provided its clicks are served by this backend
+async fn served_team(team_id: &str) -> Result<Team, AppsyncError> {
+    let team = get_team(team_id).await?.ok_or_else(team_not_found)?;
+    if team.backend != Backend::Rust {
+        return Err(not_served_here(&team));
+    }
+    Ok(team)
+}
+
+/// Verifies `secret` against the `stored_secret` of `player`
+///
+/// Secrets already verified by this instance are accepted without hashing them again,
+/// and a plaintext secret is migrated to its hash on its first successful use.
+///
+/// Returns the secret now stored for the player, to condition the following update on
+async fn verify_player_secret(
+    player: &Player,
+    stored_secret: Option<StoredSecret>,
+    secret: String,
+) -> Result<StoredSecret, AppsyncError> {
+    // No secret can match a player who has none
+    let Some(stored_secret) = stored_secret else {
+        forget_secret(player.id);
+        return Err(invalid_secret());
+    };
+    if cached_secret(player.id, &secret)
+        .is_some_and(|(cached, team_id)| cached == stored_secret && team_id == player.team)
+    {
+        return Ok(stored_secret);
+    }
+
+    let migrate = stored_secret.is_plaintext()
+        && get_team(&player.team)
+            .await?
+            .is_some_and(|team| team.backend.hashes_secret());
+    // Its time is reported apart, as it is only spent on the first request of a player to this
+    // instance
+    let (valid, stored_secret, secret, secret_hash) = run_blocking(move || {
+        let valid = stored_secret.verify(&secret);
+        let secret_hash = (valid && migrate).then(|| hash_secret(&secret));
+        (valid, stored_secret, secret, secret_hash)
+    })
+    .timed_secret_verification()
+    .await;
+    if !valid {
+        forget_secret(player.id);
+        return Err(invalid_secret());
+    }
+
+    let stored_secret = match secret_hash {
+        Some(secret_hash) => {
+            dynamodb_migrate_player_secret(player.id, &stored_secret, secret_hash.clone()).await?;
+            StoredSecret::Hashed(secret_hash)
+        }
+        None => stored_secret,
+    };
+    remember_secret(player.id, &secret, stored_secret.clone(), &player.team);
+    Ok(stored_secret)
+}
+
+/// Verifies the secret of a player enrolled in `game_id`, unless this instance already did
+///
+/// When the player is read, whether their game accepts clicks at `now` is checked first, so that
+/// players of a game not started are told so whatever their secret.
+///
+/// Returns the secret stored for the player and their team, along with their token bucket as
+/// last known by this instance
+async fn authenticate_player(
+    game_id: ID,
+    player_id: ID,
+    secret: &str,
+    now: AWSTimestamp,
+) -> Result<(StoredSecret, String, Option<TokenBucket>), AppsyncError> {
+    if let Some((stored_secret, player_team)) = cached_secret(player_id, secret) {
+        return Ok((stored_secret, player_team, cached_bucket(player_id)));
+    }
+    // A player enrolled in another game is treated as unknown in this one
+    let record = dynamodb_get_player(player_id)
+        .await?
+        .filter(|record| record.player.game_id == game_id)
+        .ok_or_else(player_not_found)?;
+    if !record.accepts_clicks(now) {
+        return Err(invalid_game_status());
+    }
+    let stored_secret =
+        verify_player_secret(&record.player, record.stored_secret, secret.to_owned()).await?;
+    if record.banned {
+        return Err(player_banned());
+    }
+    Ok((stored_secret, record.player.team, record.bucket))
+}
+
+/// Counts a `batch` of clicks of a player against their rate limit, then adds it to their click
+/// counter, provided their game accepts clicks at the time of the batch
+///
+/// The click update is conditioned on the token bucket of the player being unchanged: when
+/// another lambda instance took a token meanwhile, the click is retried with the bucket returned
+/// by DynamoDB, at most [MAX_CLICK_ATTEMPTS] times, after which the click fails as a concurrent
+/// update rather than as rate limited.
+async fn rate_limited_click(
+    game_id: ID,
+    player_id: ID,
+    team_id: String,
+    secret: String,
+    mut stored_secret: StoredSecret,
+    mut bucket: Option<TokenBucket>,
+    batch: ClickBatch,
+) -> Result<Player, AppsyncError> {
+    for _ in 0..MAX_CLICK_ATTEMPTS {
+        let Some(new_bucket) = TokenBucket::take(bucket, now_millis(), &RATE_LIMIT, batch.count)
+        else {
+            // Count the rejected clicks, so the admin can spot scripted players, but not on each
+            // rejection: a player clicking too fast would cost a write per click
+            remember_bucket(player_id, bucket);
+            let Some(count) = reject_clicks(player_id, batch.count) else {
+                return Err(rate_limited());
+            };
+            let rejected = ClickBatch { count, ..batch };
+            match dynamodb_count_rate_limited_click(game_id, player_id, &stored_secret, rejected)
+                .await
+            {
+                Ok(()) => (),
+                Err(aws_sdk_dynamodb::Error::ConditionalCheckFailedException(e)) => {
+                    if e.item
+                        .map(PlayerRecord::from_item)
+                        .is_some_and(|record| !record.accepts_clicks(batch.at))
+                    {
+                        return Err(invalid_game_status());
+                    }
+                    // Otherwise the click is rejected anyway, it just cannot be counted
+                }
+                Err(e) => return Err(e.into()),
+            }
+            return Err(rate_limited());
+        };
+        match dynamodb_update_player_click(
+            game_id,
+            player_id,
+            &team_id,
+            &stored_secret,
+            bucket,
+            new_bucket,
+            batch,
+        )
+        .await
+        {
+            Ok(record) => {
+                remember_bucket(player_id, Some(new_bucket));
+                observe_game_state_version(game_id, record.state_version);
+                return Ok(record.player);
+            }
+            Err(aws_sdk_dynamodb::Error::ConditionalCheckFailedException(e)) => {
+                // Find out which condition failed from the player item as it was
+                let Some(record) = e
+                    .item
+                    .map(PlayerRecord::from_item)
+                    .filter(|record| record.player.game_id == game_id)
+                else {
+                    forget_secret(player_id);
+                    remember_bucket(player_id, None);
+                    return Err(player_not_found());
+                };
+                if !record.accepts_clicks(batch.at) {
+                    // The game is not started, or not anymore
+                    return Err(invalid_game_status());
+                }
+                if record.player.team != team_id {
+                    // The player changed team since their secret was verified
+                    forget_secret(player_id);
+                    return Err(not_in_team(&team_id));
+                }
+                if record.stored_secret.as_ref() != Some(&stored_secret) {
+                    // The secret was migrated or rotated since it was verified
+                    forget_secret(player_id);
+                    stored_secret =
+                        verify_player_secret(&record.player, record.stored_secret, secret.clone())
+                            .await?;
+                }
+                if record.banned {
+                    // The player was banned since their secret was verified
+                    forget_secret(player_id);
+                    return Err(player_banned());
+                }
+                bucket = record.bucket;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    // Clicks of this player keep racing each other across instances
+    remember_bucket(player_id, None);
+    Err(concurrent_update())
+}
+
+/// Records a `batch` of clicks of a player, who must be in the team `team_id` if provided
+///
+/// The returned player holds the time spent on the click, see [with_server_timing].
+async fn click_for_team(
+    game_id: ID,
+    team_id: Option<String>,
+    player_id: ID,
+    secret: String,
+    batch: ClickBatch,
+) -> Result<Player, AppsyncError> {
+    with_server_timing(async move {
+        // Whether the game is "Started" (or at the end of its countdown) is checked along with the
+        // click itself, against the copy of the game state on the player item
+        // Verify the secret, unless this instance already did
+        let (stored_secret, player_team, bucket) =
+            authenticate_player(game_id, player_id, &secret, batch.at).await?;
+        // Players click for their own team, provided this backend serves it
+        if let Some(team_id) = team_id.filter(|team_id| *team_id != player_team) {
+            return Err(not_in_team(&team_id));
+        }
+        served_team(&player_team).await?;
+        // Then we increment the click_counter of the player, if they did not click too fast
+        rate_limited_click(
+            game_id,
+            player_id,
+            player_team,
+            secret,
+            stored_secret,
+            bucket,
+            batch,
+        )
+        .await
+    })
+    .await
+}
 
 // impl crate::Operation {
-//     pub async fn mutation_click_rust(
+//     pub async fn mutation_click(
+//         game_id: ID,
+//         team_id: String,
 //         player_id: ID,
 //         secret: String,
 //     ) -> Result<Player, AppsyncError> {
 //         // This is just a marker to ensure an error is thrown if the user did not chose
 //         // the correct signature for the function. Should be optimized away by the compiler.
 //         if false {
-//             return <crate::Operation as crate::DefautOperations>::mutation_click_rust(
-//                 player_id, secret,
+//             return <crate::Operation as crate::DefautOperations>::mutation_click(
+//                 game_id, team_id, player_id, secret,
 //             )
 //             .await;
 //         }
-//         let game_status = dynamodb_get_game_status()
-//             .await
-//             .map_err(from_dynamo_error)?
-//             .ok_or_else(invalid_game_status)?;
-//         if game_status != GameStatus::Started {
-//             return Err(invalid_game_status());
-//         }
-//         Ok(dynamodb_update_player_click(player_id, secret)
-//             .await
-//             .map_err(from_dynamo_error)?)
+//         click_for_team(
+//             game_id,
+//             Some(team_id),
+//             player_id,
+//             secret,
+//             ClickBatch::single(AWSTimestamp::now()),
+//         )
+//         .await
 //     }
 // }
 // This macro replace the whole function by the code commented above
+#[appsync_operation(mutation(click))]
+pub async fn click(
+    game_id: ID,
+    team_id: String,
+    player_id: ID,
+    secret: String,
+) -> Result<Player, AppsyncError> {
+    click_for_team(
+        game_id,
+        Some(team_id),
+        player_id,
+        secret,
+        ClickBatch::single(AWSTimestamp::now()),
+    )
+    .await
+}
+
+// The clients that do not provide the team click for the team of the player
 #[appsync_operation(mutation(clickRust))]
-pub async fn click(player_id: ID, secret: String) -> Result<Player, AppsyncError> {
-    // Retrieve the current game status
-    let game_status = dynamodb_get_game_status()
+pub async fn click_rust(
+    game_id: ID,
+    player_id: ID,
+    secret: String,
+) -> Result<Player, AppsyncError> {
+    click_for_team(
+        game_id,
+        None,
+        player_id,
+        secret,
+        ClickBatch::single(AWSTimestamp::now()),
+    )
+    .await
+}
+
+// High-frequency clients can send the clicks made over `client_window_ms` in a single request,
+// which is recorded atomically
+#[appsync_operation(mutation(clickBatchRust))]
+pub async fn click_batch_rust(
+    game_id: ID,
+    player_id: ID,
+    secret: String,
+    batch: ClickBatchInput,
+) -> Result<Player, AppsyncError> {
+    let ClickBatchInput {
+        count,
+        client_window_ms,
+    } = batch;
+    let batch = ClickBatch::validate(count, client_window_ms, AWSTimestamp::now(), &RATE_LIMIT)
+        .map_err(invalid_click_batch)?;
+    click_for_team(game_id, None, player_id, secret, batch).await
+}
+
+/// Adds a validated latency `report`, along with its `histogram`, to the statistics of a player
+/// of `team_id` whose `secret` was verified against `stored_secret`
+///
+/// A failed update is diagnosed from the player item returned by DynamoDB, see [ReportConflict].
+/// Reports add to counters, so they do not conflict with each other: the update is only retried
+/// when the secret of the player was migrated or rotated since it was verified, right after
+/// verifying it again, so that only wrong secrets are reported as such.
+async fn add_player_latency(
+    game_id: ID,
+    player_id: ID,
+    team_id: String,
+    secret: String,
+    mut stored_secret: StoredSecret,
+    report: &LatencyReport,
+    histogram: &LatencyHistogram,
+) -> Result<Player, AppsyncError> {
+    for _ in 0..MAX_REPORT_ATTEMPTS {
+        let e = match dynamodb_add_player_latency(
+            game_id,
+            player_id,
+            &team_id,
+            &stored_secret,
+            report.clicks,
+            report.avg_latency,
+            histogram,
+        )
+        .await
+        {
+            Ok(record) => {
+                observe_game_state_version(game_id, record.state_version);
+                return Ok(record.player);
+            }
+            Err(aws_sdk_dynamodb::Error::ConditionalCheckFailedException(e)) => e,
+            Err(e) => return Err(e.into()),
+        };
+        // Find out which condition failed from the player item as it was
+        let record = e.item.map(PlayerRecord::from_item);
+        match ReportConflict::diagnose(record, game_id, &team_id, &stored_secret) {
+            ReportConflict::PlayerNotFound => {
+                forget_secret(player_id);
+                return Err(player_not_found());
+            }
+            ReportConflict::NotInTeam => {
+                forget_secret(player_id);
+                return Err(not_in_team(&team_id));
+            }
+            ReportConflict::Banned => {
+                forget_secret(player_id);
+                return Err(player_banned());
+            }
+            ReportConflict::SecretChanged(record) => {
+                // Verifying the secret against the new one only fails for a wrong secret
+                forget_secret(player_id);
+                stored_secret =
+                    verify_player_secret(&record.player, record.stored_secret, secret.clone())
+                        .await?;
+            }
+            ReportConflict::ClicksNotMade { unreported } => {
+                // Every reported click must be a click the player actually made, and can only be
+                // reported once
+                return Err(invalid_latency_report(format!(
+                    "Reported clicks exceed the clicks of the player not reported yet: \
+                    {} reported, {unreported} not reported yet",
+                    report.clicks,
+                )));
+            }
+        }
+    }
+    // The secret changed again right after it was verified
+    Err(concurrent_update())
+}
+
+/// Returns the state of a game, read from DynamoDB unless this instance did recently
+///
+/// See [crate::game::cached_game_state] for how stale the state can be.
+async fn game_state(game_id: ID) -> Result<GameState, AppsyncError> {
+    let read_at = now_millis();
+    if let Some(state) = cached_game_state(game_id, read_at) {
+        return Ok(state);
+    }
+    let (state, version) = dynamodb_get_game_state(game_id)
         .await?
         .ok_or_else(invalid_game_status)?;
-    // If the game is not "Started", then we return an error
-    if game_status != GameStatus::Started {
-        return Err(invalid_game_status());
-    }
-    // Else we increment the click_counter of the player
-    Ok(dynamodb_update_player_click(player_id, secret).await?)
+    remember_game_state(game_id, state.clone(), version, read_at);
+    Ok(state)
+}
+
+/// Merges a latency report into the statistics of a player, for their own team
+///
+/// The report is added to the latency counters of the player with a single update, which does
+/// not conflict with the other reports of the player.
+/// The returned player holds the time spent on the report, see [with_server_timing].
+async fn report_player_latency(
+    game_id: ID,
+    player_id: ID,
+    report: LatencyReport,
+    secret: String,
+) -> Result<Player, AppsyncError> {
+    with_server_timing(async move {
+        // Reject reports that are out of bounds before anything else
+        let histogram = report.validate().map_err(invalid_latency_report)?;
+
+        // Verify the game is currently in progress
+        let now = AWSTimestamp::now();
+        if !game_state(game_id).await?.accepts_clicks(now) {
+            return Err(invalid_game_status());
+        }
+
+        // Verify the secret, unless this instance already did
+        let (stored_secret, player_team, _) =
+            authenticate_player(game_id, player_id, &secret, now).await?;
+
+        // Players report for their own team, provided this backend serves it
+        served_team(&player_team).await?;
+
+        add_player_latency(
+            game_id,
+            player_id,
+            player_team,
+            secret,
+            stored_secret,
+            &report,
+            &histogram,
+        )
+        .await
+    })
+    .await
 }
 
 // impl crate::Operation {
-//     pub async fn mutation_report_latency_rust(
+//     pub async fn mutation_report_latency(
+//         game_id: ID,
 //         player_id: ID,
 //         report: LatencyReport,
 //         secret: String,
@@ -64,137 +526,32 @@ pub async fn click(player_id: ID, secret: String) -> Result<Player, AppsyncError
 //         // This is just a marker to ensure an error is thrown if the user did not chose
 //         // the correct signature for the function. Should be optimized away by the compiler.
 //         if false {
-//             return <crate::Operation as crate::DefautOperations>::mutation_report_latency_rust(
-//                 player_id, report, secret,
+//             return <crate::Operation as crate::DefautOperations>::mutation_report_latency(
+//                 game_id, player_id, report, secret,
 //             )
 //             .await;
 //         }
-//         let player_req = lambda_appsync::tokio::spawn(dynamodb_get_player(player_id));
-//         let game_status = dynamodb_get_game_status()
-//             .await
-//             .map_err(from_dynamo_error)?
-//             .ok_or_else(invalid_game_status)?;
-//         if game_status != GameStatus::Started {
-//             return Err(invalid_game_status());
-//         }
-//         // Retrieve the current player
-//         let player = player_req
-//             .await
-//             .unwrap()
-//             .map_err(from_dynamo_error)?
-//             .ok_or_else(player_not_found)?;
-//         let LatencyReport {
-//             clicks,
-//             avg_latency,
-//         } = report;
-//         let old_avg_latency = player.avg_latency;
-//         let old_avg_latency_clicks = player.avg_latency_clicks;
-//         let old_total_latency = match (old_avg_latency, old_avg_latency_clicks) {
-//             (Some(old_avg_latency), Some(old_avg_latency_clicks)) => {
-//                 old_avg_latency * (old_avg_latency_clicks as f64)
-//             }
-//             (None, None) => 0f64,
-//             _ => unreachable!(
-//                 "Functionnal error, old_avg_latency and old_avg_latency_clicks \
-//         can only be both None or both Some"
-//             ),
-//         };
-//         let new_total_latency = old_total_latency + avg_latency * (clicks as f64);
-//         let new_avg_latency_clicks = old_avg_latency_clicks.unwrap_or_default() + clicks;
-
-//         let new_avg_latency = new_total_latency / (new_avg_latency_clicks as f64);
-//         if new_avg_latency.is_finite() {
-//             Ok(dynamodb_update_player_latency_stats(
-//                 player_id,
-//                 secret,
-//                 old_avg_latency,
-//                 old_avg_latency_clicks,
-//                 new_avg_latency,
-//                 new_avg_latency_clicks,
-//             )
-//             .await
-//             .map_err(from_dynamo_error)?)
-//         } else {
-//             Ok(player)
-//         }
+//         report_player_latency(game_id, player_id, report, secret).await
 //     }
 // }
 // This macro replace the whole function by the code commented above
-#[appsync_operation(mutation(reportLatencyRust))]
+#[appsync_operation(mutation(reportLatency))]
 pub async fn report_latency(
+    game_id: ID,
     player_id: ID,
     report: LatencyReport,
     secret: String,
 ) -> Result<Player, AppsyncError> {
-    // Kick off an async request to get the player data first, so it can run in parallel
-    // with the game status check that follows
-    let player_req = lambda_appsync::tokio::spawn(dynamodb_get_player(player_id));
-
-    // Verify the game is currently in progress
-    let game_status = dynamodb_get_game_status()
-        .await?
-        .ok_or_else(invalid_game_status)?;
-    if game_status != GameStatus::Started {
-        return Err(invalid_game_status());
-    }
-
-    // Wait for and retrieve the player data we requested earlier
-    let player = player_req.await.unwrap()?.ok_or_else(player_not_found)?;
-
-    // Extract the values from the latency report:
-    // - clicks: how many clicks were made during this reporting period
-    // - avg_latency: the average latency (in ms) measured for those clicks
-    let LatencyReport {
-        clicks,
-        avg_latency,
-    } = report;
-
-    // Get the player's current statistics:
-    // - old_avg_latency: their current average latency across all clicks
-    // - old_avg_latency_clicks: how many clicks that average is based on
-    let old_avg_latency = player.avg_latency;
-    let old_avg_latency_clicks = player.avg_latency_clicks;
-
-    // Calculate the total cumulative latency from all previous clicks.
-    // If this is the player's first report (both values None), start at 0.
-    // Otherwise multiply their current average by number of clicks to get total.
-    let old_total_latency = match (old_avg_latency, old_avg_latency_clicks) {
-        (Some(old_avg_latency), Some(old_avg_latency_clicks)) => {
-            old_avg_latency * (old_avg_latency_clicks as f64)
-        }
-        (None, None) => 0f64,
-        _ => unreachable!(
-            "Functionnal error, old_avg_latency and old_avg_latency_clicks \
-        can only be both None or both Some"
-        ),
-    };
-
-    // Add the new latency total to the cumulative total:
-    // new latency total = old latency total + this report's average * number of clicks in this report
-    let new_total_latency = old_total_latency + avg_latency * (clicks as f64);
-
-    // Update the total click count by adding new clicks to the old total (or to 0 if first report)
-    let new_avg_latency_clicks = old_avg_latency_clicks.unwrap_or_default() + clicks;
-
-    // Calculate the new overall average:
-    // total latency across all clicks / total number of clicks
-    let new_avg_latency = new_total_latency / (new_avg_latency_clicks as f64);
+    report_player_latency(game_id, player_id, report, secret).await
+}
 
-    // Only update the stats in the database if we got a valid new average latency
-    // (protects against division by zero or other invalid calculations)
-    if new_avg_latency.is_finite() {
-        // Call the update functions, with the old and the new values so it can perform a conditional update
-        Ok(dynamodb_update_player_latency_stats(
-            player_id,
-            secret,
-            old_avg_latency,
-            old_avg_latency_clicks,
-            new_avg_latency,
-            new_avg_latency_clicks,
-        )
-        .await?)
-    } else {
-        // If the calculation gave invalid results, return the player unchanged
-        Ok(player)
-    }
+// Kept for the existing clients
+#[appsync_operation(mutation(reportLatencyRust))]
+pub async fn report_latency_rust(
+    game_id: ID,
+    player_id: ID,
+    report: LatencyReport,
+    secret: String,
+) -> Result<Player, AppsyncError> {
+    report_player_latency(game_id, player_id, report, secret).await
 }
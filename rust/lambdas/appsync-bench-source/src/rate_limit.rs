@@ -0,0 +1,319 @@
+use std::{
+    sync::{LazyLock, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use aws_sdk_dynamodb::types::AttributeValue;
+use dynamodb_utils::DynamoItem;
+use lambda_appsync::{ID, log};
+use lru_cache::LruCache;
+
+/// Largest number of players whose token bucket is kept in memory
+const MAX_CACHED_BUCKETS: usize = 10_000;
+
+/// Minimum time between two writes of the rejected clicks of a player, in milliseconds
+const REJECTED_CLICKS_FLUSH_INTERVAL_MS: u64 = 5_000;
+
+/// Click rate allowed to each player, read from the environment
+///
+/// - `MAX_CLICKS_PER_SECOND`: sustained number of clicks per second (default: 20)
+/// - `CLICK_BURST`: number of clicks a player can do at once after being idle
+///   (default: `MAX_CLICKS_PER_SECOND`)
+pub static RATE_LIMIT: LazyLock<RateLimit> = LazyLock::new(|| {
+    let env_f64 = |name: &str| {
+        std::env::var(name).ok().map(|value| {
+            value
+                .parse::<f64>()
+                .ok()
+                .filter(|value| *value >= 1.0)
+                .unwrap_or_else(|| panic!("`{name}` must be a number greater than or equal to 1"))
+        })
+    };
+    let max_clicks_per_second = env_f64("MAX_CLICKS_PER_SECOND").unwrap_or(20.0);
+    let burst = env_f64("CLICK_BURST").unwrap_or(max_clicks_per_second);
+    let rate_limit = RateLimit {
+        max_clicks_per_second,
+        burst,
+    };
+    log::debug!("RATE_LIMIT={rate_limit:?}");
+    rate_limit
+});
+
+/// Last known token bucket of players, updated from the result of each click
+///
+/// Another lambda instance may have consumed tokens since, in which case the click update
+/// condition fails and the bucket is refreshed from the item returned by DynamoDB.
+static BUCKETS: LazyLock<Mutex<LruCache<ID, TokenBucket>>> =
+    LazyLock::new(|| Mutex::new(LruCache::new(MAX_CACHED_BUCKETS)));
+
+/// Clicks of players rejected by this instance, not counted on their player item yet
+static REJECTED_CLICKS: LazyLock<Mutex<LruCache<ID, RejectedClicks>>> =
+    LazyLock::new(|| Mutex::new(LruCache::new(MAX_CACHED_BUCKETS)));
+
+/// Parameters of the per-player click rate limiting
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    /// Number of tokens added to a bucket each second
+    pub max_clicks_per_second: f64,
+    /// Capacity of a bucket
+    pub burst: f64,
+}
+
+/// Token bucket of a player, stored on the player item
+///
+/// Each click takes one token. Tokens are added back continuously at
+/// [RateLimit::max_clicks_per_second], up to [RateLimit::burst].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TokenBucket {
+    /// Tokens left in the bucket at `updated_at`
+    pub tokens: f64,
+    /// Time of the last update of the bucket, in milliseconds since the epoch
+    pub updated_at: u64,
+}
+
+impl TokenBucket {
+    /// The attribute storing the tokens left
+    pub const TOKENS_PROPERTY_NAME: &'static str = "rate_tokens";
+    /// The attribute storing the time of the last update of the bucket
+    pub const UPDATED_AT_PROPERTY_NAME: &'static str = "rate_updated_at";
+
+    /// Takes `count` tokens, one per click, from `bucket` at time `now` (in milliseconds since
+    /// the epoch)
+    ///
+    /// A player without a bucket yet starts with a full one.
+    /// Returns the updated bucket, or [None] if the bucket does not hold enough tokens, in which
+    /// case none is taken.
+    pub fn take(
+        bucket: Option<Self>,
+        now: u64,
+        rate_limit: &RateLimit,
+        count: u32,
+    ) -> Option<Self> {
+        let count = count as f64;
+        let tokens = match bucket {
+            Some(TokenBucket { tokens, updated_at }) => {
+                let elapsed_seconds = now.saturating_sub(updated_at) as f64 / 1000.0;
+                (tokens + elapsed_seconds * rate_limit.max_clicks_per_second).min(rate_limit.burst)
+            }
+            None => rate_limit.burst,
+        };
+        (tokens >= count).then_some(TokenBucket {
+            tokens: tokens - count,
+            updated_at: now.max(bucket.map_or(0, |b| b.updated_at)),
+        })
+    }
+
+    /// Extracts the token bucket from a player item, if the player already has one
+    pub fn from_item(item: &DynamoItem) -> Option<Self> {
+        let number = |name| {
+            item.get(name)
+                .and_then(|value: &AttributeValue| value.as_n().ok())
+                .map(|n| n.parse().expect("valid schema"))
+        };
+        Some(TokenBucket {
+            tokens: number(Self::TOKENS_PROPERTY_NAME)?,
+            updated_at: number(Self::UPDATED_AT_PROPERTY_NAME)? as u64,
+        })
+    }
+}
+
+/// Clicks of a player rejected because of the rate limit, since they were last counted
+///
+/// A player clicking too fast would otherwise cost a DynamoDB write per rejected click, so the
+/// rejections are counted on the player item at most once per
+/// [REJECTED_CLICKS_FLUSH_INTERVAL_MS]. The count is approximate: rejections still pending when
+/// the player stops clicking, or when the instance goes away, are not counted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct RejectedClicks {
+    /// Rejected clicks not counted yet
+    pending: u32,
+    /// Time the rejected clicks were last counted, in milliseconds since the epoch
+    counted_at: u64,
+}
+
+impl RejectedClicks {
+    /// Adds `count` rejected clicks at time `now` (in milliseconds since the epoch)
+    ///
+    /// The first rejection of a player is counted right away, so the admin sees them as soon as
+    /// possible. Returns the updated rejections, and the number of clicks to count now, if any.
+    fn add(rejected: Option<Self>, now: u64, count: u32) -> (Self, Option<u32>) {
+        match rejected {
+            Some(RejectedClicks {
+                pending,
+                counted_at,
+            }) if now.saturating_sub(counted_at) < REJECTED_CLICKS_FLUSH_INTERVAL_MS => (
+                RejectedClicks {
+                    pending: pending + count,
+                    counted_at,
+                },
+                None,
+            ),
+            rejected => (
+                RejectedClicks {
+                    pending: 0,
+                    counted_at: now,
+                },
+                Some(rejected.map_or(0, |rejected| rejected.pending) + count),
+            ),
+        }
+    }
+}
+
+/// Returns the current time in milliseconds since the epoch
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time flows forward")
+        .as_millis() as u64
+}
+
+/// Returns the last known token bucket of a player
+pub fn cached_bucket(player_id: ID) -> Option<TokenBucket> {
+    BUCKETS.lock().unwrap().get(&player_id).copied()
+}
+
+/// Remembers the token bucket of a player, or forgets it if [None]
+pub fn remember_bucket(player_id: ID, bucket: Option<TokenBucket>) {
+    let mut buckets = BUCKETS.lock().unwrap();
+    match bucket {
+        Some(bucket) => {
+            buckets.insert(player_id, bucket);
+        }
+        None => {
+            buckets.remove(&player_id);
+        }
+    }
+}
+
+/// Records `count` clicks of a player rejected because of the rate limit
+///
+/// Returns the number of rejected clicks to count on the player item now, if it is time to, see
+/// [RejectedClicks].
+pub fn reject_clicks(player_id: ID, count: u32) -> Option<u32> {
+    let mut rejected_clicks = REJECTED_CLICKS.lock().unwrap();
+    let (rejected, to_count) = RejectedClicks::add(
+        rejected_clicks.get(&player_id).copied(),
+        now_millis(),
+        count,
+    );
+    rejected_clicks.insert(player_id, rejected);
+    to_count
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    const RATE_LIMIT: RateLimit = RateLimit {
+        max_clicks_per_second: 10.0,
+        burst: 3.0,
+    };
+
+    #[test]
+    fn test_take_drains_and_refills() {
+        // A new player starts with a full bucket
+        let mut bucket = TokenBucket::take(None, 1000, &RATE_LIMIT, 1);
+        assert_eq!(
+            bucket,
+            Some(TokenBucket {
+                tokens: 2.0,
+                updated_at: 1000
+            })
+        );
+        bucket = TokenBucket::take(bucket, 1000, &RATE_LIMIT, 1);
+        bucket = TokenBucket::take(bucket, 1000, &RATE_LIMIT, 1);
+        assert_eq!(bucket.map(|b| b.tokens), Some(0.0));
+        // The burst is spent
+        assert_eq!(TokenBucket::take(bucket, 1050, &RATE_LIMIT, 1), None);
+        // One token is back after 100ms
+        let refilled = TokenBucket::take(bucket, 1100, &RATE_LIMIT, 1).unwrap();
+        assert!(refilled.tokens.abs() < 1e-9);
+        assert_eq!(refilled.updated_at, 1100);
+        // Never more than the burst, however long the player was idle
+        let idle = TokenBucket::take(Some(refilled), 60_000, &RATE_LIMIT, 1).unwrap();
+        assert_eq!(idle.tokens, 2.0);
+    }
+
+    #[test]
+    fn test_take_batch() {
+        let bucket = TokenBucket::take(None, 1000, &RATE_LIMIT, 2);
+        assert_eq!(bucket.map(|b| b.tokens), Some(1.0));
+        // Not enough tokens left for the whole batch
+        assert_eq!(TokenBucket::take(bucket, 1000, &RATE_LIMIT, 2), None);
+        assert_eq!(
+            TokenBucket::take(bucket, 1100, &RATE_LIMIT, 2).map(|b| b.tokens),
+            Some(0.0)
+        );
+        // A batch larger than the burst is never allowed
+        assert_eq!(TokenBucket::take(None, 60_000, &RATE_LIMIT, 4), None);
+    }
+
+    #[test]
+    fn test_take_ignores_clock_skew() {
+        let bucket = TokenBucket {
+            tokens: 0.5,
+            updated_at: 2000,
+        };
+        // An instance with a late clock does not refill nor move the bucket back in time
+        assert_eq!(TokenBucket::take(Some(bucket), 1000, &RATE_LIMIT, 1), None);
+        let bucket = TokenBucket {
+            tokens: 1.5,
+            updated_at: 2000,
+        };
+        assert_eq!(
+            TokenBucket::take(Some(bucket), 1000, &RATE_LIMIT, 1),
+            Some(TokenBucket {
+                tokens: 0.5,
+                updated_at: 2000
+            })
+        );
+    }
+
+    #[test]
+    fn test_rejected_clicks_are_counted_once_per_interval() {
+        // The first rejection is counted right away
+        let (rejected, to_count) = RejectedClicks::add(None, 1000, 2);
+        assert_eq!(to_count, Some(2));
+        // The next ones wait for the end of the interval
+        let (rejected, to_count) = RejectedClicks::add(Some(rejected), 2000, 1);
+        assert_eq!(to_count, None);
+        let (rejected, to_count) = RejectedClicks::add(Some(rejected), 5999, 3);
+        assert_eq!(to_count, None);
+        assert_eq!(rejected.pending, 4);
+        // Then they are counted along with the new one
+        let (rejected, to_count) =
+            RejectedClicks::add(Some(rejected), 1000 + REJECTED_CLICKS_FLUSH_INTERVAL_MS, 1);
+        assert_eq!(to_count, Some(5));
+        assert_eq!(
+            rejected,
+            RejectedClicks {
+                pending: 0,
+                counted_at: 1000 + REJECTED_CLICKS_FLUSH_INTERVAL_MS
+            }
+        );
+    }
+
+    #[test]
+    fn test_bucket_from_item() {
+        let item = HashMap::from([
+            (
+                TokenBucket::TOKENS_PROPERTY_NAME.to_owned(),
+                AttributeValue::N("1.5".to_owned()),
+            ),
+            (
+                TokenBucket::UPDATED_AT_PROPERTY_NAME.to_owned(),
+                AttributeValue::N("1234".to_owned()),
+            ),
+        ]);
+        assert_eq!(
+            TokenBucket::from_item(&item),
+            Some(TokenBucket {
+                tokens: 1.5,
+                updated_at: 1234
+            })
+        );
+        assert_eq!(TokenBucket::from_item(&HashMap::new()), None);
+    }
+}
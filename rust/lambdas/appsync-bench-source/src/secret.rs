@@ -0,0 +1,70 @@
+use std::sync::{LazyLock, Mutex};
+
+use lambda_appsync::ID;
+use lru_cache::LruCache;
+use player_secret::{StoredSecret, fingerprint};
+
+/// Largest number of players whose verified secret is kept in memory
+const MAX_CACHED_SECRETS: usize = 10_000;
+
+/// Fingerprint of a verified secret, along with the stored secret it was verified against and
+/// the ID of the team of the player
+type VerifiedSecret = ([u8; 32], StoredSecret, String);
+
+/// Secrets already verified by this lambda instance, by player
+///
+/// Verifying a hashed secret is deliberately slow, so it is only done on the first use of a
+/// secret by an instance. Only a [fingerprint] of the secret is kept, along with the
+/// [StoredSecret] it was verified against and the team of the player: updates are conditioned on
+/// both being unchanged, so a stale entry can never authorize a write.
+static VERIFIED_SECRETS: LazyLock<Mutex<LruCache<ID, VerifiedSecret>>> =
+    LazyLock::new(|| Mutex::new(LruCache::new(MAX_CACHED_SECRETS)));
+
+/// Returns the stored secret `secret` was verified against, along with the team of the player,
+/// if it was already verified
+pub fn cached_secret(player_id: ID, secret: &str) -> Option<(StoredSecret, String)> {
+    let fingerprint = fingerprint(secret);
+    VERIFIED_SECRETS
+        .lock()
+        .unwrap()
+        .get(&player_id)
+        .filter(|(cached, _, _)| *cached == fingerprint)
+        .map(|(_, stored_secret, team_id)| (stored_secret.clone(), team_id.clone()))
+}
+
+/// Remembers that `secret` was verified against `stored_secret`, for a player of `team_id`
+pub fn remember_secret(player_id: ID, secret: &str, stored_secret: StoredSecret, team_id: &str) {
+    let mut verified_secrets = VERIFIED_SECRETS.lock().unwrap();
+    verified_secrets.insert(
+        player_id,
+        (fingerprint(secret), stored_secret, team_id.to_owned()),
+    );
+}
+
+/// Forgets the verified secret of a player, e.g. after an update condition failed
+pub fn forget_secret(player_id: ID) {
+    VERIFIED_SECRETS.lock().unwrap().remove(&player_id);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret_cache() {
+        let player_id = ID::new();
+        let stored_secret = StoredSecret::Hashed("$pbkdf2-sha256$i=1$AA$AA".to_owned());
+        assert_eq!(cached_secret(player_id, "my-secret"), None);
+
+        remember_secret(player_id, "my-secret", stored_secret.clone(), "RUST");
+        assert_eq!(
+            cached_secret(player_id, "my-secret"),
+            Some((stored_secret, "RUST".to_owned()))
+        );
+        assert_eq!(cached_secret(player_id, "not-my-secret"), None);
+        assert_eq!(cached_secret(ID::new(), "my-secret"), None);
+
+        forget_secret(player_id);
+        assert_eq!(cached_secret(player_id, "my-secret"), None);
+    }
+}
@@ -0,0 +1,164 @@
+use std::{
+    cell::Cell,
+    time::{Duration, Instant},
+};
+
+use lambda_appsync::tokio;
+
+use crate::{Player, ServerTiming};
+
+tokio::task_local! {
+    /// Time spent waiting for DynamoDB by the operation being timed, see [with_server_timing]
+    static DYNAMODB_TIME: Cell<Duration>;
+    /// Time spent verifying the secret of the player by the operation being timed
+    static SECRET_TIME: Cell<Duration>;
+}
+
+impl ServerTiming {
+    /// Builds the timing of an operation from the durations measured
+    fn new(handler: Duration, dynamodb: Duration, secret: Duration) -> Self {
+        ServerTiming {
+            handler_ms: handler.as_secs_f64() * 1000.0,
+            dynamodb_ms: dynamodb.as_secs_f64() * 1000.0,
+            secret_ms: secret.as_secs_f64() * 1000.0,
+        }
+    }
+}
+
+/// Runs an `operation` returning a player, and sets the `server_timing` of the player to the
+/// time spent on the operation, on its DynamoDB requests and on verifying the secret of the player
+///
+/// Only the DynamoDB requests awaited through [Timed::timed] and the verifications awaited through
+/// [Timed::timed_secret_verification] are accounted for.
+pub async fn with_server_timing(
+    operation: impl Future<Output = Result<Player, lambda_appsync::AppsyncError>>,
+) -> Result<Player, lambda_appsync::AppsyncError> {
+    let start = Instant::now();
+    let (result, dynamodb_time, secret_time) = DYNAMODB_TIME
+        .scope(
+            Cell::new(Duration::ZERO),
+            SECRET_TIME.scope(Cell::new(Duration::ZERO), async {
+                let result = operation.await;
+                (
+                    result,
+                    DYNAMODB_TIME.with(Cell::get),
+                    SECRET_TIME.with(Cell::get),
+                )
+            }),
+        )
+        .await;
+    result.map(|mut player| {
+        player.server_timing = Some(ServerTiming::new(
+            start.elapsed(),
+            dynamodb_time,
+            secret_time,
+        ));
+        player
+    })
+}
+
+/// Awaits `future`, adding its duration to the time of the operation being timed held by `time`,
+/// if any
+async fn add_duration<F: Future>(
+    time: &'static tokio::task::LocalKey<Cell<Duration>>,
+    future: F,
+) -> F::Output {
+    let start = Instant::now();
+    let output = future.await;
+    // Futures awaited outside of [with_server_timing] are not timed
+    let _ = time.try_with(|time| time.set(time.get() + start.elapsed()));
+    output
+}
+
+/// Timing of the DynamoDB requests and of the secret verifications, for [with_server_timing]
+pub trait Timed: Future + Sized {
+    /// Awaits the request, adding its duration to the DynamoDB time of the operation being
+    /// timed, if any
+    ///
+    /// Durations of concurrent requests are summed.
+    fn timed(self) -> impl Future<Output = Self::Output> {
+        add_duration(&DYNAMODB_TIME, self)
+    }
+
+    /// Awaits the verification of a secret, adding its duration to the secret time of the
+    /// operation being timed, if any
+    fn timed_secret_verification(self) -> impl Future<Output = Self::Output> {
+        add_duration(&SECRET_TIME, self)
+    }
+}
+
+impl<F: Future> Timed for F {}
+
+#[cfg(test)]
+mod tests {
+    use lambda_appsync::{AppsyncError, ID};
+
+    use super::*;
+
+    /// The player an operation returns, whose content does not matter to the timing
+    fn player() -> Player {
+        Player {
+            id: ID::new(),
+            game_id: ID::new(),
+            name: "Player".to_owned(),
+            team: "RUST".to_owned(),
+            clicks: Some(1),
+            avg_latency: None,
+            avg_latency_clicks: None,
+            rate_limited_clicks: None,
+            latency_percentiles: None,
+            server_timing: None,
+        }
+    }
+
+    #[tokio::test(crate = "lambda_appsync::tokio")]
+    async fn test_timed_sums_dynamodb_time() {
+        let player = with_server_timing(async {
+            tokio::time::sleep(Duration::from_millis(20)).timed().await;
+            tokio::time::sleep(Duration::from_millis(10)).timed().await;
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            Ok(player())
+        })
+        .await
+        .unwrap();
+        let timing = player.server_timing.unwrap();
+        assert!(timing.dynamodb_ms >= 30.0);
+        assert!(timing.handler_ms >= timing.dynamodb_ms + 10.0);
+        assert_eq!(timing.secret_ms, 0.0);
+    }
+
+    #[tokio::test(crate = "lambda_appsync::tokio")]
+    async fn test_timed_secret_verification() {
+        let player = with_server_timing(async {
+            tokio::time::sleep(Duration::from_millis(20))
+                .timed_secret_verification()
+                .await;
+            tokio::time::sleep(Duration::from_millis(10)).timed().await;
+            Ok(player())
+        })
+        .await
+        .unwrap();
+        let timing = player.server_timing.unwrap();
+        assert!(timing.secret_ms >= 20.0);
+        assert!(timing.dynamodb_ms >= 10.0 && timing.dynamodb_ms < timing.secret_ms);
+        assert!(timing.handler_ms >= timing.dynamodb_ms + timing.secret_ms);
+    }
+
+    #[tokio::test(crate = "lambda_appsync::tokio")]
+    async fn test_timed_operations_are_independent() {
+        // Requests outside of an operation, or in another one, are not accounted for
+        tokio::time::sleep(Duration::from_millis(20)).timed().await;
+        let (slow, fast) = tokio::join!(
+            with_server_timing(async {
+                tokio::time::sleep(Duration::from_millis(20)).timed().await;
+                Ok(player())
+            }),
+            with_server_timing(async { Ok(player()) }),
+        );
+        assert!(slow.unwrap().server_timing.unwrap().dynamodb_ms >= 20.0);
+        assert_eq!(fast.unwrap().server_timing.unwrap().dynamodb_ms, 0.0);
+
+        let error = with_server_timing(async { Err(AppsyncError::new("Error", "Failed")) }).await;
+        assert!(error.is_err());
+    }
+}
@@ -0,0 +1,103 @@
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+use builtin_teams::BUILTIN_TEAMS;
+
+use crate::{Backend, Team};
+
+/// How long a team read from DynamoDB is trusted by this lambda instance
+///
+/// Teams rarely change, and a team changing backend must be announced to the players anyway
+const TEAM_CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// When a team was read, and the team as read ([None] if it does not exist)
+type ReadTeam = (Instant, Option<Team>);
+
+/// Teams already read by this lambda instance, by ID
+static TEAMS: LazyLock<Mutex<HashMap<String, ReadTeam>>> = LazyLock::new(Default::default);
+
+impl Backend {
+    /// Returns whether the players of the teams served by the backend have their secret
+    /// stored hashed, see [builtin_teams::hashes_secret]
+    pub fn hashes_secret(self) -> bool {
+        builtin_teams::hashes_secret(&self.to_string())
+    }
+}
+
+impl Team {
+    /// The teams always available, unless a stored team replaces them, see [BUILTIN_TEAMS]
+    pub fn builtin() -> Vec<Team> {
+        BUILTIN_TEAMS
+            .iter()
+            .map(|team| Team {
+                id: team.id.to_owned(),
+                name: team.name.to_owned(),
+                backend: team
+                    .id
+                    .parse()
+                    .expect("builtin teams are named after a backend"),
+            })
+            .collect()
+    }
+}
+
+/// Returns the team `team_id` as read at most [TEAM_CACHE_TTL] before `now`, if it was
+///
+/// The outer [None] means the team must be read again, the inner one that it does not exist
+pub fn cached_team(team_id: &str, now: Instant) -> Option<Option<Team>> {
+    TEAMS
+        .lock()
+        .unwrap()
+        .get(team_id)
+        .filter(|(read_at, _)| now.saturating_duration_since(*read_at) < TEAM_CACHE_TTL)
+        .map(|(_, team)| team.clone())
+}
+
+/// Remembers the team `team_id` as read at `now`, [None] if it does not exist
+pub fn remember_team(team_id: &str, team: Option<Team>, now: Instant) {
+    TEAMS
+        .lock()
+        .unwrap()
+        .insert(team_id.to_owned(), (now, team));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_teams_are_served_by_their_backend() {
+        for team in Team::builtin() {
+            assert_eq!(team.id, team.backend.to_string());
+        }
+        assert!(Backend::Rust.hashes_secret());
+        assert!(Backend::Python.hashes_secret());
+        assert!(!Backend::Js.hashes_secret());
+    }
+
+    #[test]
+    fn test_team_cache_expires() {
+        let now = Instant::now();
+        let team = Team {
+            id: "TEAM_CACHE_TEST".to_owned(),
+            name: "Team Cache".to_owned(),
+            backend: Backend::Rust,
+        };
+        assert!(cached_team(&team.id, now).is_none());
+
+        remember_team(&team.id, Some(team.clone()), now);
+        let cached = cached_team(&team.id, now + TEAM_CACHE_TTL / 2);
+        assert_eq!(cached.flatten().map(|t| t.name), Some(team.name.clone()));
+        assert!(cached_team(&team.id, now + TEAM_CACHE_TTL).is_none());
+
+        // Teams that do not exist are remembered as such
+        remember_team("TEAM_CACHE_MISSING", None, now);
+        assert_eq!(
+            cached_team("TEAM_CACHE_MISSING", now).map(|t| t.is_none()),
+            Some(true)
+        );
+    }
+}
@@ -1,37 +1,87 @@
-use std::collections::HashMap;
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use aws_sdk_dynamodb::types::{
-    AttributeValue, ReturnValue, WriteRequest, builders::PutRequestBuilder,
+    AttributeValue, ConditionCheck, Delete, KeysAndAttributes, Put, ReturnValue,
+    ReturnValuesOnConditionCheckFailure, TransactWriteItem, Update, WriteRequest,
+    builders::PutRequestBuilder, error::TransactionCanceledException,
 };
 use dynamodb_utils::{
-    DynamoDBItem, DynamoItem, PK, TYPE, dynamodb_batch_write, dynamodb_delete_item,
-    dynamodb_perform_scan, table_name,
+    BANNED_ATTRIBUTE, CLICKS_OPEN_AT_ATTRIBUTE, DynamoDBItem, DynamoItem, PK,
+    STATE_VERSION_ATTRIBUTE, TYPE, dynamodb_batch_write, dynamodb_perform_scan, table_name,
+};
+use lambda_appsync::{AWSTimestamp, ID, log, tokio};
+use latency_histogram::{
+    LatencyHistogram, SAMPLES_ATTRIBUTE, TOTAL_ATTRIBUTE, UNREPORTED_ATTRIBUTE,
 };
-use lambda_appsync::{ID, log};
+use player_secret::{HASH_ATTRIBUTE, PLAINTEXT_ATTRIBUTE, StoredSecret};
 
 use serde_dynamo::{from_attribute_value, to_attribute_value};
 
-use crate::{GameStatus, Player, Team, dynamodb};
+use crate::{
+    Ban, Game, GameConfig, GameRound, GameState, GameStatus, LatencyPercentiles, Player, Team,
+    dynamodb,
+    player_name::{PlayerName, normalize},
+};
+
+/// DynamoDB storage interface for [Game] records
+impl Game {
+    /// Partition key prefix for Game items
+    const PK_TYPE: &'static str = "GAME";
+
+    /// Generates the partition key for a game ID
+    fn pk_from_uuid(id: ID) -> String {
+        format!("{}#{}", Self::PK_TYPE, id)
+    }
+}
+
+/// DynamoDB table interface implementation for Game
+impl DynamoDBItem for Game {
+    type Id = ID;
+
+    fn get_key(&self) -> DynamoItem {
+        Self::get_key_from_id(self.id)
+    }
+    fn get_key_from_id(id: Self::Id) -> DynamoItem {
+        HashMap::from([(PK.to_owned(), AttributeValue::S(Self::pk_from_uuid(id)))])
+    }
+    fn get_type() -> &'static str {
+        Self::PK_TYPE
+    }
+}
 
-/// Represents the game status in DynamoDB
+/// Represents the game state in DynamoDB
 /// Contains constants and implementations for DynamoDB storage
-impl GameStatus {
-    /// Partition key prefix for GameStatus items
+impl GameState {
+    /// Partition key prefix for GameState items
     const PK_TYPE: &'static str = "GAME_STATUS";
     /// Name of attribute storing the actual game status value
-    const PROPERTY_NAME: &'static str = "game_status";
+    const STATUS_PROPERTY_NAME: &'static str = "game_status";
+    /// Name of the [GameState] field holding the game status
+    const STATUS_FIELD_NAME: &'static str = "status";
+
+    /// Generates the partition key for the state of a game ID
+    fn pk_from_uuid(game_id: ID) -> String {
+        format!("{}#{}", Self::PK_TYPE, game_id)
+    }
 }
 
-/// DynamoDB storage implementation for [GameStatus]
-impl DynamoDBItem for GameStatus {
-    type Id = ();
+/// DynamoDB storage implementation for [GameState]
+impl DynamoDBItem for GameState {
+    type Id = ID;
 
     fn get_key(&self) -> DynamoItem {
-        Self::get_key_from_id(())
+        Self::get_key_from_id(self.game_id)
     }
 
-    fn get_key_from_id(_id: Self::Id) -> DynamoItem {
-        HashMap::from([(PK.to_owned(), AttributeValue::S(Self::PK_TYPE.to_owned()))])
+    fn get_key_from_id(game_id: Self::Id) -> DynamoItem {
+        HashMap::from([(
+            PK.to_owned(),
+            AttributeValue::S(Self::pk_from_uuid(game_id)),
+        )])
     }
 
     fn get_type() -> &'static str {
@@ -40,46 +90,106 @@ impl DynamoDBItem for GameStatus {
 
     fn to_item(&self) -> DynamoItem {
         let mut item = self.to_item_core();
-        item.insert(
-            Self::PROPERTY_NAME.to_owned(),
-            to_attribute_value(self).unwrap(),
-        );
+        let mut inner: DynamoItem = serde_dynamo::to_item(self).expect("valid schema");
+        // The status is stored under STATUS_PROPERTY_NAME, as expected by every backend
+        if let Some(status) = inner.remove(Self::STATUS_FIELD_NAME) {
+            item.insert(Self::STATUS_PROPERTY_NAME.to_owned(), status);
+        }
+        item.extend(inner);
         item
     }
 
     fn from_item(mut item: DynamoItem) -> Self {
-        item.remove(Self::PROPERTY_NAME)
-            .map(|a| serde_dynamo::from_attribute_value(a).expect("valid schema"))
-            .expect("valid schema")
+        if let Some(status) = item.remove(Self::STATUS_PROPERTY_NAME) {
+            item.insert(Self::STATUS_FIELD_NAME.to_owned(), status);
+        }
+        serde_dynamo::from_item(item).expect("valid schema")
     }
 }
 
-/// Retrieves all [Player] items from DynamoDB as raw [DynamoItem]
+/// Creates a new game in DynamoDB, along with its initial [GameState]
+///
+/// Both items are written in a single transaction
+pub async fn dynamodb_create_game(game: &Game) -> Result<(), aws_sdk_dynamodb::Error> {
+    log::debug!("ENTER dynamodb_create_game - game={game:?}");
+
+    let put_game = Put::builder()
+        .table_name(table_name())
+        .set_item(Some(game.to_item()))
+        .condition_expression(format!("attribute_not_exists({PK})"))
+        .build()
+        .expect("table and item are set");
+    let put_state = Put::builder()
+        .table_name(table_name())
+        .set_item(Some(GameState::reset(game.id).to_item()))
+        .condition_expression(format!("attribute_not_exists({PK})"))
+        .build()
+        .expect("table and item are set");
+
+    dynamodb()
+        .transact_write_items()
+        .transact_items(TransactWriteItem::builder().put(put_game).build())
+        .transact_items(TransactWriteItem::builder().put(put_state).build())
+        .send()
+        .await?;
+
+    Ok(())
+}
+
+/// Retrieves all the [Game] from DynamoDB
+pub async fn dynamodb_query_games() -> Result<Vec<Game>, aws_sdk_dynamodb::Error> {
+    log::debug!("ENTER dynamodb_query_games");
+
+    let scan_req_builder = dynamodb()
+        .scan()
+        .table_name(table_name())
+        .filter_expression("#type = :game_type")
+        .projection_expression(format!("{PK},#type,id,#name"))
+        .expression_attribute_names("#type", TYPE)
+        .expression_attribute_names("#name", "name")
+        .expression_attribute_values(":game_type", AttributeValue::S(Game::get_type().to_owned()));
+
+    Ok(dynamodb_perform_scan(scan_req_builder)
+        .await?
+        .into_iter()
+        .map(Game::from_item)
+        .collect())
+}
+
+/// Retrieves all [Player] items of a game from DynamoDB as raw [DynamoItem]
 /// Used internally by query functions that need access to the full item data
-async fn dynamodb_list_player_items() -> Result<Vec<DynamoItem>, aws_sdk_dynamodb::Error> {
+async fn dynamodb_list_player_items(
+    game_id: ID,
+) -> Result<Vec<DynamoItem>, aws_sdk_dynamodb::Error> {
     let scan_req_builder = dynamodb()
         .scan()
         .table_name(table_name())
-        .filter_expression("#type = :player_type")
+        .filter_expression("#type = :player_type AND game_id = :game_id")
         .expression_attribute_names("#type", TYPE)
         .expression_attribute_values(
             ":player_type",
             AttributeValue::S(Player::get_type().to_owned()),
-        );
+        )
+        .expression_attribute_values(":game_id", to_attribute_value(game_id).unwrap());
 
-    Ok(dynamodb_perform_scan(scan_req_builder).await?)
+    dynamodb_perform_scan(scan_req_builder).await
 }
 
-/// Resets the game state and clears all player scores
+/// Resets the game state and clears the scores of all the players of the game
 ///
-/// First sets game status to [GameStatus::Reset], then removes all score-related attributes
+/// First sets game status to [crate::GameStatus::Reset], then removes all score-related attributes
 /// from player records while preserving other player data
-pub async fn dynamodb_reset_game() -> Result<(), aws_sdk_dynamodb::Error> {
-    log::debug!("ENTER dynamodb_reset_game");
+pub async fn dynamodb_reset_game(game_id: ID) -> Result<(), aws_sdk_dynamodb::Error> {
+    log::debug!("ENTER dynamodb_reset_game - game_id={game_id}");
     // Start by changing the state to Reset
     // It serves to verify we are actualy in the correct state pour doing that
     // It also prevents any further usage of the "click" button
-    dynamodb_set_game_status(GameStatus::Reset).await?;
+    // Can only be reset from the statuses that allow it
+    let state_version = dynamodb_set_game_state(
+        GameState::reset(game_id),
+        GameStatus::Reset.valid_from_status(),
+    )
+    .await?;
 
     // Note that from this point and until we finish cleaning the players, the game is
     // in a somewhat incorrect state: the status is technically `Reset` but players still have scores.
@@ -87,7 +197,7 @@ pub async fn dynamodb_reset_game() -> Result<(), aws_sdk_dynamodb::Error> {
 
     // List players as DynamoItem
     // Because we want to retrieve the `secret` field and put it back with the PutItem
-    let player_items = dynamodb_list_player_items().await?;
+    let player_items = dynamodb_list_player_items(game_id).await?;
 
     // Create the iterator of BatchWriteRequest that will PUT every players without clicks/latency
     let batch_write_requests = player_items
@@ -96,6 +206,16 @@ pub async fn dynamodb_reset_game() -> Result<(), aws_sdk_dynamodb::Error> {
             player_item.remove("clicks");
             player_item.remove("avg_latency");
             player_item.remove("avg_latency_clicks");
+            player_item.remove("rate_limited_clicks");
+            player_item.remove(TOTAL_ATTRIBUTE);
+            player_item.remove(SAMPLES_ATTRIBUTE);
+            player_item.remove(UNREPORTED_ATTRIBUTE);
+            player_item.remove(CLICKS_OPEN_AT_ATTRIBUTE);
+            player_item.insert(
+                STATE_VERSION_ATTRIBUTE.to_owned(),
+                to_attribute_value(state_version).unwrap(),
+            );
+            player_item.retain(|name, _| !LatencyHistogram::is_attribute_name(name));
             // Create the BatchWriteRequest
             WriteRequest::builder()
                 .put_request(
@@ -110,28 +230,102 @@ pub async fn dynamodb_reset_game() -> Result<(), aws_sdk_dynamodb::Error> {
     dynamodb_batch_write(dynamodb(), batch_write_requests).await
 }
 
-/// Updates the game status in DynamoDB
+/// Returns the current time in milliseconds since the epoch
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time flows forward")
+        .as_millis() as u64
+}
+
+/// Returns a new version for a game state: the current time in milliseconds since the epoch, or
+/// just above the `current` version if the clock of the lambda instance that wrote it is ahead
 ///
-/// Enforces valid state transitions by checking the current status matches
-/// what is expected for the requested new status
-pub async fn dynamodb_set_game_status(status: GameStatus) -> Result<(), aws_sdk_dynamodb::Error> {
-    log::debug!("ENTER dynamodb_set_game_status - status={status:?}");
-    // Can only set GameStatus in some order
-    let current_status = status.valid_from_status();
+/// Versions always increase, which keeps the player items from going back to an older state, see
+/// [dynamodb_set_players_clicks_open_at].
+fn next_state_version(now: u64, current: Option<u64>) -> u64 {
+    current.map_or(now, |current| now.max(current + 1))
+}
 
-    dynamodb()
-        .put_item()
-        .table_name(table_name())
-        .set_item(Some(status.to_item()))
-        .condition_expression(format!(
-            "attribute_not_exists({PK}) OR {} = :game_status",
-            GameStatus::PROPERTY_NAME
-        ))
-        .expression_attribute_values(":game_status", to_attribute_value(current_status).unwrap())
-        .return_values(ReturnValue::None)
-        .send()
-        .await?;
-    Ok(())
+/// Reads the version of a game state item, if it has one
+fn state_version_from_item(item: &DynamoItem) -> Option<u64> {
+    item.get(STATE_VERSION_ATTRIBUTE)
+        .map(|state_version| from_attribute_value(state_version.clone()).expect("valid schema"))
+}
+
+/// Updates the game state in DynamoDB
+///
+/// Enforces valid state transitions by checking the game exists and its current status is one of
+/// `expected`: the status the new state was computed from, or those valid for a new state that
+/// does not depend on the current one
+///
+/// Returns the version stamped on the new state, to be copied to the player items with
+/// [dynamodb_set_players_clicks_open_at]
+pub async fn dynamodb_set_game_state(
+    state: GameState,
+    expected: &[GameStatus],
+) -> Result<u64, aws_sdk_dynamodb::Error> {
+    log::debug!("ENTER dynamodb_set_game_state - state={state:?} expected={expected:?}");
+    let placeholders = (0..expected.len())
+        .map(|i| format!(":game_status{i}"))
+        .collect::<Vec<_>>();
+    let mut state_version = next_state_version(now_millis(), None);
+    loop {
+        let mut item = state.to_item();
+        item.insert(
+            STATE_VERSION_ATTRIBUTE.to_owned(),
+            to_attribute_value(state_version).unwrap(),
+        );
+
+        let mut put = dynamodb()
+            .put_item()
+            .table_name(table_name())
+            .set_item(Some(item))
+            .condition_expression(format!(
+                "attribute_exists({PK}) AND {} IN ({}) \
+                AND (attribute_not_exists(#state_version) OR #state_version < :state_version)",
+                GameState::STATUS_PROPERTY_NAME,
+                placeholders.join(", ")
+            ))
+            .expression_attribute_names("#state_version", STATE_VERSION_ATTRIBUTE)
+            .expression_attribute_values(
+                ":state_version",
+                to_attribute_value(state_version).unwrap(),
+            )
+            .return_values_on_condition_check_failure(ReturnValuesOnConditionCheckFailure::AllOld);
+        for (placeholder, current_status) in placeholders.iter().zip(expected) {
+            put = put.expression_attribute_values(
+                placeholder,
+                to_attribute_value(current_status).unwrap(),
+            );
+        }
+        match put
+            .return_values(ReturnValue::None)
+            .send()
+            .await
+            .map_err(aws_sdk_dynamodb::Error::from)
+        {
+            Ok(_) => return Ok(state_version),
+            Err(aws_sdk_dynamodb::Error::ConditionalCheckFailedException(e)) => {
+                // Only the version can be retried: the status must still allow the transition
+                let current_version = e
+                    .item()
+                    .filter(|item| {
+                        item.get(GameState::STATUS_PROPERTY_NAME)
+                            .and_then(|status| from_attribute_value(status.clone()).ok())
+                            .is_some_and(|status: GameStatus| expected.contains(&status))
+                    })
+                    .and_then(state_version_from_item);
+                match current_version {
+                    Some(current_version) if current_version >= state_version => {
+                        state_version = next_state_version(now_millis(), Some(current_version));
+                    }
+                    _ => return Err(aws_sdk_dynamodb::Error::ConditionalCheckFailedException(e)),
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
 }
 
 /// DynamoDB storage interface for Player records
@@ -145,6 +339,8 @@ impl Player {
     }
 }
 
+latency_histogram::impl_latency_statistics!(Player, LatencyPercentiles);
+
 /// DynamoDB table interface implementation for Player
 impl DynamoDBItem for Player {
     type Id = ID;
@@ -158,134 +354,1901 @@ impl DynamoDBItem for Player {
     fn get_type() -> &'static str {
         Self::PK_TYPE
     }
+    fn from_item(item: DynamoItem) -> Self {
+        Self::from_item_with_latency(item).0
+    }
+}
+
+/// Outcome of [dynamodb_put_new_player]
+pub enum PlayerCreation {
+    /// The player was created
+    Created,
+    /// The player counter of the team changed since it was read
+    TeamCountChanged,
+    /// Another player of the game already has the same name
+    NameTaken,
+    /// The status of the game changed since it was read
+    GameStateChanged,
+}
+
+/// Name of the attribute of a player item holding the digest of their secret within their game,
+/// see [player_secret::scoped_digest]
+const SECRET_DIGEST_ATTRIBUTE: &str = "secret_digest";
+
+/// Copies the time from which the game accepts clicks, [None] if it does not, and the
+/// `state_version` returned by [dynamodb_set_game_state] to the items of all the players of the
+/// game
+///
+/// Must be called after each change of the state of the game. Players created concurrently are
+/// covered as long as their creation is conditioned on the previous state of the game, see
+/// [dynamodb_put_new_player]. A player item already holding this version or a newer one is left
+/// untouched, so a slow copy of an older state cannot overwrite a newer one.
+///
+/// The players that could not be updated are retried a few times. If some still fail, the error
+/// is returned and those players keep the previous state until the copy is done again, with the
+/// state returned by [dynamodb_get_versioned_game_state].
+pub async fn dynamodb_set_players_clicks_open_at(
+    game_id: ID,
+    clicks_open_at: Option<AWSTimestamp>,
+    state_version: u64,
+) -> Result<(), aws_sdk_dynamodb::Error> {
+    log::debug!(
+        "ENTER dynamodb_set_players_clicks_open_at - game_id={game_id} \
+        clicks_open_at={clicks_open_at:?} state_version={state_version}"
+    );
+
+    // Strongly consistent, so the players created just before the state changed are updated
+    let scan_req_builder = dynamodb()
+        .scan()
+        .table_name(table_name())
+        .consistent_read(true)
+        .filter_expression("#type = :player_type AND game_id = :game_id")
+        .projection_expression(PK)
+        .expression_attribute_names("#type", TYPE)
+        .expression_attribute_values(
+            ":player_type",
+            AttributeValue::S(Player::get_type().to_owned()),
+        )
+        .expression_attribute_values(":game_id", to_attribute_value(game_id).unwrap());
+    let mut player_keys = dynamodb_perform_scan(scan_req_builder).await?;
+
+    let update_player = |player_key: DynamoItem| {
+        let update = dynamodb()
+            .update_item()
+            .table_name(table_name())
+            .set_key(Some(player_key))
+            .expression_attribute_names("#clicks_open_at", CLICKS_OPEN_AT_ATTRIBUTE)
+            .expression_attribute_names("#state_version", STATE_VERSION_ATTRIBUTE)
+            .expression_attribute_values(
+                ":state_version",
+                to_attribute_value(state_version).unwrap(),
+            )
+            // The player may have been removed meanwhile, or already hold a newer state
+            .condition_expression(format!(
+                "attribute_exists({PK}) AND \
+                (attribute_not_exists(#state_version) OR #state_version < :state_version)"
+            ));
+        let update = match clicks_open_at {
+            Some(clicks_open_at) => update
+                .update_expression(
+                    "SET #clicks_open_at = :clicks_open_at, #state_version = :state_version",
+                )
+                .expression_attribute_values(
+                    ":clicks_open_at",
+                    to_attribute_value(clicks_open_at).unwrap(),
+                ),
+            None => update
+                .update_expression("SET #state_version = :state_version REMOVE #clicks_open_at"),
+        };
+        tokio::spawn(update.send())
+    };
+
+    let mut attempts = 1;
+    loop {
+        // Update the players in parallel, a few at a time
+        let mut failed_keys = vec![];
+        let mut last_error = None;
+        for chunk in player_keys.chunks(MAX_PARALLEL_UPDATES) {
+            let handles = chunk
+                .iter()
+                .map(|player_key| (player_key, update_player(player_key.clone())))
+                .collect::<Vec<_>>();
+            for (player_key, handle) in handles {
+                let result = handle.await.unwrap().map_err(aws_sdk_dynamodb::Error::from);
+                if let PlayerStateUpdate::Failed(e) = PlayerStateUpdate::from_result(result) {
+                    failed_keys.push(player_key.clone());
+                    last_error = Some(e);
+                }
+            }
+        }
+        match last_error {
+            None => return Ok(()),
+            Some(e) if attempts == MAX_PLAYER_UPDATE_ATTEMPTS => return Err(e),
+            Some(e) => log::warn!(
+                "Retrying the update of {} players of game {game_id}: {e}",
+                failed_keys.len()
+            ),
+        }
+        player_keys = failed_keys;
+        attempts += 1;
+    }
+}
+
+/// Largest number of player items updated at the same time
+const MAX_PARALLEL_UPDATES: usize = 50;
+/// Number of times the update of a player item is attempted by
+/// [dynamodb_set_players_clicks_open_at]
+const MAX_PLAYER_UPDATE_ATTEMPTS: usize = 3;
+
+/// Outcome of the copy of the state of the game to a player item
+#[derive(Debug)]
+enum PlayerStateUpdate {
+    /// The player item holds the new state
+    Updated,
+    /// The player was removed, or already holds this state or a newer one
+    Skipped,
+    /// The update failed, and can be retried
+    Failed(aws_sdk_dynamodb::Error),
+}
+
+impl PlayerStateUpdate {
+    /// Classifies the result of the update of a player item
+    fn from_result<T>(result: Result<T, aws_sdk_dynamodb::Error>) -> Self {
+        match result {
+            Ok(_) => PlayerStateUpdate::Updated,
+            Err(aws_sdk_dynamodb::Error::ConditionalCheckFailedException(_)) => {
+                PlayerStateUpdate::Skipped
+            }
+            Err(e) => PlayerStateUpdate::Failed(e),
+        }
+    }
 }
 
-/// Creates a new player record in DynamoDB
+/// Creates a new player record in DynamoDB, increments the player counter of their team and
+/// reserves their name in the game
 ///
-/// Adds the provided secret along with the player data for future authentication of the player
+/// Adds the provided secret along with the player data for future authentication of the player,
+/// and its `secret_digest` to recognize the secret if the player gets banned.
+/// The player accepts clicks from the time given by the current `game_state`.
+/// All the writes happen in a single transaction, conditioned on the team counter still having the
+/// `expected_team_count` value it had when the team was chosen ([None] if it did not exist), on
+/// the name not being reserved yet and on the status of the game being unchanged. Nothing is
+/// written if the player cannot be created.
 pub async fn dynamodb_put_new_player(
     new_player: &Player,
-    secret: String,
-) -> Result<(), aws_sdk_dynamodb::Error> {
-    log::debug!("ENTER dynamodb_put_new_player - new_player={new_player:?}");
+    name: &PlayerName,
+    stored_secret: StoredSecret,
+    secret_digest: &str,
+    expected_team_count: Option<i64>,
+    game_state: &GameState,
+) -> Result<PlayerCreation, aws_sdk_dynamodb::Error> {
+    log::debug!(
+        "ENTER dynamodb_put_new_player - new_player={new_player:?} \
+        expected_team_count={expected_team_count:?}"
+    );
 
     let mut player_item = new_player.to_item();
     // Add secret
-    player_item.insert("secret".to_owned(), AttributeValue::S(secret));
+    player_item.insert(
+        stored_secret.attribute_name().to_owned(),
+        stored_secret.to_attribute_value(),
+    );
+    player_item.insert(
+        SECRET_DIGEST_ATTRIBUTE.to_owned(),
+        AttributeValue::S(secret_digest.to_owned()),
+    );
+    if let Some(clicks_open_at) = game_state.clicks_open_at() {
+        player_item.insert(
+            CLICKS_OPEN_AT_ATTRIBUTE.to_owned(),
+            to_attribute_value(clicks_open_at).unwrap(),
+        );
+    }
 
-    dynamodb()
-        .put_item()
+    let put_player = Put::builder()
         .table_name(table_name())
         .set_item(Some(player_item))
         .condition_expression(format!("attribute_not_exists({PK})"))
-        .return_values(ReturnValue::None)
+        .build()
+        .expect("table and item are set");
+    let increment_counter = team_counter_update(
+        new_player.game_id,
+        &new_player.team,
+        1,
+        Some(expected_team_count),
+    );
+    let reserve_name = player_name_reservation(new_player, &name.normalized);
+    // Otherwise the player could miss the copy of the new state to the player items
+    let check_game_status = ConditionCheck::builder()
+        .table_name(table_name())
+        .set_key(Some(game_state.get_key()))
+        .condition_expression(format!(
+            "{} = :game_status",
+            GameState::STATUS_PROPERTY_NAME
+        ))
+        .expression_attribute_values(
+            ":game_status",
+            to_attribute_value(game_state.status).unwrap(),
+        )
+        .build()
+        .expect("table, key and condition are set");
+
+    let result = dynamodb()
+        .transact_write_items()
+        .transact_items(TransactWriteItem::builder().put(put_player).build())
+        .transact_items(
+            TransactWriteItem::builder()
+                .update(increment_counter)
+                .build(),
+        )
+        .transact_items(TransactWriteItem::builder().put(reserve_name).build())
+        .transact_items(
+            TransactWriteItem::builder()
+                .condition_check(check_game_status)
+                .build(),
+        )
         .send()
-        .await?;
+        .await;
+    match result {
+        Ok(_) => Ok(PlayerCreation::Created),
+        Err(e) => match aws_sdk_dynamodb::Error::from(e) {
+            aws_sdk_dynamodb::Error::TransactionCanceledException(e)
+                if is_conditional_check_failed(&e, 1) =>
+            {
+                Ok(PlayerCreation::TeamCountChanged)
+            }
+            aws_sdk_dynamodb::Error::TransactionCanceledException(e)
+                if is_conditional_check_failed(&e, 2) =>
+            {
+                Ok(PlayerCreation::NameTaken)
+            }
+            aws_sdk_dynamodb::Error::TransactionCanceledException(e)
+                if is_conditional_check_failed(&e, 3) =>
+            {
+                Ok(PlayerCreation::GameStateChanged)
+            }
+            e => Err(e),
+        },
+    }
+}
 
-    Ok(())
+/// Returns whether the transaction was canceled because the condition of its `index`-th
+/// item failed
+fn is_conditional_check_failed(e: &TransactionCanceledException, index: usize) -> bool {
+    e.cancellation_reasons()
+        .get(index)
+        .and_then(|reason| reason.code())
+        == Some("ConditionalCheckFailed")
+}
+
+/// Returns the item of the `index`-th item of the transaction whose condition failed, if it
+/// asked for it
+fn condition_check_item(e: &TransactionCanceledException, index: usize) -> Option<&DynamoItem> {
+    e.cancellation_reasons()
+        .get(index)
+        .and_then(|reason| reason.item())
+}
+
+/// Returns whether a player item returned by a failed condition is banned
+fn is_banned(item: Option<&DynamoItem>) -> bool {
+    item.is_some_and(|item| item.contains_key(BANNED_ATTRIBUTE))
 }
 
-/// Updates a player's name after verifying their secret
+/// Retrieves a [Player] from DynamoDB along with their stored secret, [None] if their item has
+/// none (e.g. it was edited by hand)
 ///
-/// Returns the updated [Player] record
-pub async fn dynamodb_update_player_name(
+/// Returns [None] if the player does not exist
+pub async fn dynamodb_get_player_with_secret(
     player_id: ID,
-    new_name: String,
-    secret: String,
-) -> Result<Player, aws_sdk_dynamodb::Error> {
-    log::debug!("ENTER dynamodb_update_player_name - player_id={player_id} new_name={new_name}");
+) -> Result<Option<(Player, Option<StoredSecret>)>, aws_sdk_dynamodb::Error> {
+    log::debug!("ENTER dynamodb_get_player_with_secret - player_id={player_id}");
 
     Ok(dynamodb()
-        .update_item()
+        .get_item()
         .table_name(table_name())
         .set_key(Some(Player::get_key_from_id(player_id)))
-        .update_expression("SET #name = :name")
+        .send()
+        .await?
+        .item
+        .map(|item| {
+            let stored_secret = StoredSecret::from_item(&item);
+            (Player::from_item(item), stored_secret)
+        }))
+}
+
+/// Outcome of [dynamodb_update_player_name]
+pub enum PlayerRename {
+    /// The player now has the new name
+    Renamed(Player),
+    /// Another player of the game already has the same name
+    NameTaken,
+    /// The player was banned since they were read
+    Banned,
+    /// The player changed name or secret since they were read
+    PlayerChanged,
+}
+
+/// Renames a player, moving the reservation of their name to the new one
+///
+/// All the writes happen in a single transaction, conditioned on the player still having the
+/// name and `stored_secret` they had when read, not being banned, and on the new name not being
+/// reserved by another player. If `new_secret_hash` is provided, it replaces the stored secret
+/// (see [StoredSecret::is_plaintext]).
+pub async fn dynamodb_update_player_name(
+    player: Player,
+    new_name: PlayerName,
+    stored_secret: &StoredSecret,
+    new_secret_hash: Option<String>,
+) -> Result<PlayerRename, aws_sdk_dynamodb::Error> {
+    log::debug!(
+        "ENTER dynamodb_update_player_name - player_id={} new_name={}",
+        player.id,
+        new_name.display
+    );
+
+    let update_player = Update::builder()
+        .table_name(table_name())
+        .set_key(Some(player.get_key()))
         .expression_attribute_names("#name", "name")
-        .expression_attribute_values(":name", to_attribute_value(new_name).unwrap())
-        .expression_attribute_values(":secret", AttributeValue::S(secret))
-        .condition_expression(format!("attribute_exists({PK}) AND secret = :secret"))
+        .expression_attribute_values(":old_name", AttributeValue::S(player.name.clone()))
+        .expression_attribute_values(":name", AttributeValue::S(new_name.display.clone()))
+        .expression_attribute_names("#stored_secret", stored_secret.attribute_name())
+        .expression_attribute_values(":stored_secret", stored_secret.to_attribute_value())
+        .expression_attribute_names("#banned", BANNED_ATTRIBUTE)
+        .condition_expression(format!(
+            "attribute_exists({PK}) AND #name = :old_name AND #stored_secret = :stored_secret \
+            AND attribute_not_exists(#banned)"
+        ))
+        .return_values_on_condition_check_failure(ReturnValuesOnConditionCheckFailure::AllOld);
+    let update_player = match new_secret_hash {
+        // Migrate the plaintext secret to its hash
+        Some(new_secret_hash) => update_player
+            .update_expression("SET #name = :name, #secret_hash = :secret_hash REMOVE #secret")
+            .expression_attribute_names("#secret_hash", HASH_ATTRIBUTE)
+            .expression_attribute_names("#secret", PLAINTEXT_ATTRIBUTE)
+            .expression_attribute_values(":secret_hash", AttributeValue::S(new_secret_hash)),
+        None => update_player.update_expression("SET #name = :name"),
+    }
+    .build()
+    .expect("table, key and update expression are set");
+    let reserve_name = player_name_reservation(&player, &new_name.normalized);
+
+    let old_normalized = normalize(&player.name);
+    // A name only differing by case or compatibility variants keeps the same reservation
+    let release_name = (old_normalized != new_name.normalized)
+        .then(|| player_name_release(&player, &old_normalized));
+
+    let result = dynamodb_transact_releasing_name(
+        vec![
+            TransactWriteItem::builder().update(update_player).build(),
+            TransactWriteItem::builder().put(reserve_name).build(),
+        ],
+        release_name,
+    )
+    .await;
+    match result {
+        Ok(_) => Ok(PlayerRename::Renamed(Player {
+            name: new_name.display,
+            ..player
+        })),
+        Err(aws_sdk_dynamodb::Error::TransactionCanceledException(e))
+            if is_conditional_check_failed(&e, 0) =>
+        {
+            if is_banned(condition_check_item(&e, 0)) {
+                Ok(PlayerRename::Banned)
+            } else {
+                Ok(PlayerRename::PlayerChanged)
+            }
+        }
+        Err(aws_sdk_dynamodb::Error::TransactionCanceledException(e))
+            if is_conditional_check_failed(&e, 1) =>
+        {
+            Ok(PlayerRename::NameTaken)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Outcome of [dynamodb_set_player_secret]
+pub enum SecretUpdate {
+    /// The player now has the new secret
+    Updated(Player),
+    /// The player was banned since their secret was verified
+    Banned,
+    /// The player changed secret since it was verified
+    PlayerChanged,
+}
+
+/// Replaces the secret of a player by `new_secret`
+///
+/// If `stored_secret` is provided, the update is conditioned on it being unchanged since it
+/// was verified, and on the player not being banned. Else the player only needs to exist.
+pub async fn dynamodb_set_player_secret(
+    player_id: ID,
+    stored_secret: Option<&StoredSecret>,
+    new_secret: StoredSecret,
+) -> Result<SecretUpdate, aws_sdk_dynamodb::Error> {
+    log::debug!("ENTER dynamodb_set_player_secret - player_id={player_id}");
+
+    // The secret is stored in a single attribute, so the other one must go
+    let obsolete_attribute = match new_secret {
+        StoredSecret::Hashed(_) => PLAINTEXT_ATTRIBUTE,
+        StoredSecret::Plaintext(_) => HASH_ATTRIBUTE,
+    };
+    let update = dynamodb()
+        .update_item()
+        .table_name(table_name())
+        .set_key(Some(Player::get_key_from_id(player_id)))
+        .update_expression("SET #new_secret = :new_secret REMOVE #obsolete_secret")
+        .expression_attribute_names("#new_secret", new_secret.attribute_name())
+        .expression_attribute_values(":new_secret", new_secret.to_attribute_value())
+        .expression_attribute_names("#obsolete_secret", obsolete_attribute);
+    let update = match stored_secret {
+        Some(stored_secret) => update
+            .expression_attribute_names("#stored_secret", stored_secret.attribute_name())
+            .expression_attribute_values(":stored_secret", stored_secret.to_attribute_value())
+            .expression_attribute_names("#banned", BANNED_ATTRIBUTE)
+            .condition_expression(format!(
+                "attribute_exists({PK}) AND #stored_secret = :stored_secret \
+                AND attribute_not_exists(#banned)"
+            ))
+            .return_values_on_condition_check_failure(ReturnValuesOnConditionCheckFailure::AllOld),
+        None => update.condition_expression(format!("attribute_exists({PK})")),
+    };
+
+    match update
         .return_values(ReturnValue::AllNew)
         .send()
-        .await?
-        .attributes
-        .map(Player::from_item)
-        .expect("asked for them"))
+        .await
+        .map_err(aws_sdk_dynamodb::Error::from)
+    {
+        Ok(output) => Ok(SecretUpdate::Updated(
+            output
+                .attributes
+                .map(Player::from_item)
+                .expect("asked for them"),
+        )),
+        // A reset by an admin only fails if the player does not exist, which is an error
+        Err(aws_sdk_dynamodb::Error::ConditionalCheckFailedException(e))
+            if stored_secret.is_some() =>
+        {
+            if is_banned(e.item()) {
+                Ok(SecretUpdate::Banned)
+            } else {
+                Ok(SecretUpdate::PlayerChanged)
+            }
+        }
+        Err(e) => Err(e),
+    }
 }
 
-/// Deletes a player record from DynamoDB
+/// Deletes a player record from DynamoDB, decrements the player counter of their team and
+/// releases their name
+///
+/// All the writes happen in a single transaction, conditioned on the player still being in the
+/// team and having the name they had when read.
 ///
-/// Returns the deleted [Player] if it existed
+/// Returns the deleted [Player] if it existed in the game `game_id`
 pub async fn dynamodb_delete_player(
+    game_id: ID,
     player_id: ID,
 ) -> Result<Option<Player>, aws_sdk_dynamodb::Error> {
-    log::debug!("ENTER dynamodb_delete_player - player_id={player_id}");
+    log::debug!("ENTER dynamodb_delete_player - game_id={game_id} player_id={player_id}");
 
-    Ok(
-        dynamodb_delete_item(dynamodb(), Player::get_key_from_id(player_id))
-            .await?
-            .map(Player::from_item),
+    let Some((player, _)) = dynamodb_get_player_with_secret(player_id)
+        .await?
+        .filter(|(player, _)| player.game_id == game_id)
+    else {
+        return Ok(None);
+    };
+
+    let delete_player = Delete::builder()
+        .table_name(table_name())
+        .set_key(Some(Player::get_key_from_id(player_id)))
+        .condition_expression(format!(
+            "attribute_exists({PK}) AND team = :team AND #name = :name"
+        ))
+        .expression_attribute_values(":team", AttributeValue::S(player.team.clone()))
+        .expression_attribute_names("#name", "name")
+        .expression_attribute_values(":name", AttributeValue::S(player.name.clone()))
+        .build()
+        .expect("table and key are set");
+    let decrement_counter = team_counter_update(player.game_id, &player.team, -1, None);
+    let release_name = player_name_release(&player, &normalize(&player.name));
+
+    dynamodb_transact_releasing_name(
+        vec![
+            TransactWriteItem::builder().delete(delete_player).build(),
+            TransactWriteItem::builder()
+                .update(decrement_counter)
+                .build(),
+        ],
+        Some(release_name),
     )
+    .await?;
+
+    Ok(Some(player))
+}
+
+/// DynamoDB storage interface for [Ban] records
+impl Ban {
+    /// Partition key prefix for Ban items
+    const PK_TYPE: &'static str = "BAN";
+
+    /// Generates the partition key for the ban of a player
+    fn pk_from_uuid(player_id: ID) -> String {
+        format!("{}#{}", Self::PK_TYPE, player_id)
+    }
+}
+
+/// DynamoDB table interface implementation for Ban
+impl DynamoDBItem for Ban {
+    type Id = ID;
+
+    fn get_key(&self) -> DynamoItem {
+        Self::get_key_from_id(self.player_id)
+    }
+    fn get_key_from_id(player_id: Self::Id) -> DynamoItem {
+        HashMap::from([(
+            PK.to_owned(),
+            AttributeValue::S(Self::pk_from_uuid(player_id)),
+        )])
+    }
+    fn get_type() -> &'static str {
+        Self::PK_TYPE
+    }
+}
+
+/// Partition key prefix for the items listing the banned players of a game who registered with
+/// a given secret
+const BANNED_SECRET_PK_TYPE: &str = "BANNED_SECRET";
+/// Partition key prefix for the items listing the banned players of a game
+const GAME_BANS_PK_TYPE: &str = "BANS";
+/// Name of the attribute of a banned secret or game bans item holding the IDs of the banned
+/// players
+const BANNED_PLAYERS_PROPERTY_NAME: &str = "player_ids";
+
+/// Generates the key of the item listing the banned players of a game who registered with the
+/// secret of digest `secret_digest`
+fn banned_secret_key(game_id: ID, secret_digest: &str) -> DynamoItem {
+    HashMap::from([(
+        PK.to_owned(),
+        AttributeValue::S(format!("{BANNED_SECRET_PK_TYPE}#{game_id}#{secret_digest}")),
+    )])
+}
+
+/// Generates the key of the item listing the banned players of a game
+fn game_bans_key(game_id: ID) -> DynamoItem {
+    HashMap::from([(
+        PK.to_owned(),
+        AttributeValue::S(format!("{GAME_BANS_PK_TYPE}#{game_id}")),
+    )])
 }
 
-/// Queries DynamoDB to get a count of players per team
+/// Builds the update adding (`ADD`) or removing (`DELETE`) the player `player_id` to the banned
+/// players listed by the item of `key` and type `pk_type`
+fn banned_players_update(key: DynamoItem, pk_type: &str, action: &str, player_id: ID) -> Update {
+    Update::builder()
+        .table_name(table_name())
+        .set_key(Some(key))
+        .update_expression(format!(
+            "SET #type = :type {action} #player_ids :player_ids"
+        ))
+        .expression_attribute_names("#type", TYPE)
+        .expression_attribute_names("#player_ids", BANNED_PLAYERS_PROPERTY_NAME)
+        .expression_attribute_values(":type", AttributeValue::S(pk_type.to_owned()))
+        .expression_attribute_values(
+            ":player_ids",
+            AttributeValue::Ss(vec![player_id.to_string()]),
+        )
+        .build()
+        .expect("table, key and update expression are set")
+}
+
+/// Builds the updates adding (`ADD`) or removing (`DELETE`) the player `player_id` to the banned
+/// players of their game, and to those who registered with the secret of digest `secret_digest`
+/// if known
+fn ban_updates(
+    game_id: ID,
+    secret_digest: Option<&str>,
+    action: &str,
+    player_id: ID,
+) -> Vec<TransactWriteItem> {
+    let game_bans =
+        banned_players_update(game_bans_key(game_id), GAME_BANS_PK_TYPE, action, player_id);
+    let banned_secret = secret_digest.map(|secret_digest| {
+        banned_players_update(
+            banned_secret_key(game_id, secret_digest),
+            BANNED_SECRET_PK_TYPE,
+            action,
+            player_id,
+        )
+    });
+    std::iter::once(game_bans)
+        .chain(banned_secret)
+        .map(|update| TransactWriteItem::builder().update(update).build())
+        .collect()
+}
+
+/// Bans a player: marks their item as banned, which stops their clicks, and records the [Ban]
+/// along with the digest of the secret they registered with
+///
+/// All the writes happen in a single transaction, conditioned on the player still existing.
+/// Banning a banned player replaces their ban.
 ///
-/// Returns a vector of ([Team], count) tuples
-pub async fn dynamodb_query_teams_player_count()
--> Result<Vec<(Team, usize)>, aws_sdk_dynamodb::Error> {
-    log::debug!("ENTER dynamodb_query_teams_player_count");
+/// Returns [None] if the player does not exist
+pub async fn dynamodb_ban_player(
+    player_id: ID,
+    reason: String,
+    banned_at: AWSTimestamp,
+) -> Result<Option<Ban>, aws_sdk_dynamodb::Error> {
+    log::debug!("ENTER dynamodb_ban_player - player_id={player_id} reason={reason}");
 
-    let scan_req_builder = dynamodb()
-        .scan()
+    let Some(player_item) = dynamodb()
+        .get_item()
+        .table_name(table_name())
+        .set_key(Some(Player::get_key_from_id(player_id)))
+        .send()
+        .await?
+        .item
+    else {
+        return Ok(None);
+    };
+    // Players registered before bans were introduced have no digest, and are only marked
+    let secret_digest = player_item.get(SECRET_DIGEST_ATTRIBUTE).cloned();
+    let player = Player::from_item(player_item);
+
+    let ban = Ban {
+        player_id,
+        game_id: player.game_id,
+        name: player.name,
+        reason,
+        banned_at,
+    };
+
+    let mark_player = Update::builder()
+        .table_name(table_name())
+        .set_key(Some(Player::get_key_from_id(player_id)))
+        .update_expression("SET #banned = :banned")
+        .expression_attribute_names("#banned", BANNED_ATTRIBUTE)
+        .expression_attribute_values(":banned", AttributeValue::Bool(true))
+        .condition_expression(format!("attribute_exists({PK})"))
+        .build()
+        .expect("table, key and update expression are set");
+    let mut ban_item = ban.to_item();
+    let ban_updates = ban_updates(
+        ban.game_id,
+        secret_digest
+            .as_ref()
+            .map(|digest| digest.as_s().expect("valid schema").as_str()),
+        "ADD",
+        player_id,
+    );
+    if let Some(secret_digest) = secret_digest {
+        // Keep the digest to clear it when the player is unbanned
+        ban_item.insert(SECRET_DIGEST_ATTRIBUTE.to_owned(), secret_digest);
+    }
+    let put_ban = Put::builder()
+        .table_name(table_name())
+        .set_item(Some(ban_item))
+        .build()
+        .expect("table and item are set");
+
+    let mut transaction = dynamodb()
+        .transact_write_items()
+        .transact_items(TransactWriteItem::builder().update(mark_player).build())
+        .transact_items(TransactWriteItem::builder().put(put_ban).build());
+    for update in ban_updates {
+        transaction = transaction.transact_items(update);
+    }
+    transaction.send().await?;
+    Ok(Some(ban))
+}
+
+/// Lifts the ban of a player, if they still exist, and forgets the digest of the secret they
+/// registered with
+///
+/// All the writes happen in a single transaction, conditioned on the ban still existing.
+///
+/// Returns the lifted [Ban], [None] if the player was not banned
+pub async fn dynamodb_unban_player(player_id: ID) -> Result<Option<Ban>, aws_sdk_dynamodb::Error> {
+    log::debug!("ENTER dynamodb_unban_player - player_id={player_id}");
+
+    let Some(ban_item) = dynamodb()
+        .get_item()
+        .table_name(table_name())
+        .set_key(Some(Ban::get_key_from_id(player_id)))
+        .send()
+        .await?
+        .item
+    else {
+        return Ok(None);
+    };
+    let secret_digest = ban_item
+        .get(SECRET_DIGEST_ATTRIBUTE)
+        .map(|digest| digest.as_s().expect("valid schema").to_owned());
+    let ban = Ban::from_item(ban_item);
+
+    let delete_ban = Delete::builder()
+        .table_name(table_name())
+        .set_key(Some(ban.get_key()))
+        .condition_expression(format!("attribute_exists({PK})"))
+        .build()
+        .expect("table and key are set");
+    let mut transaction = dynamodb()
+        .transact_write_items()
+        .transact_items(TransactWriteItem::builder().delete(delete_ban).build());
+    for update in ban_updates(ban.game_id, secret_digest.as_deref(), "DELETE", player_id) {
+        transaction = transaction.transact_items(update);
+    }
+    // The player may have been removed since they were banned
+    let player_exists = dynamodb_get_player_with_secret(player_id).await?.is_some();
+    if player_exists {
+        let unmark_player = Update::builder()
+            .table_name(table_name())
+            .set_key(Some(Player::get_key_from_id(player_id)))
+            .update_expression("REMOVE #banned")
+            .expression_attribute_names("#banned", BANNED_ATTRIBUTE)
+            .condition_expression(format!("attribute_exists({PK})"))
+            .build()
+            .expect("table, key and update expression are set");
+        transaction =
+            transaction.transact_items(TransactWriteItem::builder().update(unmark_player).build());
+    }
+
+    transaction.send().await?;
+    Ok(Some(ban))
+}
+
+/// Retrieves all the [Ban] of a game from DynamoDB, most recent first
+///
+/// The bans are read by key, from the list of the banned players of the game
+pub async fn dynamodb_query_bans(game_id: ID) -> Result<Vec<Ban>, aws_sdk_dynamodb::Error> {
+    log::debug!("ENTER dynamodb_query_bans - game_id={game_id}");
+
+    // The list of banned players is removed with the last of them
+    let player_ids = dynamodb()
+        .get_item()
+        .table_name(table_name())
+        .set_key(Some(game_bans_key(game_id)))
+        .send()
+        .await?
+        .item
+        .and_then(|mut item| item.remove(BANNED_PLAYERS_PROPERTY_NAME))
+        .map(|player_ids| from_attribute_value::<_, Vec<ID>>(player_ids).expect("valid schema"))
+        .unwrap_or_default();
+
+    let mut bans =
+        dynamodb_batch_get_items(player_ids.into_iter().map(Ban::get_key_from_id).collect())
+            .await?
+            .into_iter()
+            .map(Ban::from_item)
+            .collect::<Vec<_>>();
+    bans.sort_by_key(|ban| Reverse(ban.banned_at));
+    Ok(bans)
+}
+
+/// Returns whether a banned player of the game registered with the secret of digest
+/// `secret_digest`
+pub async fn dynamodb_is_secret_banned(
+    game_id: ID,
+    secret_digest: &str,
+) -> Result<bool, aws_sdk_dynamodb::Error> {
+    log::debug!("ENTER dynamodb_is_secret_banned - game_id={game_id}");
+
+    // The set of banned players is removed with the last of them
+    Ok(dynamodb()
+        .get_item()
+        .table_name(table_name())
+        .set_key(Some(banned_secret_key(game_id, secret_digest)))
+        .consistent_read(true)
+        .send()
+        .await?
+        .item
+        .is_some_and(|item| item.contains_key(BANNED_PLAYERS_PROPERTY_NAME)))
+}
+
+/// Partition key prefix for the items reserving the player names of a game
+const PLAYER_NAME_PK_TYPE: &str = "NAME";
+
+/// Generates the key of the item reserving the `normalized` player name in a game
+fn player_name_key(game_id: ID, normalized: &str) -> DynamoItem {
+    HashMap::from([(
+        PK.to_owned(),
+        AttributeValue::S(format!("{PLAYER_NAME_PK_TYPE}#{game_id}#{normalized}")),
+    )])
+}
+
+/// Builds the put reserving the `normalized` name for `player`
+///
+/// The put is conditioned on the name not being reserved by another player.
+fn player_name_reservation(player: &Player, normalized: &str) -> Put {
+    let mut item = player_name_key(player.game_id, normalized);
+    item.insert(
+        TYPE.to_owned(),
+        AttributeValue::S(PLAYER_NAME_PK_TYPE.to_owned()),
+    );
+    item.insert(
+        "game_id".to_owned(),
+        to_attribute_value(player.game_id).unwrap(),
+    );
+    item.insert(
+        "player_id".to_owned(),
+        to_attribute_value(player.id).unwrap(),
+    );
+    Put::builder()
+        .table_name(table_name())
+        .set_item(Some(item))
+        .condition_expression(format!(
+            "attribute_not_exists({PK}) OR player_id = :player_id"
+        ))
+        .expression_attribute_values(":player_id", to_attribute_value(player.id).unwrap())
+        .build()
+        .expect("table and item are set")
+}
+
+/// Builds the delete releasing the `normalized` name of `player`
+///
+/// The delete is conditioned on the name not being reserved by another player.
+fn player_name_release(player: &Player, normalized: &str) -> Delete {
+    Delete::builder()
+        .table_name(table_name())
+        .set_key(Some(player_name_key(player.game_id, normalized)))
+        .condition_expression(format!(
+            "attribute_not_exists({PK}) OR player_id = :player_id"
+        ))
+        .expression_attribute_values(":player_id", to_attribute_value(player.id).unwrap())
+        .build()
+        .expect("table and key are set")
+}
+
+/// Writes `items` in a single transaction, along with the release of a player name if any
+///
+/// Players registered before names were reserved do not own a reservation for their name, which
+/// may have been reserved by another player since. In that case the reservation is kept, and the
+/// transaction is written again without the release.
+async fn dynamodb_transact_releasing_name(
+    items: Vec<TransactWriteItem>,
+    release_name: Option<Delete>,
+) -> Result<(), aws_sdk_dynamodb::Error> {
+    let release_index = items.len();
+    let transaction = dynamodb()
+        .transact_write_items()
+        .set_transact_items(Some(items.clone()));
+    let result = match release_name {
+        Some(release_name) => {
+            transaction
+                .transact_items(TransactWriteItem::builder().delete(release_name).build())
+                .send()
+                .await
+        }
+        None => transaction.send().await,
+    };
+    match result.map_err(aws_sdk_dynamodb::Error::from) {
+        Ok(_) => Ok(()),
+        Err(aws_sdk_dynamodb::Error::TransactionCanceledException(e))
+            if is_conditional_check_failed(&e, release_index) =>
+        {
+            log::info!("Player name reserved by another player, keeping the reservation");
+            dynamodb()
+                .transact_write_items()
+                .set_transact_items(Some(items))
+                .send()
+                .await?;
+            Ok(())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Outcome of [dynamodb_move_player]
+pub enum PlayerMove {
+    /// The player is now in the new team
+    Moved(Player),
+    /// The game status does not allow players to change team
+    InvalidGameStatus,
+    /// The player was banned since they were read
+    Banned,
+    /// The player changed team or secret since they were read
+    PlayerChanged,
+}
+
+/// Moves a player to `new_team`, and updates the player counters of both teams
+///
+/// All the writes happen in a single transaction, conditioned on the game status being one of
+/// [GameStatus::TEAM_CHANGE_STATUSES], and on the player still having the team and
+/// `stored_secret` they had when read, and not being banned. If `new_secret` is provided, it replaces the stored secret
+/// (the new team may not store secrets the same way).
+pub async fn dynamodb_move_player(
+    player: Player,
+    stored_secret: &StoredSecret,
+    new_team: String,
+    new_secret: Option<StoredSecret>,
+) -> Result<PlayerMove, aws_sdk_dynamodb::Error> {
+    log::debug!(
+        "ENTER dynamodb_move_player - player_id={} new_team={new_team}",
+        player.id
+    );
+
+    let placeholders = (0..GameStatus::TEAM_CHANGE_STATUSES.len())
+        .map(|i| format!(":game_status{i}"))
+        .collect::<Vec<_>>();
+    let mut check_game_status = ConditionCheck::builder()
+        .table_name(table_name())
+        .set_key(Some(GameState::get_key_from_id(player.game_id)))
+        .condition_expression(format!(
+            "{} IN ({})",
+            GameState::STATUS_PROPERTY_NAME,
+            placeholders.join(", ")
+        ));
+    for (placeholder, status) in placeholders
+        .into_iter()
+        .zip(GameStatus::TEAM_CHANGE_STATUSES)
+    {
+        check_game_status = check_game_status
+            .expression_attribute_values(placeholder, to_attribute_value(status).unwrap());
+    }
+    let check_game_status = check_game_status
+        .build()
+        .expect("table, key and condition are set");
+
+    let update_player = Update::builder()
+        .table_name(table_name())
+        .set_key(Some(player.get_key()))
+        .expression_attribute_values(":old_team", AttributeValue::S(player.team.clone()))
+        .expression_attribute_values(":new_team", AttributeValue::S(new_team.clone()))
+        .expression_attribute_names("#stored_secret", stored_secret.attribute_name())
+        .expression_attribute_values(":stored_secret", stored_secret.to_attribute_value())
+        .expression_attribute_names("#banned", BANNED_ATTRIBUTE)
+        .condition_expression(format!(
+            "attribute_exists({PK}) AND team = :old_team AND #stored_secret = :stored_secret \
+            AND attribute_not_exists(#banned)"
+        ))
+        .return_values_on_condition_check_failure(ReturnValuesOnConditionCheckFailure::AllOld);
+    let update_player = match new_secret {
+        Some(new_secret) => {
+            // The secret is stored in a single attribute, so the other one must go
+            let obsolete_attribute = match new_secret {
+                StoredSecret::Hashed(_) => PLAINTEXT_ATTRIBUTE,
+                StoredSecret::Plaintext(_) => HASH_ATTRIBUTE,
+            };
+            update_player
+                .update_expression(
+                    "SET team = :new_team, #new_secret = :new_secret REMOVE #obsolete_secret",
+                )
+                .expression_attribute_names("#new_secret", new_secret.attribute_name())
+                .expression_attribute_values(":new_secret", new_secret.to_attribute_value())
+                .expression_attribute_names("#obsolete_secret", obsolete_attribute)
+        }
+        None => update_player.update_expression("SET team = :new_team"),
+    }
+    .build()
+    .expect("table, key and update expression are set");
+
+    let decrement_counter = team_counter_update(player.game_id, &player.team, -1, None);
+    let increment_counter = team_counter_update(player.game_id, &new_team, 1, None);
+
+    let result = dynamodb()
+        .transact_write_items()
+        .transact_items(
+            TransactWriteItem::builder()
+                .condition_check(check_game_status)
+                .build(),
+        )
+        .transact_items(TransactWriteItem::builder().update(update_player).build())
+        .transact_items(
+            TransactWriteItem::builder()
+                .update(decrement_counter)
+                .build(),
+        )
+        .transact_items(
+            TransactWriteItem::builder()
+                .update(increment_counter)
+                .build(),
+        )
+        .send()
+        .await;
+    match result {
+        Ok(_) => Ok(PlayerMove::Moved(Player {
+            team: new_team,
+            ..player
+        })),
+        Err(e) => match aws_sdk_dynamodb::Error::from(e) {
+            aws_sdk_dynamodb::Error::TransactionCanceledException(e)
+                if is_conditional_check_failed(&e, 0) =>
+            {
+                Ok(PlayerMove::InvalidGameStatus)
+            }
+            aws_sdk_dynamodb::Error::TransactionCanceledException(e)
+                if is_conditional_check_failed(&e, 1) =>
+            {
+                if is_banned(condition_check_item(&e, 1)) {
+                    Ok(PlayerMove::Banned)
+                } else {
+                    Ok(PlayerMove::PlayerChanged)
+                }
+            }
+            e => Err(e),
+        },
+    }
+}
+
+/// Partition key prefix for the items counting the players of each team of a game
+const TEAM_COUNTER_PK_TYPE: &str = "TEAM_COUNT";
+/// Name of the attribute of a team counter item holding the number of players
+const TEAM_COUNTER_PROPERTY_NAME: &str = "players";
+
+/// Largest number of keys of a BatchGetItem request
+const MAX_BATCH_GET_KEYS: usize = 100;
+
+/// Generates the key of the item counting the players of the team `team_id` in a game
+fn team_counter_key(game_id: ID, team_id: &str) -> DynamoItem {
+    HashMap::from([(
+        PK.to_owned(),
+        AttributeValue::S(format!("{TEAM_COUNTER_PK_TYPE}#{game_id}#{team_id}")),
+    )])
+}
+
+/// Builds the update adding `delta` to the player counter of the team `team_id` in a game
+///
+/// If `expected` is provided, the update is conditioned on the counter still having this value,
+/// [None] meaning the counter does not exist yet.
+fn team_counter_update(
+    game_id: ID,
+    team_id: &str,
+    delta: i64,
+    expected: Option<Option<i64>>,
+) -> Update {
+    let update = Update::builder()
         .table_name(table_name())
-        .filter_expression("#type = :player_type")
-        .projection_expression(format!("{PK},#type,team"))
+        .set_key(Some(team_counter_key(game_id, team_id)))
+        .update_expression(
+            "SET #type = :counter_type, game_id = :game_id, team = :team ADD #players :delta",
+        )
         .expression_attribute_names("#type", TYPE)
+        .expression_attribute_names("#players", TEAM_COUNTER_PROPERTY_NAME)
         .expression_attribute_values(
-            ":player_type",
-            AttributeValue::S(Player::get_type().to_owned()),
+            ":counter_type",
+            AttributeValue::S(TEAM_COUNTER_PK_TYPE.to_owned()),
+        )
+        .expression_attribute_values(":game_id", to_attribute_value(game_id).unwrap())
+        .expression_attribute_values(":team", AttributeValue::S(team_id.to_owned()))
+        .expression_attribute_values(":delta", to_attribute_value(delta).unwrap());
+    let update = match expected {
+        Some(Some(expected)) => update
+            .condition_expression("#players = :expected")
+            .expression_attribute_values(":expected", to_attribute_value(expected).unwrap()),
+        Some(None) => update.condition_expression("attribute_not_exists(#players)"),
+        None => update,
+    };
+    update
+        .build()
+        .expect("table, key and update expression are set")
+}
+
+/// Reads the player counters of the `teams` of a game
+///
+/// Returns the value of each existing counter, by team ID. The value can be negative for games
+/// which had players before the counters were introduced, as removing them decrements a missing
+/// counter, until they are backfilled with `utils/backfill_team_counters.py`.
+pub async fn dynamodb_get_team_counters(
+    game_id: ID,
+    teams: &[Team],
+) -> Result<HashMap<String, i64>, aws_sdk_dynamodb::Error> {
+    log::debug!("ENTER dynamodb_get_team_counters - game_id={game_id}");
+
+    let mut counters = HashMap::new();
+    for teams in teams.chunks(MAX_BATCH_GET_KEYS) {
+        let mut keys_and_attributes = Some(
+            teams
+                .iter()
+                .fold(KeysAndAttributes::builder(), |builder, team| {
+                    builder.keys(team_counter_key(game_id, &team.id))
+                })
+                // Strongly consistent, so the conditional increment has a chance to succeed
+                .consistent_read(true)
+                .build()
+                .expect("keys are set"),
         );
+        while let Some(request) = keys_and_attributes.take() {
+            let output = dynamodb()
+                .batch_get_item()
+                .request_items(table_name(), request)
+                .send()
+                .await?;
+            for mut item in output
+                .responses
+                .and_then(|mut responses| responses.remove(&table_name()))
+                .unwrap_or_default()
+            {
+                let team_id =
+                    from_attribute_value::<_, String>(item.remove("team").expect("valid schema"))
+                        .expect("valid schema");
+                let players = from_attribute_value::<_, i64>(
+                    item.remove(TEAM_COUNTER_PROPERTY_NAME)
+                        .expect("valid schema"),
+                )
+                .expect("valid schema");
+                counters.insert(team_id, players);
+            }
+            // Retry the keys DynamoDB did not process
+            keys_and_attributes = output
+                .unprocessed_keys
+                .and_then(|mut unprocessed| unprocessed.remove(&table_name()));
+        }
+    }
 
-    let items = dynamodb_perform_scan(scan_req_builder).await?;
+    Ok(counters)
+}
 
-    let teams = items.into_iter().map(|mut item| {
-        from_attribute_value::<_, Team>(item.remove("team").expect("invalid schema"))
-            .expect("invalid schema")
-    });
-    let mut counts = HashMap::new();
-    for team in teams {
-        *counts.entry(team).or_insert(0usize) += 1;
+/// DynamoDB storage interface for Team records
+impl Team {
+    /// Partition key prefix for Team items
+    const PK_TYPE: &'static str = "TEAM";
+
+    /// Generates the partition key for a team ID
+    fn pk_from_id(id: &str) -> String {
+        format!("{}#{}", Self::PK_TYPE, id)
+    }
+}
+
+/// DynamoDB table interface implementation for Team
+impl DynamoDBItem for Team {
+    type Id = String;
+
+    fn get_key(&self) -> DynamoItem {
+        Self::get_key_from_id(self.id.clone())
+    }
+    fn get_key_from_id(id: Self::Id) -> DynamoItem {
+        HashMap::from([(PK.to_owned(), AttributeValue::S(Self::pk_from_id(&id)))])
+    }
+    fn get_type() -> &'static str {
+        Self::PK_TYPE
     }
+}
+
+/// Partition key of the item listing the IDs of the stored teams, so they can be read without
+/// scanning the table
+const TEAM_IDS_PK: &str = "TEAM_IDS";
+/// Name of the attribute of the [TEAM_IDS_PK] item holding the set of team IDs
+const TEAM_IDS_PROPERTY_NAME: &str = "team_ids";
+
+/// Retrieves all the [Team], ordered by ID
+///
+/// The [Team::builtin] teams are always included, unless a stored team replaces them.
+pub async fn dynamodb_query_teams() -> Result<Vec<Team>, aws_sdk_dynamodb::Error> {
+    log::debug!("ENTER dynamodb_query_teams");
+
+    let team_ids = dynamodb()
+        .get_item()
+        .table_name(table_name())
+        .key(PK, AttributeValue::S(TEAM_IDS_PK.to_owned()))
+        .send()
+        .await?
+        .item
+        .and_then(|mut item| item.remove(TEAM_IDS_PROPERTY_NAME))
+        .map(|team_ids| from_attribute_value::<_, Vec<String>>(team_ids).expect("valid schema"))
+        .unwrap_or_default();
 
-    Ok(counts
+    let mut teams = Team::builtin()
         .into_iter()
-        .map(|(team, count)| (team, count))
-        .collect())
+        .map(|team| (team.id.clone(), team))
+        .collect::<HashMap<_, _>>();
+    for team_ids in team_ids.chunks(MAX_BATCH_GET_KEYS) {
+        let mut keys_and_attributes = Some(
+            team_ids
+                .iter()
+                .fold(KeysAndAttributes::builder(), |builder, team_id| {
+                    builder.keys(Team::get_key_from_id(team_id.clone()))
+                })
+                .build()
+                .expect("keys are set"),
+        );
+        while let Some(request) = keys_and_attributes.take() {
+            let output = dynamodb()
+                .batch_get_item()
+                .request_items(table_name(), request)
+                .send()
+                .await?;
+            for item in output
+                .responses
+                .and_then(|mut responses| responses.remove(&table_name()))
+                .unwrap_or_default()
+            {
+                let team = Team::from_item(item);
+                teams.insert(team.id.clone(), team);
+            }
+            // Retry the keys DynamoDB did not process
+            keys_and_attributes = output
+                .unprocessed_keys
+                .and_then(|mut unprocessed| unprocessed.remove(&table_name()));
+        }
+    }
+    let mut teams = teams.into_values().collect::<Vec<_>>();
+    teams.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(teams)
+}
+
+/// Retrieves a [Team] from DynamoDB
+///
+/// A [Team::builtin] team that was never stored is returned as is, so its players keep being
+/// served once other teams are created.
+///
+/// Returns [None] if the team does not exist
+pub async fn dynamodb_get_team(team_id: String) -> Result<Option<Team>, aws_sdk_dynamodb::Error> {
+    log::debug!("ENTER dynamodb_get_team - team_id={team_id}");
+
+    let team = dynamodb()
+        .get_item()
+        .table_name(table_name())
+        .set_key(Some(Team::get_key_from_id(team_id.clone())))
+        .send()
+        .await?
+        .item
+        .map(Team::from_item);
+    Ok(team.or_else(|| Team::builtin().into_iter().find(|team| team.id == team_id)))
+}
+
+/// Creates or replaces a [Team] in DynamoDB, and adds its ID to the [TEAM_IDS_PK] item
+pub async fn dynamodb_put_team(team: &Team) -> Result<(), aws_sdk_dynamodb::Error> {
+    log::debug!("ENTER dynamodb_put_team - team={team:?}");
+
+    let put_team = Put::builder()
+        .table_name(table_name())
+        .set_item(Some(team.to_item()))
+        .build()
+        .expect("table and item are set");
+    let add_team_id = Update::builder()
+        .table_name(table_name())
+        .key(PK, AttributeValue::S(TEAM_IDS_PK.to_owned()))
+        .update_expression("SET #type = :type ADD #team_ids :team_id")
+        .expression_attribute_names("#type", TYPE)
+        .expression_attribute_values(":type", AttributeValue::S(TEAM_IDS_PK.to_owned()))
+        .expression_attribute_names("#team_ids", TEAM_IDS_PROPERTY_NAME)
+        .expression_attribute_values(":team_id", AttributeValue::Ss(vec![team.id.clone()]))
+        .build()
+        .expect("table, key and update expression are set");
+    dynamodb()
+        .transact_write_items()
+        .transact_items(TransactWriteItem::builder().put(put_team).build())
+        .transact_items(TransactWriteItem::builder().update(add_team_id).build())
+        .send()
+        .await?;
+    Ok(())
 }
 
-/// Retrieves all players from DynamoDB
+/// Retrieves all players of a game from DynamoDB
 ///
 /// Returns a vector of Player objects
-pub async fn dynamodb_query_players() -> Result<Vec<Player>, aws_sdk_dynamodb::Error> {
-    log::debug!("ENTER dynamodb_query_players");
+pub async fn dynamodb_query_players(game_id: ID) -> Result<Vec<Player>, aws_sdk_dynamodb::Error> {
+    log::debug!("ENTER dynamodb_query_players - game_id={game_id}");
 
     // List players as DynamoItem
-    let player_items = dynamodb_list_player_items().await?;
+    let player_items = dynamodb_list_player_items(game_id).await?;
     // Map to Player objects
     Ok(player_items.into_iter().map(Player::from_item).collect())
 }
 
-/// Retrieves the current game status from DynamoDB
+/// Retrieves all the players of a game from DynamoDB, along with the latency histogram of each
+/// team, by team ID
+///
+/// The histogram of a team is the sum of the histograms of its players, so that reports only
+/// ever update the item of the player. Teams without any latency report sent with a histogram
+/// are left out.
+pub async fn dynamodb_query_players_with_latency_histograms(
+    game_id: ID,
+) -> Result<(Vec<Player>, HashMap<String, LatencyHistogram>), aws_sdk_dynamodb::Error> {
+    log::debug!("ENTER dynamodb_query_players_with_latency_histograms - game_id={game_id}");
+
+    let mut latency_histograms = HashMap::<String, LatencyHistogram>::new();
+    let players = dynamodb_list_player_items(game_id)
+        .await?
+        .into_iter()
+        .map(|item| {
+            let (player, histogram) = Player::from_item_with_latency(item);
+            if !histogram.is_empty() {
+                latency_histograms
+                    .entry(player.team.clone())
+                    .or_default()
+                    .merge(&histogram);
+            }
+            player
+        })
+        .collect();
+    Ok((players, latency_histograms))
+}
+
+/// Retrieves the current state of a game from DynamoDB
+///
+/// Returns [None] if the game does not exist
+pub async fn dynamodb_get_game_state(
+    game_id: ID,
+) -> Result<Option<GameState>, aws_sdk_dynamodb::Error> {
+    log::debug!("ENTER dynamodb_get_game_state - game_id={game_id}");
+
+    Ok(dynamodb()
+        .get_item()
+        .table_name(table_name())
+        .set_key(Some(GameState::get_key_from_id(game_id)))
+        .send()
+        .await?
+        .item
+        .map(GameState::from_item))
+}
+
+/// Retrieves the current state of a game from DynamoDB, along with its version (0 if it was
+/// written before states were versioned)
 ///
-/// Returns the GameStatus enum value
-pub async fn dynamodb_get_game_status() -> Result<Option<GameStatus>, aws_sdk_dynamodb::Error> {
-    log::debug!("ENTER dynamodb_get_game_status");
+/// The read is strongly consistent, so the state can be copied to the players again with
+/// [dynamodb_set_players_clicks_open_at] when a previous copy failed. Returns [None] if the game
+/// does not exist.
+pub async fn dynamodb_get_versioned_game_state(
+    game_id: ID,
+) -> Result<Option<(GameState, u64)>, aws_sdk_dynamodb::Error> {
+    log::debug!("ENTER dynamodb_get_versioned_game_state - game_id={game_id}");
 
     Ok(dynamodb()
         .get_item()
         .table_name(table_name())
-        .set_key(Some(GameStatus::get_key_from_id(())))
+        .set_key(Some(GameState::get_key_from_id(game_id)))
+        .consistent_read(true)
         .send()
         .await?
         .item
-        .map(GameStatus::from_item))
+        .map(|item| {
+            let state_version = state_version_from_item(&item).unwrap_or_default();
+            (GameState::from_item(item), state_version)
+        }))
+}
+
+/// DynamoDB storage interface for [GameConfig] records
+impl GameConfig {
+    /// Partition key prefix for GameConfig items
+    const PK_TYPE: &'static str = "GAME_CONFIG";
+
+    /// Generates the partition key for the configuration of a game ID
+    fn pk_from_uuid(game_id: ID) -> String {
+        format!("{}#{}", Self::PK_TYPE, game_id)
+    }
+}
+
+/// DynamoDB table interface implementation for GameConfig
+impl DynamoDBItem for GameConfig {
+    type Id = ID;
+
+    fn get_key(&self) -> DynamoItem {
+        Self::get_key_from_id(self.game_id)
+    }
+    fn get_key_from_id(game_id: Self::Id) -> DynamoItem {
+        HashMap::from([(
+            PK.to_owned(),
+            AttributeValue::S(Self::pk_from_uuid(game_id)),
+        )])
+    }
+    fn get_type() -> &'static str {
+        Self::PK_TYPE
+    }
+}
+
+/// Retrieves the [GameConfig] of a game from DynamoDB
+///
+/// Returns [None] if the game was never configured
+pub async fn dynamodb_get_game_config(
+    game_id: ID,
+) -> Result<Option<GameConfig>, aws_sdk_dynamodb::Error> {
+    log::debug!("ENTER dynamodb_get_game_config - game_id={game_id}");
+
+    Ok(dynamodb()
+        .get_item()
+        .table_name(table_name())
+        .set_key(Some(GameConfig::get_key_from_id(game_id)))
+        .send()
+        .await?
+        .item
+        .map(GameConfig::from_item))
+}
+
+/// Creates or replaces the [GameConfig] of a game in DynamoDB
+pub async fn dynamodb_put_game_config(config: &GameConfig) -> Result<(), aws_sdk_dynamodb::Error> {
+    log::debug!("ENTER dynamodb_put_game_config - config={config:?}");
+
+    dynamodb()
+        .put_item()
+        .table_name(table_name())
+        .set_item(Some(config.to_item()))
+        .send()
+        .await?;
+    Ok(())
+}
+
+/// DynamoDB storage interface for [GameRound] records
+///
+/// A round is stored as a header item, holding everything but its teams and players, and one
+/// entry item for each of its teams and players, keyed by their position in the round. A round
+/// is thus not limited by the size of a DynamoDB item, however many players it has.
+impl GameRound {
+    /// Partition key prefix for GameRound items
+    const PK_TYPE: &'static str = "GAME_ROUND";
+    /// Partition key prefix for the team entries of GameRound items
+    const TEAM_PK_TYPE: &'static str = "GAME_ROUND_TEAM";
+    /// Partition key prefix for the player entries of GameRound items
+    const PLAYER_PK_TYPE: &'static str = "GAME_ROUND_PLAYER";
+    /// Name of the [Game] attribute storing the id of its last archived round
+    const COUNTER_PROPERTY_NAME: &'static str = "last_round";
+    /// Name of the [Game] attribute storing the version of the stopped state its last round was
+    /// archived from
+    const STATE_VERSION_PROPERTY_NAME: &'static str = "last_round_state_version";
+    /// Name of the [Game] attribute storing the time its last archived round was stopped at
+    const STOPPED_AT_PROPERTY_NAME: &'static str = "last_round_stopped_at";
+    /// Name of the attributes of a header item storing its number of teams and players
+    const TEAM_COUNT_PROPERTY_NAME: &'static str = "team_count";
+    const PLAYER_COUNT_PROPERTY_NAME: &'static str = "player_count";
+    /// Name of the attributes of an entry item storing its round and its position in the round
+    const ENTRY_ROUND_PROPERTY_NAME: &'static str = "round_id";
+    const ENTRY_INDEX_PROPERTY_NAME: &'static str = "index";
+
+    /// Generates the partition key for a round id of a game
+    fn pk_from_id(game_id: ID, id: i32) -> String {
+        format!("{}#{}#{}", Self::PK_TYPE, game_id, id)
+    }
+
+    /// Generates the key of the entry of type `pk_type` at `index` in a round of a game
+    fn entry_key(pk_type: &str, game_id: ID, id: i32, index: usize) -> DynamoItem {
+        HashMap::from([(
+            PK.to_owned(),
+            AttributeValue::S(format!("{pk_type}#{game_id}#{id}#{index}")),
+        )])
+    }
+
+    /// Splits the round into its header item and the entry items of its teams and players
+    fn to_items(&self) -> (DynamoItem, Vec<DynamoItem>) {
+        let mut header = self.to_item();
+        header.remove("teams");
+        header.remove("players");
+        header.insert(
+            Self::TEAM_COUNT_PROPERTY_NAME.to_owned(),
+            to_attribute_value(self.teams.len()).unwrap(),
+        );
+        header.insert(
+            Self::PLAYER_COUNT_PROPERTY_NAME.to_owned(),
+            to_attribute_value(self.players.len()).unwrap(),
+        );
+
+        let entry = |pk_type: &'static str, index: usize, mut item: DynamoItem| {
+            item.extend(Self::entry_key(pk_type, self.game_id, self.id, index));
+            item.insert(TYPE.to_owned(), AttributeValue::S(pk_type.to_owned()));
+            item.insert(
+                Self::ENTRY_ROUND_PROPERTY_NAME.to_owned(),
+                to_attribute_value(self.id).unwrap(),
+            );
+            item.insert(
+                Self::ENTRY_INDEX_PROPERTY_NAME.to_owned(),
+                to_attribute_value(index).unwrap(),
+            );
+            item
+        };
+        let teams = self.teams.iter().enumerate().map(|(index, team)| {
+            entry(
+                Self::TEAM_PK_TYPE,
+                index,
+                serde_dynamo::to_item(team).expect("valid schema"),
+            )
+        });
+        let players = self.players.iter().enumerate().map(|(index, player)| {
+            entry(
+                Self::PLAYER_PK_TYPE,
+                index,
+                serde_dynamo::to_item(player).expect("valid schema"),
+            )
+        });
+        (header, teams.chain(players).collect())
+    }
+
+    /// Returns the keys of the entries of the round stored in `header`
+    ///
+    /// Rounds archived as a single item, before entries were introduced, have none.
+    fn entry_keys(header: &DynamoItem) -> Vec<DynamoItem> {
+        let count = |name: &str| -> usize {
+            header
+                .get(name)
+                .map(|count| from_attribute_value(count.clone()).expect("valid schema"))
+                .unwrap_or_default()
+        };
+        let game_id = from_attribute_value(header["game_id"].clone()).expect("valid schema");
+        let id = from_attribute_value(header["id"].clone()).expect("valid schema");
+        (0..count(Self::TEAM_COUNT_PROPERTY_NAME))
+            .map(|index| Self::entry_key(Self::TEAM_PK_TYPE, game_id, id, index))
+            .chain(
+                (0..count(Self::PLAYER_COUNT_PROPERTY_NAME))
+                    .map(|index| Self::entry_key(Self::PLAYER_PK_TYPE, game_id, id, index)),
+            )
+            .collect()
+    }
+
+    /// Assembles a round from its header item and its entry items, in any order
+    ///
+    /// Rounds archived as a single item, before entries were introduced, are read as is.
+    fn from_items(mut header: DynamoItem, entries: Vec<DynamoItem>) -> Self {
+        if header.contains_key("players") {
+            return Self::from_item(header);
+        }
+        header.insert("teams".to_owned(), AttributeValue::L(vec![]));
+        header.insert("players".to_owned(), AttributeValue::L(vec![]));
+        let mut round = Self::from_item(header);
+
+        let mut entries = entries
+            .into_iter()
+            .map(|entry| {
+                let index: usize =
+                    from_attribute_value(entry[Self::ENTRY_INDEX_PROPERTY_NAME].clone())
+                        .expect("valid schema");
+                (index, entry)
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|(index, _)| *index);
+        for (_, entry) in entries {
+            if entry[TYPE].as_s().expect("valid schema") == Self::TEAM_PK_TYPE {
+                round
+                    .teams
+                    .push(serde_dynamo::from_item(entry).expect("valid schema"));
+            } else {
+                // The latency statistics were computed when the round was archived
+                round
+                    .players
+                    .push(serde_dynamo::from_item(entry).expect("valid schema"));
+            }
+        }
+        round
+    }
+}
+
+/// DynamoDB table interface implementation for GameRound
+impl DynamoDBItem for GameRound {
+    type Id = (ID, i32);
+
+    fn get_key(&self) -> DynamoItem {
+        Self::get_key_from_id((self.game_id, self.id))
+    }
+    fn get_key_from_id((game_id, id): Self::Id) -> DynamoItem {
+        HashMap::from([(
+            PK.to_owned(),
+            AttributeValue::S(Self::pk_from_id(game_id, id)),
+        )])
+    }
+    fn get_type() -> &'static str {
+        Self::PK_TYPE
+    }
+}
+
+/// Reserves the id of the round of a game that ended with the stopped state of version
+/// `state_version`, stopped at `stopped_at`
+///
+/// Archiving the same stopped state again, after an attempt that failed midway, returns the id
+/// and the stop time reserved by the first attempt, so each round is archived once.
+pub async fn dynamodb_reserve_round_id(
+    game_id: ID,
+    state_version: u64,
+    stopped_at: AWSTimestamp,
+) -> Result<(i32, AWSTimestamp), aws_sdk_dynamodb::Error> {
+    log::debug!(
+        "ENTER dynamodb_reserve_round_id - game_id={game_id} state_version={state_version}"
+    );
+
+    let reserved_round = |mut attributes: DynamoItem| {
+        let id = from_attribute_value(
+            attributes
+                .remove(GameRound::COUNTER_PROPERTY_NAME)
+                .expect("valid schema"),
+        )
+        .expect("valid schema");
+        let stopped_at = from_attribute_value(
+            attributes
+                .remove(GameRound::STOPPED_AT_PROPERTY_NAME)
+                .expect("valid schema"),
+        )
+        .expect("valid schema");
+        (id, stopped_at)
+    };
+    let result = dynamodb()
+        .update_item()
+        .table_name(table_name())
+        .set_key(Some(Game::get_key_from_id(game_id)))
+        .update_expression(
+            "SET #last_round = if_not_exists(#last_round, :zero) + :one, \
+            #state_version = :state_version, #stopped_at = :stopped_at",
+        )
+        .condition_expression(format!(
+            "attribute_exists({PK}) AND \
+            (attribute_not_exists(#state_version) OR #state_version <> :state_version)"
+        ))
+        .expression_attribute_names("#last_round", GameRound::COUNTER_PROPERTY_NAME)
+        .expression_attribute_names("#state_version", GameRound::STATE_VERSION_PROPERTY_NAME)
+        .expression_attribute_names("#stopped_at", GameRound::STOPPED_AT_PROPERTY_NAME)
+        .expression_attribute_values(":zero", AttributeValue::N("0".to_owned()))
+        .expression_attribute_values(":one", AttributeValue::N("1".to_owned()))
+        .expression_attribute_values(":state_version", to_attribute_value(state_version).unwrap())
+        .expression_attribute_values(":stopped_at", to_attribute_value(stopped_at).unwrap())
+        .return_values(ReturnValue::UpdatedNew)
+        .return_values_on_condition_check_failure(ReturnValuesOnConditionCheckFailure::AllOld)
+        .send()
+        .await
+        .map_err(aws_sdk_dynamodb::Error::from);
+    match result {
+        Ok(output) => Ok(reserved_round(output.attributes.expect("asked for them"))),
+        // The round of this state was already reserved, unless the game does not exist
+        Err(aws_sdk_dynamodb::Error::ConditionalCheckFailedException(e)) if e.item.is_some() => {
+            Ok(reserved_round(e.item.expect("checked above")))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Archives a [GameRound] in DynamoDB
+///
+/// The entries of the round are written before its header, so a round is only listed once it is
+/// complete. Archiving the same round again rewrites the same items, so a failed attempt can be
+/// retried.
+pub async fn dynamodb_put_game_round(round: &GameRound) -> Result<(), aws_sdk_dynamodb::Error> {
+    log::debug!("ENTER dynamodb_put_game_round - round.id={}", round.id);
+
+    let (header, entries) = round.to_items();
+    // Written [MAX_PARALLEL_UPDATES] at a time, and each chunk awaited before the next one, so a
+    // failed entry is reported before the header is written
+    for entries in entries.chunks(MAX_PARALLEL_UPDATES) {
+        let handles = entries
+            .iter()
+            .map(|entry| {
+                tokio::spawn(
+                    dynamodb()
+                        .put_item()
+                        .table_name(table_name())
+                        .set_item(Some(entry.clone()))
+                        .return_values(ReturnValue::None)
+                        .send(),
+                )
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.await.unwrap()?;
+        }
+    }
+    dynamodb()
+        .put_item()
+        .table_name(table_name())
+        .set_item(Some(header))
+        .return_values(ReturnValue::None)
+        .send()
+        .await?;
+
+    Ok(())
+}
+
+/// Checks whether the round `id` of a game is completely archived
+pub async fn dynamodb_is_round_archived(
+    game_id: ID,
+    id: i32,
+) -> Result<bool, aws_sdk_dynamodb::Error> {
+    log::debug!("ENTER dynamodb_is_round_archived - game_id={game_id} id={id}");
+
+    Ok(dynamodb()
+        .get_item()
+        .table_name(table_name())
+        .set_key(Some(GameRound::get_key_from_id((game_id, id))))
+        .projection_expression(PK)
+        .consistent_read(true)
+        .send()
+        .await?
+        .item
+        .is_some())
+}
+
+/// Retrieves items from DynamoDB by key, in batches
+///
+/// The items that do not exist are left out, and the others are returned in any order
+async fn dynamodb_batch_get_items(
+    keys: Vec<DynamoItem>,
+) -> Result<Vec<DynamoItem>, aws_sdk_dynamodb::Error> {
+    let mut items = Vec::with_capacity(keys.len());
+    for keys in keys.chunks(MAX_BATCH_GET_KEYS) {
+        let mut keys_and_attributes = Some(
+            KeysAndAttributes::builder()
+                .set_keys(Some(keys.to_vec()))
+                .build()
+                .expect("keys are set"),
+        );
+        while let Some(request) = keys_and_attributes.take() {
+            let output = dynamodb()
+                .batch_get_item()
+                .request_items(table_name(), request)
+                .send()
+                .await?;
+            items.extend(
+                output
+                    .responses
+                    .and_then(|mut responses| responses.remove(&table_name()))
+                    .unwrap_or_default(),
+            );
+            // Retry the keys DynamoDB did not process
+            keys_and_attributes = output
+                .unprocessed_keys
+                .and_then(|mut unprocessed| unprocessed.remove(&table_name()));
+        }
+    }
+    Ok(items)
+}
+
+/// Reads the entries of the rounds stored in `headers`, and assembles the rounds
+async fn dynamodb_assemble_rounds(
+    headers: Vec<DynamoItem>,
+) -> Result<Vec<GameRound>, aws_sdk_dynamodb::Error> {
+    let entry_keys = headers.iter().flat_map(GameRound::entry_keys).collect();
+    let mut entries_by_round = HashMap::<i32, Vec<DynamoItem>>::new();
+    for entry in dynamodb_batch_get_items(entry_keys).await? {
+        let round_id = from_attribute_value(entry[GameRound::ENTRY_ROUND_PROPERTY_NAME].clone())
+            .expect("valid schema");
+        entries_by_round.entry(round_id).or_default().push(entry);
+    }
+    Ok(headers
+        .into_iter()
+        .map(|header| {
+            let round_id = from_attribute_value(header["id"].clone()).expect("valid schema");
+            let entries = entries_by_round.remove(&round_id).unwrap_or_default();
+            GameRound::from_items(header, entries)
+        })
+        .collect())
+}
+
+/// Retrieves all the archived [GameRound] of a game from DynamoDB
+///
+/// Rounds are numbered from 1 to the round counter of the game, so they are read by key.
+/// Returns them sorted by id, most recent first
+pub async fn dynamodb_query_rounds(game_id: ID) -> Result<Vec<GameRound>, aws_sdk_dynamodb::Error> {
+    log::debug!("ENTER dynamodb_query_rounds - game_id={game_id}");
+
+    let last_round: i32 = dynamodb()
+        .get_item()
+        .table_name(table_name())
+        .set_key(Some(Game::get_key_from_id(game_id)))
+        .projection_expression("#last_round")
+        .expression_attribute_names("#last_round", GameRound::COUNTER_PROPERTY_NAME)
+        .send()
+        .await?
+        .item
+        .and_then(|mut item| item.remove(GameRound::COUNTER_PROPERTY_NAME))
+        .map(|last_round| from_attribute_value(last_round).expect("valid schema"))
+        .unwrap_or_default();
+    // A round whose archive failed midway has no header, and is left out
+    let headers = dynamodb_batch_get_items(
+        (1..=last_round)
+            .map(|id| GameRound::get_key_from_id((game_id, id)))
+            .collect(),
+    )
+    .await?;
+
+    let mut rounds = dynamodb_assemble_rounds(headers).await?;
+    rounds.sort_by_key(|r| std::cmp::Reverse(r.id));
+    Ok(rounds)
+}
+
+/// Retrieves an archived [GameRound] from DynamoDB
+///
+/// Returns [None] if the round does not exist
+pub async fn dynamodb_get_round(
+    game_id: ID,
+    id: i32,
+) -> Result<Option<GameRound>, aws_sdk_dynamodb::Error> {
+    log::debug!("ENTER dynamodb_get_round - game_id={game_id} id={id}");
+
+    let header = dynamodb()
+        .get_item()
+        .table_name(table_name())
+        .set_key(Some(GameRound::get_key_from_id((game_id, id))))
+        .send()
+        .await?
+        .item;
+    Ok(match header {
+        Some(header) => dynamodb_assemble_rounds(vec![header]).await?.pop(),
+        None => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use aws_sdk_dynamodb::types::error::{ConditionalCheckFailedException, InternalServerError};
+
+    use super::*;
+    use crate::test_fixtures::player;
+
+    /// A round of the game `game_id` with a few players, and a team without players
+    fn round(game_id: ID) -> GameRound {
+        let players = vec![
+            player("first", "RUST", Some(10), Some((20.0, 10))),
+            player("second", "JS", Some(30), None),
+            player("third", "RUST", Some(5), None),
+        ];
+        GameRound::new(
+            2,
+            &GameState::reset(game_id),
+            AWSTimestamp::from(200),
+            &Team::builtin(),
+            players,
+            &HashMap::new(),
+        )
+    }
+
+    #[test]
+    fn test_round_items() {
+        let round = round(ID::new());
+        let (header, entries) = round.to_items();
+        assert!(!header.contains_key("players") && !header.contains_key("teams"));
+        assert_eq!(entries.len(), round.teams.len() + round.players.len());
+        let entry_keys = GameRound::entry_keys(&header);
+        assert_eq!(entry_keys.len(), entries.len());
+        for entry in &entries {
+            assert!(entry_keys.contains(&HashMap::from([(PK.to_owned(), entry[PK].clone())])));
+        }
+
+        // The entries can be read back in any order
+        let read = GameRound::from_items(header, entries.into_iter().rev().collect());
+        assert_eq!((read.game_id, read.id), (round.game_id, round.id));
+        assert_eq!(read.stopped_at, round.stopped_at);
+        let names = |round: &GameRound| {
+            round
+                .players
+                .iter()
+                .map(|p| p.name.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&read), names(&round));
+        assert_eq!(read.players[0].avg_latency, Some(20.0));
+        let teams = |round: &GameRound| {
+            round
+                .teams
+                .iter()
+                .map(|t| (t.team.clone(), t.clicks))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(teams(&read), teams(&round));
+    }
+
+    #[test]
+    fn test_round_items_before_entries() {
+        // Rounds used to be archived as a single item
+        let round = round(ID::new());
+        let item = round.to_item();
+        assert!(GameRound::entry_keys(&item).is_empty());
+        let read = GameRound::from_items(item, vec![]);
+        assert_eq!(read.players.len(), round.players.len());
+        assert_eq!(read.teams.len(), round.teams.len());
+    }
+
+    #[test]
+    fn test_next_state_version() {
+        assert_eq!(next_state_version(1000, None), 1000);
+        assert_eq!(next_state_version(1000, Some(999)), 1000);
+        // The clock of the instance that wrote the current state is ahead
+        assert_eq!(next_state_version(1000, Some(1000)), 1001);
+        assert_eq!(next_state_version(1000, Some(5000)), 5001);
+    }
+
+    #[test]
+    fn test_player_state_update_from_result() {
+        assert!(matches!(
+            PlayerStateUpdate::from_result(Ok(())),
+            PlayerStateUpdate::Updated
+        ));
+        // The guard failed: the player was removed or holds a newer state
+        let guard_failed = aws_sdk_dynamodb::Error::ConditionalCheckFailedException(
+            ConditionalCheckFailedException::builder().build(),
+        );
+        assert!(matches!(
+            PlayerStateUpdate::from_result::<()>(Err(guard_failed)),
+            PlayerStateUpdate::Skipped
+        ));
+        let failed =
+            aws_sdk_dynamodb::Error::InternalServerError(InternalServerError::builder().build());
+        assert!(matches!(
+            PlayerStateUpdate::from_result::<()>(Err(failed)),
+            PlayerStateUpdate::Failed(aws_sdk_dynamodb::Error::InternalServerError(_))
+        ));
+    }
 }
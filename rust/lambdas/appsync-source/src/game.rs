@@ -1,18 +1,241 @@
-use crate::GameStatus;
+use lambda_appsync::{AWSTimestamp, ID};
+
+use crate::{GameState, GameStatus};
 
 impl GameStatus {
-    /// Returns the allowed current game status when transitioning to a new status.
+    /// The game statuses in which players can change team, i.e. when no round is being played
+    pub const TEAM_CHANGE_STATUSES: &'static [Self] = &[GameStatus::Reset, GameStatus::Stopped];
+
+    /// Returns the allowed current game statuses when transitioning to a new status.
     ///
     /// The game status can only transition in a specific order:
     /// - Reset -> Started -> Stopped -> Reset
+    /// - Reset -> Countdown -> Started -> Stopped -> Reset
+    /// - Started <-> Paused, and a paused game can be stopped directly
+    ///
+    /// A game in countdown starts by itself once its `starts_at` has passed, so it can
+    /// also be paused or stopped directly from the Countdown status.
     ///
     /// This method returns what the current status must be to allow transitioning
     /// to the target status (self).
-    pub fn valid_from_status(self) -> Self {
+    pub fn valid_from_status(self) -> &'static [Self] {
         match self {
-            GameStatus::Started => GameStatus::Reset,
-            GameStatus::Stopped => GameStatus::Started,
-            GameStatus::Reset => GameStatus::Stopped,
+            GameStatus::Countdown => &[GameStatus::Reset],
+            GameStatus::Started => &[GameStatus::Reset, GameStatus::Countdown, GameStatus::Paused],
+            GameStatus::Paused => &[GameStatus::Started, GameStatus::Countdown],
+            GameStatus::Stopped => &[
+                GameStatus::Started,
+                GameStatus::Countdown,
+                GameStatus::Paused,
+            ],
+            GameStatus::Reset => &[GameStatus::Stopped],
+        }
+    }
+}
+
+impl GameState {
+    /// Returns the state of a game that was never started or was just reset
+    pub fn reset(game_id: ID) -> Self {
+        GameState {
+            game_id,
+            status: GameStatus::Reset,
+            starts_at: None,
+            started_at: None,
+            paused_at: None,
+            paused_seconds: None,
+        }
+    }
+
+    /// Returns whether a countdown game has reached its `starts_at` time
+    fn countdown_over(&self, now: AWSTimestamp) -> bool {
+        self.status == GameStatus::Countdown && self.starts_at.is_some_and(|s| s <= now)
+    }
+
+    /// Returns the total time spent paused during this round, including the current pause
+    fn paused_seconds_at(&self, now: AWSTimestamp) -> i32 {
+        let current_pause = self.paused_at.map_or(0, |paused_at| {
+            now.into_u64().saturating_sub(paused_at.into_u64()) as i32
+        });
+        self.paused_seconds.unwrap_or_default() + current_pause
+    }
+
+    /// Returns the new state of the game when it is scheduled to start at `starts_at`,
+    /// or [None] if the game cannot enter the [GameStatus::Countdown] status
+    pub fn countdown(&self, starts_at: AWSTimestamp) -> Option<Self> {
+        if !GameStatus::Countdown
+            .valid_from_status()
+            .contains(&self.status)
+        {
+            return None;
+        }
+        Some(GameState {
+            game_id: self.game_id,
+            status: GameStatus::Countdown,
+            starts_at: Some(starts_at),
+            started_at: Some(starts_at),
+            paused_at: None,
+            paused_seconds: None,
+        })
+    }
+
+    /// Returns the time from which the game accepts clicks, or [None] if it does not
+    ///
+    /// A started game accepts clicks since the epoch rather than since `started_at`, so that
+    /// the clock of the backend checking it cannot reject the first clicks of the round.
+    /// This time is copied to the player items, so that a click can be checked against it
+    /// without reading the state of the game.
+    pub fn clicks_open_at(&self) -> Option<AWSTimestamp> {
+        match self.status {
+            GameStatus::Started => Some(AWSTimestamp::from(0)),
+            GameStatus::Countdown => self.starts_at,
+            GameStatus::Paused | GameStatus::Stopped | GameStatus::Reset => None,
         }
     }
+
+    /// Returns the new state of the game when transitioning to `status` at `now`,
+    /// or [None] if the transition is not allowed
+    ///
+    /// The time spent in [GameStatus::Paused] is accumulated in `paused_seconds` so that
+    /// it does not count against the play time of the round.
+    /// Use [GameState::countdown] to transition to [GameStatus::Countdown].
+    pub fn transition(&self, status: GameStatus, now: AWSTimestamp) -> Option<Self> {
+        if !status.valid_from_status().contains(&self.status) {
+            return None;
+        }
+        match status {
+            GameStatus::Reset => Some(Self::reset(self.game_id)),
+            GameStatus::Countdown => None,
+            GameStatus::Started if self.status == GameStatus::Paused => Some(GameState {
+                status,
+                paused_at: None,
+                paused_seconds: Some(self.paused_seconds_at(now)),
+                ..self.clone()
+            }),
+            GameStatus::Started => Some(GameState {
+                game_id: self.game_id,
+                status,
+                starts_at: None,
+                // Keep the scheduled start time if the countdown is already over
+                started_at: Some(self.started_at.filter(|s| *s <= now).unwrap_or(now)),
+                paused_at: None,
+                paused_seconds: None,
+            }),
+            GameStatus::Paused => {
+                // A game still counting down has nothing to pause yet
+                if self.status == GameStatus::Countdown && !self.countdown_over(now) {
+                    return None;
+                }
+                Some(GameState {
+                    status,
+                    paused_at: Some(now),
+                    ..self.clone()
+                })
+            }
+            GameStatus::Stopped => Some(GameState {
+                status,
+                started_at: self.started_at.filter(|s| *s <= now),
+                paused_at: None,
+                paused_seconds: Some(self.paused_seconds_at(now)),
+                ..self.clone()
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_from_status() {
+        assert_eq!(
+            GameStatus::Countdown.valid_from_status(),
+            [GameStatus::Reset]
+        );
+        assert_eq!(
+            GameStatus::Started.valid_from_status(),
+            [GameStatus::Reset, GameStatus::Countdown, GameStatus::Paused]
+        );
+        assert_eq!(
+            GameStatus::Paused.valid_from_status(),
+            [GameStatus::Started, GameStatus::Countdown]
+        );
+        assert_eq!(
+            GameStatus::Stopped.valid_from_status(),
+            [
+                GameStatus::Started,
+                GameStatus::Countdown,
+                GameStatus::Paused
+            ]
+        );
+        assert_eq!(GameStatus::Reset.valid_from_status(), [GameStatus::Stopped]);
+    }
+
+    #[test]
+    fn test_pause_resume_accumulates_paused_time() {
+        let started = GameState::reset(ID::new())
+            .transition(GameStatus::Started, AWSTimestamp::from(100))
+            .unwrap();
+        assert_eq!(started.started_at, Some(AWSTimestamp::from(100)));
+
+        let paused = started
+            .transition(GameStatus::Paused, AWSTimestamp::from(110))
+            .unwrap();
+        let resumed = paused
+            .transition(GameStatus::Started, AWSTimestamp::from(125))
+            .unwrap();
+        assert_eq!(resumed.status, GameStatus::Started);
+        assert_eq!(resumed.started_at, Some(AWSTimestamp::from(100)));
+        assert_eq!(resumed.paused_at, None);
+        assert_eq!(resumed.paused_seconds, Some(15));
+
+        let stopped = resumed
+            .transition(GameStatus::Paused, AWSTimestamp::from(130))
+            .unwrap()
+            .transition(GameStatus::Stopped, AWSTimestamp::from(135))
+            .unwrap();
+        assert_eq!(stopped.paused_seconds, Some(20));
+    }
+
+    #[test]
+    fn test_clicks_open_at() {
+        let reset = GameState::reset(ID::new());
+        assert_eq!(reset.clicks_open_at(), None);
+
+        let countdown = reset.countdown(AWSTimestamp::from(110)).unwrap();
+        assert_eq!(countdown.clicks_open_at(), Some(AWSTimestamp::from(110)));
+
+        let started = reset
+            .transition(GameStatus::Started, AWSTimestamp::from(100))
+            .unwrap();
+        assert_eq!(started.clicks_open_at(), Some(AWSTimestamp::from(0)));
+
+        let paused = started
+            .transition(GameStatus::Paused, AWSTimestamp::from(105))
+            .unwrap();
+        assert_eq!(paused.clicks_open_at(), None);
+        let stopped = paused
+            .transition(GameStatus::Stopped, AWSTimestamp::from(106))
+            .unwrap();
+        assert_eq!(stopped.clicks_open_at(), None);
+    }
+
+    #[test]
+    fn test_invalid_transitions() {
+        let reset = GameState::reset(ID::new());
+        let now = AWSTimestamp::from(100);
+        assert!(reset.transition(GameStatus::Paused, now).is_none());
+        assert!(reset.transition(GameStatus::Stopped, now).is_none());
+        assert!(reset.transition(GameStatus::Countdown, now).is_none());
+
+        // A countdown can only be paused once it is over
+        let countdown = reset.countdown(AWSTimestamp::from(110)).unwrap();
+        assert!(countdown.transition(GameStatus::Paused, now).is_none());
+        assert!(
+            countdown
+                .transition(GameStatus::Paused, AWSTimestamp::from(110))
+                .is_some()
+        );
+        assert!(countdown.countdown(AWSTimestamp::from(120)).is_none());
+    }
 }
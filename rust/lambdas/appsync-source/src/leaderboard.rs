@@ -0,0 +1,143 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::HashMap,
+};
+
+use latency_histogram::LatencyHistogram;
+
+use crate::{Leaderboard, Player, PlayerStanding, Team, TeamResult};
+
+/// Orders players by clicks, the most clicks first
+///
+/// Ties are broken by player name, so every viewer sees the same order.
+fn by_clicks(a: &Player, b: &Player) -> Ordering {
+    Reverse(a.clicks.unwrap_or_default())
+        .cmp(&Reverse(b.clicks.unwrap_or_default()))
+        .then_with(|| a.name.cmp(&b.name))
+}
+
+impl Leaderboard {
+    /// Number of players in each ranking when no limit is requested
+    pub const DEFAULT_LIMIT: i32 = 10;
+    /// Largest number of players a ranking can hold
+    pub const MAX_LIMIT: i32 = 100;
+
+    /// Ranks `players`, keeping the `limit` best of each ranking, and aggregates the `teams`
+    /// along with their `latency_histograms`
+    ///
+    /// Players without any latency report are left out of the latency ranking.
+    /// Ties are broken by player name, so every viewer sees the same order.
+    pub fn new(
+        teams: &[Team],
+        players: Vec<Player>,
+        latency_histograms: &HashMap<String, LatencyHistogram>,
+        limit: usize,
+    ) -> Self {
+        let teams = TeamResult::for_teams(teams, &players, latency_histograms);
+
+        let mut top_latency = players
+            .iter()
+            .filter_map(|p| p.avg_latency.map(|l| (l, p)))
+            .collect::<Vec<_>>();
+        top_latency.sort_by(|(la, a), (lb, b)| la.total_cmp(lb).then_with(|| a.name.cmp(&b.name)));
+        top_latency.truncate(limit);
+        let top_latency = top_latency.into_iter().map(|(_, p)| p.clone()).collect();
+
+        let mut top_clicks = players;
+        top_clicks.sort_by(by_clicks);
+        top_clicks.truncate(limit);
+
+        Leaderboard {
+            top_clicks,
+            top_latency,
+            teams,
+        }
+    }
+}
+
+impl PlayerStanding {
+    /// Ranks `player` of `team` among the `players` of their game, like [Leaderboard::new]
+    /// ranks the top players by clicks
+    ///
+    /// `players` may or may not include `player` itself.
+    pub fn new(player: Player, team: Team, players: &[Player]) -> Self {
+        let ahead = players
+            .iter()
+            .filter(|p| p.id != player.id && by_clicks(p, &player).is_lt())
+            .collect::<Vec<_>>();
+        let team_ahead = ahead.iter().filter(|p| p.team == player.team).count();
+        PlayerStanding {
+            rank: ahead.len() as i32 + 1,
+            team_rank: team_ahead as i32 + 1,
+            player,
+            team,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::player;
+
+    fn names(players: &[Player]) -> Vec<&str> {
+        players.iter().map(|p| p.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_leaderboard_rankings() {
+        let players = vec![
+            player("a", "RUST", Some(5), Some((30.0, 5))),
+            player("b", "JS", Some(20), Some((10.0, 20))),
+            player("c", "RUST", None, None),
+            player("d", "PYTHON", Some(20), Some((50.0, 20))),
+        ];
+        let leaderboard = Leaderboard::new(&Team::builtin(), players, &HashMap::new(), 3);
+
+        assert_eq!(names(&leaderboard.top_clicks), ["b", "d", "a"]);
+        assert_eq!(names(&leaderboard.top_latency), ["b", "a", "d"]);
+        assert_eq!(leaderboard.teams.len(), Team::builtin().len());
+
+        let rust = leaderboard.teams.iter().find(|t| t.team == "RUST").unwrap();
+        assert_eq!((rust.players, rust.clicks), (2, 5));
+        assert_eq!(rust.avg_latency, Some(30.0));
+    }
+
+    #[test]
+    fn test_leaderboard_limit() {
+        let players = vec![
+            player("a", "RUST", Some(1), Some((1.0, 1))),
+            player("b", "JS", Some(2), Some((2.0, 2))),
+        ];
+        let leaderboard = Leaderboard::new(&Team::builtin(), players, &HashMap::new(), 1);
+        assert_eq!(names(&leaderboard.top_clicks), ["b"]);
+        assert_eq!(names(&leaderboard.top_latency), ["a"]);
+        assert_eq!(leaderboard.teams.len(), Team::builtin().len());
+    }
+
+    #[test]
+    fn test_player_standing() {
+        let players = vec![
+            player("a", "RUST", Some(5), None),
+            player("b", "JS", Some(20), None),
+            player("c", "RUST", None, None),
+            player("d", "RUST", Some(20), None),
+        ];
+        let rust = Team::find(&Team::builtin(), "RUST").unwrap().clone();
+        let standing = |name: &str| {
+            let me = players.iter().find(|p| p.name == name).unwrap().clone();
+            let standing = PlayerStanding::new(me, rust.clone(), &players);
+            (standing.rank, standing.team_rank)
+        };
+        // Same order as the leaderboard: b, d, a, c
+        assert_eq!(standing("d"), (2, 1));
+        assert_eq!(standing("a"), (3, 2));
+        assert_eq!(standing("c"), (4, 3));
+
+        // The player may have clicked since the other players were read
+        let mut me = players[2].clone();
+        me.clicks = Some(30);
+        let standing = PlayerStanding::new(me, rust, &players);
+        assert_eq!((standing.rank, standing.team_rank), (1, 1));
+    }
+}
@@ -1,6 +1,13 @@
 mod dynamodb_helpers;
 mod game;
+mod leaderboard;
 mod operations;
+mod player_name;
+mod round;
+mod team;
+mod team_assignment;
+#[cfg(test)]
+mod test_fixtures;
 
 // use lambda_appsync::{
 //     arg_from_json, aws_config, env_logger, lambda_runtime, log, res_to_json,
@@ -375,7 +382,11 @@ mod operations;
 //     lambda_runtime::run(lambda_runtime::service_fn(function_handler)).await
 // }
 
-// This macro generates all the code commented above
-// For the types and operations specific to this AppSync project, it uses the GraphQL schema file as a reference
-// for the Lambda handler and integration types, it uses generic (and opiniated) event structs
-lambda_appsync::appsync_lambda_main! ("graphql/schema.gql", dynamodb() -> aws_sdk_dynamodb::Client);
+// `appsync_lambda_main!` generates the types and operations of the code commented above, using
+// the GraphQL schema file as a reference, and the Lambda integration types as generic (and
+// opiniated) event structs.
+// The lambda handler, the `main` function and the DynamoDB client are generated by
+// `lambda_telemetry::appsync_lambda_handler!` instead, so that the init phase and each invocation
+// are measured and reported as metrics, and that the logs carry the request ID of the invocation
+lambda_appsync::appsync_lambda_main!("graphql/schema.gql", exclude_lambda_handler = true);
+lambda_telemetry::appsync_lambda_handler!();
@@ -1,188 +1,709 @@
-use std::collections::HashSet;
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
 
 use crate::{
-    GameStatus, Player, Team,
+    Ban, Game, GameConfig, GameConfigInput, GameRound, GameState, GameStatus, Leaderboard,
+    NewPlayerInput, Player, PlayerStanding, Team, TeamAssignment, TeamInput, TeamWeight,
+    TeamWeightInput,
     dynamodb_helpers::{
-        dynamodb_delete_player, dynamodb_get_game_status, dynamodb_put_new_player,
-        dynamodb_query_players, dynamodb_query_teams_player_count, dynamodb_reset_game,
-        dynamodb_set_game_status, dynamodb_update_player_name,
+        PlayerCreation, PlayerMove, PlayerRename, SecretUpdate, dynamodb_ban_player,
+        dynamodb_create_game, dynamodb_delete_player, dynamodb_get_game_config,
+        dynamodb_get_game_state, dynamodb_get_player_with_secret, dynamodb_get_round,
+        dynamodb_get_team, dynamodb_get_team_counters, dynamodb_get_versioned_game_state,
+        dynamodb_is_round_archived, dynamodb_is_secret_banned, dynamodb_move_player,
+        dynamodb_put_game_config, dynamodb_put_game_round, dynamodb_put_new_player,
+        dynamodb_put_team, dynamodb_query_bans, dynamodb_query_games, dynamodb_query_players,
+        dynamodb_query_players_with_latency_histograms, dynamodb_query_rounds,
+        dynamodb_query_teams, dynamodb_reserve_round_id, dynamodb_reset_game,
+        dynamodb_set_game_state, dynamodb_set_player_secret, dynamodb_set_players_clicks_open_at,
+        dynamodb_unban_player, dynamodb_update_player_name,
     },
+    player_name::PlayerName,
+    team_assignment::AssignmentContext,
 };
-use lambda_appsync::{AppsyncError, ID, appsync_operation};
+use lambda_appsync::{AWSTimestamp, AppsyncError, ID, appsync_operation, tokio};
+use player_secret::{StoredSecret, hash_secret, run_blocking, scoped_digest};
+
+/// Longest countdown an admin can schedule before the start of a game
+const MAX_COUNTDOWN_SECONDS: i32 = 3600;
+/// Number of times a registration is attempted when other players register in the same team
+/// at the same time
+const MAX_REGISTRATION_ATTEMPTS: usize = 5;
 
 fn player_not_found() -> AppsyncError {
     AppsyncError::new("PlayerNotFound", "Player does not exist")
 }
+fn invalid_secret() -> AppsyncError {
+    AppsyncError::new("InvalidSecret", "Player secret is invalid")
+}
+fn invalid_player_name(reason: String) -> AppsyncError {
+    AppsyncError::new("InvalidPlayerName", reason)
+}
+fn player_name_taken() -> AppsyncError {
+    AppsyncError::new(
+        "PlayerNameTaken",
+        "Another player of the game already has this name",
+    )
+}
+fn player_banned() -> AppsyncError {
+    AppsyncError::new("PlayerBanned", "Player is banned from the game")
+}
+fn ban_not_found() -> AppsyncError {
+    AppsyncError::new("BanNotFound", "Player is not banned")
+}
+fn team_not_found() -> AppsyncError {
+    AppsyncError::new("TeamNotFound", "Team does not exist")
+}
+fn invalid_team(reason: &str) -> AppsyncError {
+    AppsyncError::new("InvalidTeam", reason)
+}
+fn game_not_found() -> AppsyncError {
+    AppsyncError::new("GameNotFound", "Game does not exist")
+}
+fn invalid_game_status() -> AppsyncError {
+    AppsyncError::new(
+        "InvalidGameStatus",
+        "The game status does not allow this operation",
+    )
+}
+fn invalid_limit() -> AppsyncError {
+    AppsyncError::new(
+        "InvalidLimit",
+        format!("Limit must be between 1 and {}", Leaderboard::MAX_LIMIT),
+    )
+}
+fn invalid_game_config(reason: &str) -> AppsyncError {
+    AppsyncError::new("InvalidGameConfig", reason)
+}
+fn registration_conflict() -> AppsyncError {
+    AppsyncError::new(
+        "RegistrationConflict",
+        "Too many players are registering at the same time, please retry",
+    )
+}
+fn player_changed() -> AppsyncError {
+    AppsyncError::new(
+        "PlayerChanged",
+        "The player was updated at the same time, please retry",
+    )
+}
+fn missing_secret() -> AppsyncError {
+    AppsyncError::new(
+        "MissingSecret",
+        "The player has no secret, it must be reset first",
+    )
+}
+fn secret_not_transferable() -> AppsyncError {
+    AppsyncError::new(
+        "SecretNotTransferable",
+        "The secret of the player cannot be verified by the new team, \
+        the player must switch team themselves or have their secret reset",
+    )
+}
+fn invalid_countdown() -> AppsyncError {
+    AppsyncError::new(
+        "InvalidCountdown",
+        format!("Countdown must be between 1 and {MAX_COUNTDOWN_SECONDS} seconds"),
+    )
+}
+
+#[appsync_operation(query(teams))]
+pub async fn teams() -> Result<Vec<Team>, AppsyncError> {
+    Ok(dynamodb_query_teams().await?)
+}
+
+#[appsync_operation(mutation(putTeam))]
+pub async fn put_team(team: TeamInput) -> Result<Team, AppsyncError> {
+    team.validate().map_err(|reason| invalid_team(&reason))?;
+    let TeamInput { id, name, backend } = team;
+    let team = Team { id, name, backend };
+    dynamodb_put_team(&team).await?;
+    Ok(team)
+}
+
+#[appsync_operation(query(games))]
+pub async fn games() -> Result<Vec<Game>, AppsyncError> {
+    Ok(dynamodb_query_games().await?)
+}
+
+#[appsync_operation(mutation(createGame))]
+pub async fn create_game(name: String) -> Result<Game, AppsyncError> {
+    let new_game = Game {
+        id: ID::new(),
+        name,
+    };
+    // Also creates the state of the game, in the Reset status
+    dynamodb_create_game(&new_game).await?;
+    Ok(new_game)
+}
 
 // impl crate::Operation {
-//     pub async fn query_players() -> Result<Vec<Player>, AppSyncError> {
+//     pub async fn query_players(game_id: ID) -> Result<Vec<Player>, AppSyncError> {
 //         // This is just a marker to ensure an error is thrown if the user did not chose
 //         // the correct signature for the function. Should be optimized away by the compiler.
 //         if false {
-//             return <crate::Operation as crate::DefautOperations>::query_players().await;
+//             return <crate::Operation as crate::DefautOperations>::query_players(game_id).await;
 //         }
-//         Ok(dynamodb_query_players().await?)
+//         Ok(dynamodb_query_players(game_id).await?)
 //     }
 // }
 // This macro replace the whole function by the code commented above
 #[appsync_operation(query(players))]
-pub async fn players() -> Result<Vec<Player>, AppsyncError> {
-    Ok(dynamodb_query_players().await?)
+pub async fn players(game_id: ID) -> Result<Vec<Player>, AppsyncError> {
+    Ok(dynamodb_query_players(game_id).await?)
 }
 // impl crate::Operation {
-//     pub async fn query_game_status() -> Result<GameStatus, AppSyncError> {
+//     pub async fn query_game_status(game_id: ID) -> Result<GameStatus, AppSyncError> {
 //         // This is just a marker to ensure an error is thrown if the user did not chose
 //         // the correct signature for the function. Should be optimized away by the compiler.
 //         if false {
-//             return <crate::Operation as crate::DefautOperations>::query_game_status().await;
+//             return <crate::Operation as crate::DefautOperations>::query_game_status(game_id)
+//                 .await;
 //         }
-//         Ok(dynamodb_get_game_status().await?)
+//         Ok(game_state(game_id).await?.status)
 //     }
 // }
 // This macro replace the whole function by the code commented above
 #[appsync_operation(query(gameStatus))]
-pub async fn game_status() -> Result<GameStatus, AppsyncError> {
-    Ok(dynamodb_get_game_status()
+pub async fn game_status(game_id: ID) -> Result<GameStatus, AppsyncError> {
+    Ok(game_state(game_id).await?.status)
+}
+
+// `keep_original_function_name` keeps `game_state` callable from the `gameStatus` query above
+#[appsync_operation(query(gameState), keep_original_function_name)]
+pub async fn game_state(game_id: ID) -> Result<GameState, AppsyncError> {
+    dynamodb_get_game_state(game_id)
+        .await?
+        .ok_or_else(game_not_found)
+}
+
+/// Copies the current state of a game to its players again, and returns it
+///
+/// Repairs the players a previous change of the state failed to update, so an admin can retry
+/// a mutation that failed after the state was changed.
+async fn copy_game_state_to_players(game_id: ID) -> Result<GameState, AppsyncError> {
+    let (state, state_version) = dynamodb_get_versioned_game_state(game_id)
         .await?
-        .unwrap_or_else(|| GameStatus::Reset))
+        .ok_or_else(game_not_found)?;
+    dynamodb_set_players_clicks_open_at(game_id, state.clicks_open_at(), state_version).await?;
+    Ok(state)
+}
+
+/// Moves the game to `status`, provided its current status is one of `from`
+///
+/// If the game already has `status`, only its state is copied to the players again, see
+/// [copy_game_state_to_players]
+///
+/// Returns the new [GameState] on success
+async fn transition_game_state(
+    game_id: ID,
+    status: GameStatus,
+    from: &[GameStatus],
+) -> Result<GameState, AppsyncError> {
+    // Retrieve the current state, because the new one depends on it (e.g. the paused time)
+    let current_state = game_state(game_id).await?;
+    if current_state.status == status {
+        return copy_game_state_to_players(game_id).await;
+    }
+    if !from.contains(&current_state.status) {
+        return Err(invalid_game_status());
+    }
+    let new_state = current_state
+        .transition(status, AWSTimestamp::now())
+        .ok_or_else(invalid_game_status)?;
+    // Update the game state in DynamoDB to the new state
+    // The write is conditioned on the status it was computed from, in case of concurrent updates
+    let state_version = dynamodb_set_game_state(new_state.clone(), &[current_state.status]).await?;
+    // Then let the players know, so clicks can be checked without reading the game state
+    dynamodb_set_players_clicks_open_at(game_id, new_state.clicks_open_at(), state_version).await?;
+    Ok(new_state)
 }
 
 // This is a declarative macro that helps reduce boilerplate code for game status mutation operations.
 // It generates a function for each game status mutation (like startGame and stopGame) that follows
 // the same pattern but with different GameStatus values.
 macro_rules! game_status_mut {
-    // The macro takes two parameters:
+    // The macro takes two or three parameters:
     // $mut_name: The identifier for the mutation name (like startGame)
     // $status: The path to the GameStatus variant to set (like GameStatus::Started)
-    ($mut_name:ident, $status:path ) => {
+    // $from: Optionnaly, the GameStatus variants the mutation can be called from
+    //        (default to all the statuses allowed by `GameStatus::valid_from_status`)
+    ($mut_name:ident, $status:path) => {
+        game_status_mut!($mut_name, $status, $status.valid_from_status());
+    };
+    ($mut_name:ident, $status:path, $from:expr) => {
         // The macro generates an async function annotated with appsync_operation
         // indicating this is a GraphQL mutation handler
         #[appsync_operation(mutation($mut_name))]
-        pub async fn _discarded() -> Result<GameStatus, AppsyncError> {
-            // Update the game status in DynamoDB to the new status
-            dynamodb_set_game_status($status).await?;
-            // Return the new status on success
-            Ok($status)
+        pub async fn _discarded(game_id: ID) -> Result<GameState, AppsyncError> {
+            transition_game_state(game_id, $status, &$from).await
         }
     };
 }
 
-// Generate two mutation handlers:
-// - startGame: Sets game status to Started
-// - stopGame: Sets game status to Stopped
-game_status_mut!(startGame, GameStatus::Started);
-game_status_mut!(stopGame, GameStatus::Stopped);
+/// The statuses a game can be started from, as opposed to resumed
+const START_FROM: [GameStatus; 2] = [GameStatus::Reset, GameStatus::Countdown];
+
+// Generate the mutation handlers:
+// - startGame: Sets game status to Started, from Reset or Countdown
+// - pauseGame: Sets game status to Paused
+// - resumeGame: Sets game status back to Started, only from Paused
+game_status_mut!(startGame, GameStatus::Started, START_FROM);
+game_status_mut!(pauseGame, GameStatus::Paused);
+game_status_mut!(resumeGame, GameStatus::Started, [GameStatus::Paused]);
+
+/// Stops the current round of a game, and archives its results
+///
+/// The round is identified by the version of the stopped state, so stopping the game again
+/// after an archive that failed midway completes it, and does not archive the round twice.
+///
+/// Returns the new [GameState] on success
+async fn stop_and_archive_game(game_id: ID) -> Result<GameState, AppsyncError> {
+    let stopped_at = AWSTimestamp::now();
+    // Stopping first guarantees no more clicks are accepted, so the scores are final
+    transition_game_state(
+        game_id,
+        GameStatus::Stopped,
+        GameStatus::Stopped.valid_from_status(),
+    )
+    .await?;
+    let (new_state, state_version) = dynamodb_get_versioned_game_state(game_id)
+        .await?
+        .ok_or_else(game_not_found)?;
+    // The game was reset meanwhile, and its scores cleared
+    if new_state.status != GameStatus::Stopped {
+        return Err(invalid_game_status());
+    }
+
+    // Archive the results of the round before they get cleared by a reset
+    let (round_id, stopped_at) =
+        dynamodb_reserve_round_id(game_id, state_version, stopped_at).await?;
+    if dynamodb_is_round_archived(game_id, round_id).await? {
+        return Ok(new_state);
+    }
+    let teams = dynamodb_query_teams().await?;
+    let (players, latency_histograms) =
+        dynamodb_query_players_with_latency_histograms(game_id).await?;
+    let round = GameRound::new(
+        round_id,
+        &new_state,
+        stopped_at,
+        &teams,
+        players,
+        &latency_histograms,
+    );
+    dynamodb_put_game_round(&round).await?;
+
+    Ok(new_state)
+}
+
+#[appsync_operation(mutation(stopGame))]
+pub async fn stop_game(game_id: ID) -> Result<GameState, AppsyncError> {
+    stop_and_archive_game(game_id).await
+}
+
+#[appsync_operation(query(leaderboard))]
+pub async fn leaderboard(game_id: ID, limit: Option<i32>) -> Result<Leaderboard, AppsyncError> {
+    let limit = limit.unwrap_or(Leaderboard::DEFAULT_LIMIT);
+    if !(1..=Leaderboard::MAX_LIMIT).contains(&limit) {
+        return Err(invalid_limit());
+    }
+    // Rankings are computed once here so that every viewer gets the same results
+    let teams = dynamodb_query_teams().await?;
+    let (players, latency_histograms) =
+        dynamodb_query_players_with_latency_histograms(game_id).await?;
+    Ok(Leaderboard::new(
+        &teams,
+        players,
+        &latency_histograms,
+        limit as usize,
+    ))
+}
+
+#[appsync_operation(query(me))]
+pub async fn me(player_id: ID, secret: String) -> Result<PlayerStanding, AppsyncError> {
+    // A read does not migrate the secret, the next update of the player will
+    let (player, _, _) = verify_player_secret(player_id, secret).await?;
+    let team = dynamodb_get_team(player.team.clone())
+        .await?
+        .ok_or_else(team_not_found)?;
+    let players = dynamodb_query_players(player.game_id).await?;
+    Ok(PlayerStanding::new(player, team, &players))
+}
+
+#[appsync_operation(query(rounds))]
+pub async fn rounds(game_id: ID) -> Result<Vec<GameRound>, AppsyncError> {
+    Ok(dynamodb_query_rounds(game_id).await?)
+}
+
+#[appsync_operation(query(round))]
+pub async fn round(game_id: ID, id: i32) -> Result<Option<GameRound>, AppsyncError> {
+    Ok(dynamodb_get_round(game_id, id).await?)
+}
+
+#[appsync_operation(query(gameConfig), keep_original_function_name)]
+async fn game_config(game_id: ID) -> Result<GameConfig, AppsyncError> {
+    // A game that was never configured uses the default configuration
+    Ok(dynamodb_get_game_config(game_id)
+        .await?
+        .unwrap_or_else(|| GameConfig::default_for(game_id)))
+}
+
+#[appsync_operation(mutation(setGameConfig))]
+pub async fn set_game_config(
+    game_id: ID,
+    config: GameConfigInput,
+) -> Result<GameConfig, AppsyncError> {
+    let GameConfigInput {
+        team_assignment,
+        seed,
+        team_weights,
+    } = config;
+    let team_weights = team_weights
+        .into_iter()
+        .map(|TeamWeightInput { team, weight }| TeamWeight { team, weight })
+        .collect::<Vec<_>>();
+    if team_weights.iter().any(|tw| tw.weight < 0) {
+        return Err(invalid_game_config("Team weights cannot be negative"));
+    }
+    let weighted_teams = team_weights
+        .iter()
+        .map(|tw| tw.team.as_str())
+        .collect::<HashSet<_>>();
+    if weighted_teams.len() != team_weights.len() {
+        return Err(invalid_game_config("Each team can only have one weight"));
+    }
+    let teams = dynamodb_query_teams().await?;
+    if let Some(unknown_team) = weighted_teams
+        .into_iter()
+        .find(|team_id| Team::find(&teams, team_id).is_none())
+    {
+        return Err(invalid_game_config(&format!(
+            "Team {unknown_team} does not exist"
+        )));
+    }
+    if team_assignment == TeamAssignment::Weighted && !team_weights.iter().any(|tw| tw.weight > 0) {
+        return Err(invalid_game_config(
+            "The WEIGHTED strategy needs at least one team with a positive weight",
+        ));
+    }
+
+    // Make sure the game exists
+    game_state(game_id).await?;
+
+    let config = GameConfig {
+        game_id,
+        team_assignment,
+        seed,
+        team_weights,
+    };
+    dynamodb_put_game_config(&config).await?;
+    Ok(config)
+}
+
+#[appsync_operation(query(suspiciousPlayers))]
+pub async fn suspicious_players(game_id: ID) -> Result<Vec<Player>, AppsyncError> {
+    let mut players = dynamodb_query_players(game_id).await?;
+    // Players who never hit the rate limit are not suspicious
+    players.retain(|p| p.rate_limited_clicks.unwrap_or_default() > 0);
+    players.sort_by_key(|p| Reverse(p.rate_limited_clicks));
+    Ok(players)
+}
+
+#[appsync_operation(mutation(scheduleGameStart))]
+pub async fn schedule_game_start(
+    game_id: ID,
+    countdown_seconds: i32,
+) -> Result<GameState, AppsyncError> {
+    if !(1..=MAX_COUNTDOWN_SECONDS).contains(&countdown_seconds) {
+        return Err(invalid_countdown());
+    }
+    // The game will accept clicks as soon as `starts_at` is reached, without
+    // needing any further action from the admin
+    let starts_at = AWSTimestamp::now() + Duration::from_secs(countdown_seconds as u64);
+    let current_state = game_state(game_id).await?;
+    if current_state.status == GameStatus::Countdown {
+        // Keep the scheduled start, and repair the players if scheduling it failed midway
+        return copy_game_state_to_players(game_id).await;
+    }
+    let new_state = current_state
+        .countdown(starts_at)
+        .ok_or_else(invalid_game_status)?;
+    let state_version = dynamodb_set_game_state(new_state.clone(), &[current_state.status]).await?;
+    dynamodb_set_players_clicks_open_at(game_id, new_state.clicks_open_at(), state_version).await?;
+    Ok(new_state)
+}
 
 // impl crate::Operation {
-//     pub async fn mutation_reset_game() -> Result<GameStatus, AppSyncError> {
+//     pub async fn mutation_reset_game(game_id: ID) -> Result<GameState, AppSyncError> {
 //         // This is just a marker to ensure an error is thrown if the user did not chose
 //         // the correct signature for the function. Should be optimized away by the compiler.
 //         if false {
-//             return <crate::Operation as crate::DefautOperations>::mutation_reset_game().await;
+//             return <crate::Operation as crate::DefautOperations>::mutation_reset_game(game_id)
+//                 .await;
 //         }
-//         dynamodb_reset_game().await.map_err(from_dynamo_error)?;
-//         Ok(GameStatus::Reset)
+//         dynamodb_reset_game(game_id).await.map_err(from_dynamo_error)?;
+//         Ok(GameState::reset(game_id))
 //     }
 // }
 // This macro replace the whole function by the code commented above
 #[appsync_operation(mutation(resetGame))]
-pub async fn reset_game() -> Result<GameStatus, AppsyncError> {
-    dynamodb_reset_game().await?;
-    Ok(GameStatus::Reset)
+pub async fn reset_game(game_id: ID) -> Result<GameState, AppsyncError> {
+    dynamodb_reset_game(game_id).await?;
+    Ok(GameState::reset(game_id))
 }
 
 // impl crate::Operation {
 //     pub async fn mutation_register_new_player(
-//         name: String,
+//         game_id: ID,
+//         player: NewPlayerInput,
 //         secret: String,
 //     ) -> Result<Player, AppSyncError> {
 //         // This is just a marker to ensure an error is thrown if the user did not chose
 //         // the correct signature for the function. Should be optimized away by the compiler.
 //         if false {
 //             return <crate::Operation as crate::DefautOperations>::mutation_register_new_player(
-//                 name, secret,
+//                 game_id, player, secret,
 //             )
 //             .await;
 //         }
-//         let mut teams_player_count = dynamodb_query_teams_player_count()
-//             .await
-//             .map_err(from_dynamo_error)?;
-//         let team = if teams_player_count.len() < Team::COUNT {
-//             // If all teams are not yet used, choose one of the unused
-//             let mut all_teams = HashSet::from(Team::all());
-//             while let Some((team, _)) = teams_player_count.pop() {
-//                 all_teams.remove(&team);
-//             }
-//             all_teams
-//                 .into_iter()
-//                 .next()
-//                 .expect("we ensured teams_player_count had less element than all_teams")
-//         } else {
-//             // Else chose the one with less players
-//             teams_player_count.sort_by_key(|o| o.1);
-//             teams_player_count[0].0
-//         };
-//         let id = ID::new();
-//         let new_player = Player {
-//             id,
+//         let NewPlayerInput {
 //             name,
-//             team,
-//             clicks: None,
-//             avg_latency: None,
-//             avg_latency_clicks: None,
-//         };
-//         dynamodb_put_new_player(&new_player, secret)
+//             preferred_team,
+//         } = player;
+//         let name = PlayerName::parse(&name).map_err(invalid_player_name)?;
+//         let mut state = game_state(game_id).await?;
+//         let secret_digest = scoped_digest(&secret, &game_id.to_string());
+//         if dynamodb_is_secret_banned(game_id, &secret_digest)
 //             .await
-//             .map_err(from_dynamo_error)?;
-//         Ok(new_player)
+//             .map_err(from_dynamo_error)?
+//         {
+//             return Err(player_banned());
+//         }
+//         let config = game_config(game_id).await?;
+//         let teams = dynamodb_query_teams().await.map_err(from_dynamo_error)?;
+//         if preferred_team
+//             .as_deref()
+//             .is_some_and(|team_id| Team::find(&teams, team_id).is_none())
+//         {
+//             return Err(team_not_found());
+//         }
+//         let hashed_secret = tokio::sync::OnceCell::new();
+//         for _ in 0..MAX_REGISTRATION_ATTEMPTS {
+//             let team_counters = dynamodb_get_team_counters(game_id, &teams)
+//                 .await
+//                 .map_err(from_dynamo_error)?;
+//             let teams_player_count = team_counters
+//                 .iter()
+//                 .map(|(team_id, count)| (team_id.clone(), (*count).max(0) as usize))
+//                 .collect::<HashMap<_, _>>();
+//             let team = config.strategy().assign(&AssignmentContext {
+//                 teams: &teams,
+//                 teams_player_count: &teams_player_count,
+//                 preferred_team: preferred_team.as_deref(),
+//             });
+//             let id = ID::new();
+//             let new_player = Player {
+//                 id,
+//                 game_id,
+//                 name: name.display.clone(),
+//                 team: team.id.clone(),
+//                 clicks: None,
+//                 avg_latency: None,
+//                 avg_latency_clicks: None,
+//                 rate_limited_clicks: None,
+//                 latency_percentiles: None,
+//                 server_timing: None,
+//             };
+//             let stored_secret = if team.backend.hashes_secret() {
+//                 hashed_secret
+//                     .get_or_init(|| new_stored_secret(true, secret.clone()))
+//                     .await
+//                     .clone()
+//             } else {
+//                 StoredSecret::Plaintext(secret.clone())
+//             };
+//             match dynamodb_put_new_player(
+//                 &new_player,
+//                 &name,
+//                 stored_secret,
+//                 &secret_digest,
+//                 team_counters.get(&team.id).copied(),
+//                 &state,
+//             )
+//             .await
+//             .map_err(from_dynamo_error)?
+//             {
+//                 PlayerCreation::Created => return Ok(new_player),
+//                 PlayerCreation::TeamCountChanged => (),
+//                 PlayerCreation::NameTaken => return Err(player_name_taken()),
+//                 PlayerCreation::GameStateChanged => state = game_state(game_id).await?,
+//             }
+//         }
+//         Err(registration_conflict())
 //     }
 // }
 // This macro replace the whole function by the code commented above
 #[appsync_operation(mutation(registerNewPlayer))]
-pub async fn register_new_player(name: String, secret: String) -> Result<Player, AppsyncError> {
-    // Query DynamoDB to get the current count of players in each team
-    let mut teams_player_count = dynamodb_query_teams_player_count().await?;
-
-    // Choose which team to assign this player to
-    let team = if teams_player_count.len() < Team::COUNT {
-        // If all teams are not yet used, choose one of the unused teams
-        let mut all_teams = HashSet::from(Team::all());
-        while let Some((team, _)) = teams_player_count.pop() {
-            all_teams.remove(&team);
+pub async fn register_new_player(
+    game_id: ID,
+    player: NewPlayerInput,
+    secret: String,
+) -> Result<Player, AppsyncError> {
+    let NewPlayerInput {
+        name,
+        preferred_team,
+    } = player;
+
+    // Names are displayed to every player, make sure they are acceptable
+    let name = PlayerName::parse(&name).map_err(invalid_player_name)?;
+
+    // Make sure the game the player wants to enrol in exists
+    let mut state = game_state(game_id).await?;
+
+    // Banned players cannot come back with the same secret
+    let secret_digest = scoped_digest(&secret, &game_id.to_string());
+    if dynamodb_is_secret_banned(game_id, &secret_digest).await? {
+        return Err(player_banned());
+    }
+
+    // The configuration of the game tells how to choose the team of the player
+    let config = game_config(game_id).await?;
+
+    // The player can only be assigned to one of the current teams
+    let teams = dynamodb_query_teams().await?;
+    if preferred_team
+        .as_deref()
+        .is_some_and(|team_id| Team::find(&teams, team_id).is_none())
+    {
+        return Err(team_not_found());
+    }
+
+    // Hashing is deliberately slow, so the secret is hashed at most once, whatever the number of
+    // attempts below
+    let hashed_secret = tokio::sync::OnceCell::new();
+
+    // The team counters are read and incremented optimistically: if another player joins the
+    // chosen team in the meantime, the registration is retried with the updated counters
+    for _ in 0..MAX_REGISTRATION_ATTEMPTS {
+        // Get the current count of players in each team of the game
+        let team_counters = dynamodb_get_team_counters(game_id, &teams).await?;
+        let teams_player_count = team_counters
+            .iter()
+            .map(|(team_id, count)| (team_id.clone(), (*count).max(0) as usize))
+            .collect::<HashMap<_, _>>();
+
+        // Choose which team to assign this player to
+        let team = config.strategy().assign(&AssignmentContext {
+            teams: &teams,
+            teams_player_count: &teams_player_count,
+            preferred_team: preferred_team.as_deref(),
+        });
+
+        // Generate a new unique ID for this player
+        let id = ID::new();
+
+        // Create the new player record
+        let new_player = Player {
+            id,
+            game_id,
+            name: name.display.clone(),
+            team: team.id.clone(),
+            clicks: None,
+            avg_latency: None,
+            avg_latency_clicks: None,
+            rate_limited_clicks: None,
+            latency_percentiles: None,
+            server_timing: None,
+        };
+
+        // Save the new player to DynamoDB along with their secret, provided the team counter
+        // did not change and nobody took the name
+        let stored_secret = if team.backend.hashes_secret() {
+            hashed_secret
+                .get_or_init(|| new_stored_secret(true, secret.clone()))
+                .await
+                .clone()
+        } else {
+            StoredSecret::Plaintext(secret.clone())
+        };
+        match dynamodb_put_new_player(
+            &new_player,
+            &name,
+            stored_secret,
+            &secret_digest,
+            team_counters.get(&team.id).copied(),
+            &state,
+        )
+        .await?
+        {
+            // Return the newly created player
+            PlayerCreation::Created => return Ok(new_player),
+            PlayerCreation::TeamCountChanged => (),
+            PlayerCreation::NameTaken => return Err(player_name_taken()),
+            PlayerCreation::GameStateChanged => state = game_state(game_id).await?,
         }
-        // Get the first unused team
-        all_teams
-            .into_iter()
-            .next()
-            .expect("we ensured teams_player_count had less element than all_teams")
+    }
+
+    Err(registration_conflict())
+}
+
+/// Returns how `secret` must be stored, hashed or not
+///
+/// The secret must be hashed if the backend of the team of the player can verify it
+/// (see [crate::Backend::hashes_secret])
+async fn new_stored_secret(hashed: bool, secret: String) -> StoredSecret {
+    if hashed {
+        StoredSecret::Hashed(run_blocking(move || hash_secret(&secret)).await)
     } else {
-        // If all teams are used, choose the one with the fewest players
-        teams_player_count.sort_by_key(|o| o.1);
-        teams_player_count[0].0
-    };
+        StoredSecret::Plaintext(secret)
+    }
+}
 
-    // Generate a new unique ID for this player
-    let id = ID::new();
+/// Returns whether the players of the team `team_id` have their secret stored hashed
+///
+/// The players of a team that does not exist anymore keep their secret as it is
+async fn team_hashes_secret(team_id: &str) -> Result<bool, AppsyncError> {
+    Ok(dynamodb_get_team(team_id.to_owned())
+        .await?
+        .is_some_and(|team| team.backend.hashes_secret()))
+}
 
-    // Create the new player record
-    let new_player = Player {
-        id,
-        name,
-        team,
-        clicks: None,
-        avg_latency: None,
-        avg_latency_clicks: None,
-    };
+/// Verifies the `secret` of a player against the stored one
+///
+/// Returns the player, the stored secret to condition the following update on, and the hash
+/// of `secret` if the stored secret is still in plaintext and must be migrated
+async fn verify_player_secret(
+    player_id: ID,
+    secret: String,
+) -> Result<(Player, StoredSecret, Option<String>), AppsyncError> {
+    let (player, stored_secret) = dynamodb_get_player_with_secret(player_id)
+        .await?
+        .ok_or_else(player_not_found)?;
+    // No secret can match a player who has none
+    let stored_secret = stored_secret.ok_or_else(invalid_secret)?;
 
-    // Save the new player to DynamoDB
-    dynamodb_put_new_player(&new_player, secret).await?;
+    let migrate = stored_secret.is_plaintext() && team_hashes_secret(&player.team).await?;
+    let (valid, stored_secret, new_secret_hash) = run_blocking(move || {
+        let valid = stored_secret.verify(&secret);
+        let new_secret_hash = (valid && migrate).then(|| hash_secret(&secret));
+        (valid, stored_secret, new_secret_hash)
+    })
+    .await;
 
-    // Return the newly created player
-    Ok(new_player)
+    if valid {
+        Ok((player, stored_secret, new_secret_hash))
+    } else {
+        Err(invalid_secret())
+    }
 }
 
 // impl crate::Operation {
 //     pub async fn mutation_update_player_name(
+//         game_id: ID,
 //         player_id: ID,
 //         new_name: String,
 //         secret: String,
@@ -191,36 +712,146 @@ pub async fn register_new_player(name: String, secret: String) -> Result<Player,
 //         // the correct signature for the function. Should be optimized away by the compiler.
 //         if false {
 //             return <crate::Operation as crate::DefautOperations>::mutation_update_player_name(
-//                 player_id, new_name, secret,
+//                 game_id, player_id, new_name, secret,
 //             )
 //             .await;
 //         }
-//         Ok(dynamodb_update_player_name(player_id, new_name, secret)
+//         let new_name = PlayerName::parse(&new_name).map_err(invalid_player_name)?;
+//         let (player, stored_secret, new_secret_hash) =
+//             verify_player_secret(player_id, secret).await?;
+//         if player.game_id != game_id {
+//             return Err(player_not_found());
+//         }
+//         match dynamodb_update_player_name(player, new_name, &stored_secret, new_secret_hash)
 //             .await
-//             .map_err(from_dynamo_error)?)
+//             .map_err(from_dynamo_error)?
+//         {
+//             PlayerRename::Renamed(player) => Ok(player),
+//             PlayerRename::NameTaken => Err(player_name_taken()),
+//             PlayerRename::Banned => Err(player_banned()),
+//             PlayerRename::PlayerChanged => Err(player_changed()),
+//         }
 //     }
 // }
 // This macro replace the whole function by the code commented above
 #[appsync_operation(mutation(updatePlayerName))]
 pub async fn update_player_name(
+    game_id: ID,
     player_id: ID,
     new_name: String,
     secret: String,
 ) -> Result<Player, AppsyncError> {
-    Ok(dynamodb_update_player_name(player_id, new_name, secret).await?)
+    let new_name = PlayerName::parse(&new_name).map_err(invalid_player_name)?;
+    let (player, stored_secret, new_secret_hash) = verify_player_secret(player_id, secret).await?;
+    // Players of other games are out of reach, as if they did not exist
+    if player.game_id != game_id {
+        return Err(player_not_found());
+    }
+    // The name is reserved transactionally, so two players cannot take it at the same time
+    match dynamodb_update_player_name(player, new_name, &stored_secret, new_secret_hash).await? {
+        PlayerRename::Renamed(player) => Ok(player),
+        PlayerRename::NameTaken => Err(player_name_taken()),
+        PlayerRename::Banned => Err(player_banned()),
+        PlayerRename::PlayerChanged => Err(player_changed()),
+    }
 }
 
 // impl crate::Operation {
-//     pub async fn mutation_remove_player(player_id: ID) -> Result<Player, AppSyncError> {
+//     pub async fn mutation_rotate_player_secret(
+//         player_id: ID,
+//         old_secret: String,
+//         new_secret: String,
+//     ) -> Result<Player, AppSyncError> {
 //         // This is just a marker to ensure an error is thrown if the user did not chose
 //         // the correct signature for the function. Should be optimized away by the compiler.
 //         if false {
-//             return <crate::Operation as crate::DefautOperations>::mutation_remove_player(
+//             return <crate::Operation as crate::DefautOperations>::mutation_rotate_player_secret(
+//                 player_id, old_secret, new_secret,
+//             )
+//             .await;
+//         }
+//         let (player, stored_secret, _) = verify_player_secret(player_id, old_secret).await?;
+//         let new_secret = new_stored_secret(team_hashes_secret(&player.team).await?, new_secret).await;
+//         match dynamodb_set_player_secret(player_id, Some(&stored_secret), new_secret)
+//             .await
+//             .map_err(from_dynamo_error)?
+//         {
+//             SecretUpdate::Updated(player) => Ok(player),
+//             SecretUpdate::Banned => Err(player_banned()),
+//             SecretUpdate::PlayerChanged => Err(player_changed()),
+//         }
+//     }
+// }
+// This macro replace the whole function by the code commented above
+#[appsync_operation(mutation(rotatePlayerSecret))]
+pub async fn rotate_player_secret(
+    player_id: ID,
+    old_secret: String,
+    new_secret: String,
+) -> Result<Player, AppsyncError> {
+    // The player must prove they know their current secret
+    let (player, stored_secret, _) = verify_player_secret(player_id, old_secret).await?;
+    let new_secret = new_stored_secret(team_hashes_secret(&player.team).await?, new_secret).await;
+    // Only replace the secret if it did not change since it was verified
+    match dynamodb_set_player_secret(player_id, Some(&stored_secret), new_secret).await? {
+        SecretUpdate::Updated(player) => Ok(player),
+        SecretUpdate::Banned => Err(player_banned()),
+        SecretUpdate::PlayerChanged => Err(player_changed()),
+    }
+}
+
+// impl crate::Operation {
+//     pub async fn mutation_reset_player_secret(player_id: ID) -> Result<String, AppSyncError> {
+//         // This is just a marker to ensure an error is thrown if the user did not chose
+//         // the correct signature for the function. Should be optimized away by the compiler.
+//         if false {
+//             return <crate::Operation as crate::DefautOperations>::mutation_reset_player_secret(
 //                 player_id,
 //             )
 //             .await;
 //         }
-//         Ok(dynamodb_delete_player(player_id)
+//         let (player, _) = dynamodb_get_player_with_secret(player_id)
+//             .await
+//             .map_err(from_dynamo_error)?
+//             .ok_or_else(player_not_found)?;
+//         let secret = ID::new().to_string();
+//         let new_secret =
+//             new_stored_secret(team_hashes_secret(&player.team).await?, secret.clone()).await;
+//         dynamodb_set_player_secret(player_id, None, new_secret)
+//             .await
+//             .map_err(from_dynamo_error)?;
+//         Ok(secret)
+//     }
+// }
+// This macro replace the whole function by the code commented above
+#[appsync_operation(mutation(resetPlayerSecret))]
+pub async fn reset_player_secret(player_id: ID) -> Result<String, AppsyncError> {
+    let (player, _) = dynamodb_get_player_with_secret(player_id)
+        .await?
+        .ok_or_else(player_not_found)?;
+
+    // Generate a new secret the same way the website does, and only return it this once
+    let secret = ID::new().to_string();
+    let new_secret =
+        new_stored_secret(team_hashes_secret(&player.team).await?, secret.clone()).await;
+    dynamodb_set_player_secret(player_id, None, new_secret).await?;
+    Ok(secret)
+}
+
+// impl crate::Operation {
+//     pub async fn mutation_remove_player(
+//         game_id: ID,
+//         player_id: ID,
+//     ) -> Result<Player, AppSyncError> {
+//         // This is just a marker to ensure an error is thrown if the user did not chose
+//         // the correct signature for the function. Should be optimized away by the compiler.
+//         if false {
+//             return <crate::Operation as crate::DefautOperations>::mutation_remove_player(
+//                 game_id, player_id,
+//             )
+//             .await;
+//         }
+//         Ok(dynamodb_delete_player(game_id, player_id)
 //             .await
 //             .map_err(from_dynamo_error)?
 //             .ok_or_else(player_not_found)?)
@@ -228,8 +859,145 @@ pub async fn update_player_name(
 // }
 // This macro replace the whole function by the code commented above
 #[appsync_operation(mutation(removePlayer))]
-pub async fn remove_player(player_id: ID) -> Result<Player, AppsyncError> {
-    Ok(dynamodb_delete_player(player_id)
+pub async fn remove_player(game_id: ID, player_id: ID) -> Result<Player, AppsyncError> {
+    Ok(dynamodb_delete_player(game_id, player_id)
         .await?
         .ok_or_else(player_not_found)?)
 }
+
+#[appsync_operation(query(bans))]
+pub async fn bans(game_id: ID) -> Result<Vec<Ban>, AppsyncError> {
+    Ok(dynamodb_query_bans(game_id).await?)
+}
+
+#[appsync_operation(mutation(banPlayer))]
+pub async fn ban_player(player_id: ID, reason: String) -> Result<Ban, AppsyncError> {
+    dynamodb_ban_player(player_id, reason, AWSTimestamp::now())
+        .await?
+        .ok_or_else(player_not_found)
+}
+
+#[appsync_operation(mutation(unbanPlayer))]
+pub async fn unban_player(player_id: ID) -> Result<Ban, AppsyncError> {
+    dynamodb_unban_player(player_id)
+        .await?
+        .ok_or_else(ban_not_found)
+}
+
+/// Returns the team `team_id`, provided players can currently join it
+async fn joinable_team(team_id: &str) -> Result<Team, AppsyncError> {
+    dynamodb_query_teams()
+        .await?
+        .into_iter()
+        .find(|team| team.id == team_id)
+        .ok_or_else(team_not_found)
+}
+
+/// Moves a player to `new_team`, provided the game status allows it
+///
+/// If `new_secret` is provided, it replaces the stored secret of the player
+async fn move_player(
+    player: Player,
+    stored_secret: StoredSecret,
+    new_team: Team,
+    new_secret: Option<StoredSecret>,
+) -> Result<Player, AppsyncError> {
+    if player.team == new_team.id {
+        // Nothing to move, but the same game status rule applies
+        let state = game_state(player.game_id).await?;
+        return if GameStatus::TEAM_CHANGE_STATUSES.contains(&state.status) {
+            Ok(player)
+        } else {
+            Err(invalid_game_status())
+        };
+    }
+    match dynamodb_move_player(player, &stored_secret, new_team.id, new_secret).await? {
+        PlayerMove::Moved(player) => Ok(player),
+        PlayerMove::InvalidGameStatus => Err(invalid_game_status()),
+        PlayerMove::Banned => Err(player_banned()),
+        PlayerMove::PlayerChanged => Err(player_changed()),
+    }
+}
+
+// impl crate::Operation {
+//     pub async fn mutation_switch_team(
+//         player_id: ID,
+//         secret: String,
+//         team: String,
+//     ) -> Result<Player, AppSyncError> {
+//         // This is just a marker to ensure an error is thrown if the user did not chose
+//         // the correct signature for the function. Should be optimized away by the compiler.
+//         if false {
+//             return <crate::Operation as crate::DefautOperations>::mutation_switch_team(
+//                 player_id, secret, team,
+//             )
+//             .await;
+//         }
+//         let new_team = joinable_team(&team).await?;
+//         let (player, stored_secret, _) = verify_player_secret(player_id, secret.clone()).await?;
+//         let hashed = new_team.backend.hashes_secret();
+//         let new_secret = if hashed == stored_secret.is_plaintext() {
+//             Some(new_stored_secret(hashed, secret).await)
+//         } else {
+//             None
+//         };
+//         move_player(player, stored_secret, new_team, new_secret).await
+//     }
+// }
+// This macro replace the whole function by the code commented above
+#[appsync_operation(mutation(switchTeam))]
+pub async fn switch_team(
+    player_id: ID,
+    secret: String,
+    team: String,
+) -> Result<Player, AppsyncError> {
+    let new_team = joinable_team(&team).await?;
+    let (player, stored_secret, _) = verify_player_secret(player_id, secret.clone()).await?;
+    // The player provided their secret, so it can be stored the way the new team expects
+    let hashed = new_team.backend.hashes_secret();
+    let new_secret = if hashed == stored_secret.is_plaintext() {
+        Some(new_stored_secret(hashed, secret).await)
+    } else {
+        None
+    };
+    move_player(player, stored_secret, new_team, new_secret).await
+}
+
+// impl crate::Operation {
+//     pub async fn mutation_move_player(player_id: ID, team: String) -> Result<Player, AppSyncError> {
+//         // This is just a marker to ensure an error is thrown if the user did not chose
+//         // the correct signature for the function. Should be optimized away by the compiler.
+//         if false {
+//             return <crate::Operation as crate::DefautOperations>::mutation_move_player(
+//                 player_id, team,
+//             )
+//             .await;
+//         }
+//         let new_team = joinable_team(&team).await?;
+//         let (player, stored_secret) = dynamodb_get_player_with_secret(player_id)
+//             .await
+//             .map_err(from_dynamo_error)?
+//             .ok_or_else(player_not_found)?;
+//         let stored_secret = stored_secret.ok_or_else(missing_secret)?;
+//         if !stored_secret.is_plaintext() && !new_team.backend.hashes_secret() {
+//             return Err(secret_not_transferable());
+//         }
+//         move_player(player, stored_secret, new_team, None).await
+//     }
+// }
+// This macro replace the whole function by the code commented above
+#[appsync_operation(mutation(movePlayer))]
+pub async fn move_player_by_admin(player_id: ID, team: String) -> Result<Player, AppsyncError> {
+    let new_team = joinable_team(&team).await?;
+    let (player, stored_secret) = dynamodb_get_player_with_secret(player_id)
+        .await?
+        .ok_or_else(player_not_found)?;
+    // The move is conditioned on the secret being unchanged, so the player must have one
+    let stored_secret = stored_secret.ok_or_else(missing_secret)?;
+    // A hashed secret cannot be turned back into the plaintext the other backends compare.
+    // A plaintext one is fine anywhere, it is hashed on its first use by a hashing backend.
+    if !stored_secret.is_plaintext() && !new_team.backend.hashes_secret() {
+        return Err(secret_not_transferable());
+    }
+    move_player(player, stored_secret, new_team, None).await
+}
@@ -0,0 +1,190 @@
+use std::sync::LazyLock;
+
+use lambda_appsync::log;
+use unicode_normalization::UnicodeNormalization;
+
+/// Shortest name a player can have, in characters
+pub const MIN_NAME_LEN: usize = 2;
+/// Longest name a player can have, in characters
+pub const MAX_NAME_LEN: usize = 24;
+
+/// Terms player names cannot contain, read from the environment
+///
+/// `PLAYER_NAME_BLOCKLIST` is a comma-separated list of terms (default: empty). Terms are
+/// compared with the normalized names (see [PlayerName::normalized]), so they are normalized
+/// the same way, and split into words (see [words]): a name contains a term when it contains
+/// its words in a row, as whole words.
+static NAME_BLOCKLIST: LazyLock<Vec<Vec<String>>> = LazyLock::new(|| {
+    let blocklist = std::env::var("PLAYER_NAME_BLOCKLIST")
+        .unwrap_or_default()
+        .split(',')
+        .map(|term| {
+            words(&normalize(term))
+                .map(str::to_owned)
+                .collect::<Vec<_>>()
+        })
+        .filter(|term| !term.is_empty())
+        .collect::<Vec<_>>();
+    log::debug!("NAME_BLOCKLIST has {} terms", blocklist.len());
+    blocklist
+});
+
+/// A valid player name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlayerName {
+    /// The name as displayed: NFC normalized, without control or invisible characters, and
+    /// with whitespace collapsed to single spaces
+    pub display: String,
+    /// The name as compared with the other names: NFKC normalized and lowercase, so names
+    /// only differing by case or by compatibility variants of their characters (e.g. fullwidth
+    /// letters) are the same
+    ///
+    /// Other lookalike characters, such as a Cyrillic "а" in place of a Latin "a", are not
+    /// folded, so they still make a different name.
+    pub normalized: String,
+}
+
+impl PlayerName {
+    /// Cleans up `name` and checks it is acceptable
+    ///
+    /// Returns the reason the name is invalid, if any
+    pub fn parse(name: &str) -> Result<Self, String> {
+        Self::parse_with_blocklist(name, &NAME_BLOCKLIST)
+    }
+
+    fn parse_with_blocklist(name: &str, blocklist: &[Vec<String>]) -> Result<Self, String> {
+        let display = clean(name);
+        let len = display.chars().count();
+        if !(MIN_NAME_LEN..=MAX_NAME_LEN).contains(&len) {
+            return Err(format!(
+                "Player name must be {MIN_NAME_LEN} to {MAX_NAME_LEN} characters long"
+            ));
+        }
+        let normalized = normalize(&display);
+        let name_words = words(&normalized).collect::<Vec<_>>();
+        if blocklist
+            .iter()
+            .any(|term| name_words.windows(term.len()).any(|window| window == term))
+        {
+            return Err("Player name is not allowed".to_owned());
+        }
+        Ok(PlayerName {
+            display,
+            normalized,
+        })
+    }
+}
+
+/// Returns whether `c` is invisible and could be used to make a name look like another
+fn is_invisible(c: char) -> bool {
+    matches!(c,
+        '\u{00AD}' // Soft hyphen
+        | '\u{200B}'..='\u{200F}' // Zero width spaces and joiners, direction marks
+        | '\u{202A}'..='\u{202E}' // Bidirectional embeddings and overrides
+        | '\u{2060}'..='\u{2064}' // Word joiner and invisible operators
+        | '\u{2066}'..='\u{2069}' // Bidirectional isolates
+        | '\u{FEFF}' // Zero width no-break space
+    )
+}
+
+/// Returns `name` NFC normalized, without control or invisible characters, and with
+/// whitespace collapsed to single spaces
+fn clean(name: &str) -> String {
+    name.nfc()
+        .filter(|c| c.is_whitespace() || !(c.is_control() || is_invisible(*c)))
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Returns the words of a normalized name, separated by any character that is not a letter or a
+/// digit (e.g. "the_admin" is made of "the" and "admin")
+fn words(normalized: &str) -> impl Iterator<Item = &str> {
+    normalized
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+}
+
+/// Returns the form of `name` used to compare it with other names
+///
+/// Also used for the names of players registered before names were validated, which may not
+/// be clean.
+pub fn normalize(name: &str) -> String {
+    clean(name).nfkc().collect::<String>().to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(name: &str) -> Result<PlayerName, String> {
+        PlayerName::parse_with_blocklist(
+            name,
+            &[
+                vec!["admin".to_owned()],
+                vec!["game".to_owned(), "master".to_owned()],
+            ],
+        )
+    }
+
+    #[test]
+    fn test_name_is_cleaned_up() {
+        let name = parse("  Jean\u{200B}\t\n Dupont\u{0007} ").unwrap();
+        assert_eq!(name.display, "Jean Dupont");
+        assert_eq!(name.normalized, "jean dupont");
+
+        // Decomposed characters are composed
+        let name = parse("Jose\u{0301}").unwrap();
+        assert_eq!(name.display, "Jos\u{00E9}");
+    }
+
+    #[test]
+    fn test_name_length_bounds() {
+        assert!(parse("").is_err());
+        assert!(parse(" \u{200B} ").is_err());
+        assert!(parse("A").is_err());
+        assert!(parse("Al").is_ok());
+        assert!(parse(&"é".repeat(MAX_NAME_LEN)).is_ok());
+        assert!(parse(&"é".repeat(MAX_NAME_LEN + 1)).is_err());
+    }
+
+    #[test]
+    fn test_compatibility_variants_are_the_same() {
+        let normalized = parse("Player1").unwrap().normalized;
+        assert_eq!(parse("PLAYER1").unwrap().normalized, normalized);
+        // Fullwidth letters and digit
+        assert_eq!(
+            parse("\u{FF30}layer\u{FF11}").unwrap().normalized,
+            normalized
+        );
+        assert_eq!(parse("Play\u{200D}er1").unwrap().normalized, normalized);
+        assert_ne!(parse("Player2").unwrap().normalized, normalized);
+    }
+
+    #[test]
+    fn test_blocklist() {
+        assert!(parse("Admin").is_err());
+        assert!(parse("the_ADMIN").is_err());
+        assert!(parse("\u{FF21}dmin").is_err());
+        assert!(parse("Adm in").is_ok());
+        // Terms are only matched as whole words
+        assert!(parse("Badminton").is_ok());
+        assert!(parse("Admins").is_ok());
+        assert!(parse("Admin-42").is_err());
+        // Terms of several words match those words in a row
+        assert!(parse("Game Master").is_err());
+        assert!(parse("the.game_master").is_err());
+        assert!(parse("Gamemaster").is_ok());
+        assert!(parse("Master of the game").is_ok());
+    }
+
+    #[test]
+    fn test_words() {
+        assert_eq!(
+            words("the_admin, 42!").collect::<Vec<_>>(),
+            ["the", "admin", "42"]
+        );
+        assert_eq!(words(" _-").count(), 0);
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+
+use lambda_appsync::AWSTimestamp;
+use latency_histogram::LatencyHistogram;
+
+use crate::{GameRound, GameState, LatencyPercentiles, Player, Team, TeamResult};
+
+impl TeamResult {
+    /// Aggregates the final scores of the `players` belonging to the team `team_id`
+    pub fn from_players<'a>(team_id: &str, players: impl Iterator<Item = &'a Player>) -> Self {
+        let mut result = TeamResult {
+            team: team_id.to_owned(),
+            players: 0,
+            clicks: 0,
+            avg_latency: None,
+            avg_latency_clicks: 0,
+            latency_percentiles: None,
+        };
+        let mut total_latency = 0f64;
+        for player in players.filter(|p| p.team == team_id) {
+            result.players += 1;
+            result.clicks += player.clicks.unwrap_or_default();
+            if let (Some(avg_latency), Some(avg_latency_clicks)) =
+                (player.avg_latency, player.avg_latency_clicks)
+            {
+                total_latency += avg_latency * (avg_latency_clicks as f64);
+                result.avg_latency_clicks += avg_latency_clicks;
+            }
+        }
+        if result.avg_latency_clicks > 0 {
+            result.avg_latency = Some(total_latency / (result.avg_latency_clicks as f64));
+        }
+        result
+    }
+
+    /// Aggregates the scores of `players` for each of the `teams`, and for the teams that
+    /// players are still part of but are not available anymore
+    ///
+    /// The latency percentiles of each team are computed from its histogram in
+    /// `latency_histograms`, by team ID.
+    pub fn for_teams(
+        teams: &[Team],
+        players: &[Player],
+        latency_histograms: &HashMap<String, LatencyHistogram>,
+    ) -> Vec<Self> {
+        let mut team_ids = teams
+            .iter()
+            .map(|team| team.id.as_str())
+            .collect::<Vec<_>>();
+        for player in players {
+            if !team_ids.contains(&player.team.as_str()) {
+                team_ids.push(&player.team);
+            }
+        }
+        team_ids
+            .into_iter()
+            .map(|team_id| TeamResult {
+                latency_percentiles: latency_histograms
+                    .get(team_id)
+                    .and_then(LatencyPercentiles::from_histogram),
+                ..TeamResult::from_players(team_id, players.iter())
+            })
+            .collect()
+    }
+}
+
+impl GameRound {
+    /// Creates the archive of the round described by `state`, stopped at `stopped_at`,
+    /// with the final scores of `players` in `teams` and the latency histograms of the teams
+    pub fn new(
+        id: i32,
+        state: &GameState,
+        stopped_at: AWSTimestamp,
+        teams: &[Team],
+        players: Vec<Player>,
+        latency_histograms: &HashMap<String, LatencyHistogram>,
+    ) -> Self {
+        let teams = TeamResult::for_teams(teams, &players, latency_histograms);
+        GameRound {
+            game_id: state.game_id,
+            id,
+            started_at: state.started_at,
+            stopped_at,
+            paused_seconds: state.paused_seconds,
+            teams,
+            players,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lambda_appsync::ID;
+
+    use super::*;
+    use crate::GameStatus;
+    use crate::test_fixtures::player;
+
+    #[test]
+    fn test_new_round_aggregates_teams() {
+        let state = GameState {
+            game_id: ID::new(),
+            status: GameStatus::Stopped,
+            starts_at: None,
+            started_at: Some(AWSTimestamp::from(100)),
+            paused_at: None,
+            paused_seconds: Some(5),
+        };
+        let players = vec![
+            player("player", "RUST", Some(10), Some((20.0, 10))),
+            player("player", "RUST", Some(30), Some((40.0, 30))),
+            player("player", "JS", Some(4), None),
+            player("player", "VTL", None, None),
+            // The team of this player is not available anymore
+            player("player", "GO", Some(7), None),
+        ];
+        let mut rust_histogram = LatencyHistogram::default();
+        rust_histogram.add(LatencyHistogram::bucket_of(20.0), 10);
+        rust_histogram.add(LatencyHistogram::bucket_of(40.0), 30);
+        let round = GameRound::new(
+            3,
+            &state,
+            AWSTimestamp::from(200),
+            &Team::builtin(),
+            players,
+            &HashMap::from([("RUST".to_owned(), rust_histogram.clone())]),
+        );
+
+        assert_eq!(round.game_id, state.game_id);
+        assert_eq!(round.id, 3);
+        assert_eq!(round.started_at, Some(AWSTimestamp::from(100)));
+        assert_eq!(round.stopped_at, AWSTimestamp::from(200));
+        assert_eq!(round.paused_seconds, Some(5));
+        assert_eq!(round.players.len(), 5);
+        assert_eq!(round.teams.len(), Team::builtin().len() + 1);
+
+        let team = |team: &str| round.teams.iter().find(|t| t.team == team).unwrap();
+        let rust = team("RUST");
+        assert_eq!(
+            (rust.players, rust.clicks, rust.avg_latency_clicks),
+            (2, 40, 40)
+        );
+        assert_eq!(rust.avg_latency, Some(35.0));
+        let percentiles = rust.latency_percentiles.as_ref().unwrap();
+        // Three quarters of the clicks are in the bucket of 40 ms
+        assert_eq!(percentiles.p50, rust_histogram.quantile(0.5).unwrap());
+        assert!((40.0..40.0 * 1.19).contains(&percentiles.p50));
+        assert_eq!(percentiles.p99, percentiles.p50);
+        let js = team("JS");
+        assert_eq!((js.players, js.clicks, js.avg_latency), (1, 4, None));
+        assert!(js.latency_percentiles.is_none());
+        let vtl = team("VTL");
+        assert_eq!((vtl.players, vtl.clicks), (1, 0));
+        let python = team("PYTHON");
+        assert_eq!((python.players, python.clicks), (0, 0));
+        let go = team("GO");
+        assert_eq!((go.players, go.clicks), (1, 7));
+    }
+}
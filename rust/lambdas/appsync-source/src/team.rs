@@ -0,0 +1,101 @@
+use builtin_teams::BUILTIN_TEAMS;
+
+use crate::{Backend, Team, TeamInput};
+
+impl Backend {
+    /// Returns whether the players of the teams served by the backend have their secret
+    /// stored hashed, see [builtin_teams::hashes_secret]
+    pub fn hashes_secret(self) -> bool {
+        builtin_teams::hashes_secret(&self.to_string())
+    }
+}
+
+impl Team {
+    /// Longest ID a team can have
+    pub const MAX_ID_LEN: usize = 32;
+
+    /// The teams always available, unless a stored team replaces them, see [BUILTIN_TEAMS]
+    pub fn builtin() -> Vec<Team> {
+        BUILTIN_TEAMS
+            .iter()
+            .map(|team| Team {
+                id: team.id.to_owned(),
+                name: team.name.to_owned(),
+                backend: team
+                    .id
+                    .parse()
+                    .expect("builtin teams are named after a backend"),
+            })
+            .collect()
+    }
+
+    /// Finds the team `team_id` among `teams`
+    pub fn find<'a>(teams: &'a [Team], team_id: &str) -> Option<&'a Team> {
+        teams.iter().find(|team| team.id == team_id)
+    }
+}
+
+impl TeamInput {
+    /// Checks the team can be stored
+    ///
+    /// Returns the reason the team is invalid, if any
+    pub fn validate(&self) -> Result<(), String> {
+        // The ID ends up in DynamoDB keys and GraphQL arguments, keep it simple
+        let valid_id = !self.id.is_empty()
+            && self.id.len() <= Team::MAX_ID_LEN
+            && self
+                .id
+                .chars()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_');
+        if !valid_id {
+            return Err(format!(
+                "Team ID must be 1 to {} uppercase letters, digits or underscores",
+                Team::MAX_ID_LEN
+            ));
+        }
+        if self.name.trim().is_empty() {
+            return Err("Team name cannot be empty".to_owned());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hashes_secret() {
+        assert!(Backend::Rust.hashes_secret());
+        assert!(Backend::Python.hashes_secret());
+        assert!(!Backend::Js.hashes_secret());
+    }
+
+    #[test]
+    fn test_builtin_teams() {
+        let teams = Team::builtin();
+        assert_eq!(teams.len(), Backend::COUNT);
+        let rust = Team::find(&teams, "RUST").unwrap();
+        assert_eq!(rust.backend, Backend::Rust);
+        assert!(Team::find(&teams, "GO").is_none());
+    }
+
+    #[test]
+    fn test_validate_team_input() {
+        let input = |id: &str, name: &str| TeamInput {
+            id: id.to_owned(),
+            name: name.to_owned(),
+            backend: Backend::Rust,
+        };
+        assert!(input("RUST_2", "Team Rust 2").validate().is_ok());
+        assert!(input("", "Team").validate().is_err());
+        assert!(input("rust", "Team").validate().is_err());
+        assert!(input("RUST#2", "Team").validate().is_err());
+        assert!(
+            input(&"A".repeat(Team::MAX_ID_LEN + 1), "Team")
+                .validate()
+                .is_err()
+        );
+        assert!(input("GO", "  ").validate().is_err());
+    }
+}
@@ -0,0 +1,308 @@
+use std::collections::HashMap;
+
+use lambda_appsync::ID;
+
+use crate::{GameConfig, Team, TeamAssignment, TeamWeight};
+
+/// What a [TeamAssignmentStrategy] knows when a new player registers
+pub struct AssignmentContext<'a> {
+    /// Teams a player can be assigned to, never empty
+    pub teams: &'a [Team],
+    /// Number of players already in each team of the game, by team ID
+    pub teams_player_count: &'a HashMap<String, usize>,
+    /// ID of the team the player asked for, if any
+    pub preferred_team: Option<&'a str>,
+}
+
+impl<'a> AssignmentContext<'a> {
+    /// Number of players already in `team`
+    fn count(&self, team: &Team) -> usize {
+        self.teams_player_count
+            .get(&team.id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Total number of players already in the game
+    fn total(&self) -> usize {
+        self.teams_player_count.values().sum()
+    }
+}
+
+/// Chooses the team of a new player, among [AssignmentContext::teams]
+pub trait TeamAssignmentStrategy {
+    fn assign<'a>(&self, ctx: &AssignmentContext<'a>) -> &'a Team;
+}
+
+/// Puts the player in the team with the fewest players
+///
+/// The preferred team is honoured if it is one of the teams with the fewest players.
+pub struct Balanced;
+
+impl TeamAssignmentStrategy for Balanced {
+    fn assign<'a>(&self, ctx: &AssignmentContext<'a>) -> &'a Team {
+        Weighted::even(ctx.teams).assign(ctx)
+    }
+}
+
+/// Puts the players in each team in turn, in registration order
+///
+/// The preferred team is ignored.
+pub struct RoundRobin;
+
+impl TeamAssignmentStrategy for RoundRobin {
+    fn assign<'a>(&self, ctx: &AssignmentContext<'a>) -> &'a Team {
+        &ctx.teams[ctx.total() % ctx.teams.len()]
+    }
+}
+
+/// Puts the player in a pseudo-random team, reproducible from the seed and the registration order
+///
+/// The preferred team is ignored.
+pub struct SeededRandom {
+    pub seed: u64,
+}
+
+impl SeededRandom {
+    /// SplitMix64, to derive a well distributed number from the seed and the registration index
+    fn mix(&self, index: u64) -> u64 {
+        let mut z = self
+            .seed
+            .wrapping_add(index.wrapping_add(1).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+impl TeamAssignmentStrategy for SeededRandom {
+    fn assign<'a>(&self, ctx: &AssignmentContext<'a>) -> &'a Team {
+        &ctx.teams[(self.mix(ctx.total() as u64) % ctx.teams.len() as u64) as usize]
+    }
+}
+
+/// Fills the teams in proportion of their weight, putting the player in the team that is the
+/// furthest below its share. Teams with a weight of 0 get no player.
+///
+/// The preferred team is honoured if it is one of the teams the furthest below their share.
+pub struct Weighted {
+    /// Weight of each team, by team ID
+    weights: HashMap<String, u32>,
+}
+
+impl Weighted {
+    /// Creates the strategy from the team weights, teams without a weight counting as 0
+    pub fn new(team_weights: &[TeamWeight]) -> Self {
+        Weighted {
+            weights: team_weights
+                .iter()
+                .map(|tw| (tw.team.clone(), tw.weight.max(0) as u32))
+                .collect(),
+        }
+    }
+
+    /// All the `teams` with the same weight
+    fn even(teams: &[Team]) -> Self {
+        Weighted {
+            weights: teams.iter().map(|team| (team.id.clone(), 1)).collect(),
+        }
+    }
+}
+
+impl TeamAssignmentStrategy for Weighted {
+    fn assign<'a>(&self, ctx: &AssignmentContext<'a>) -> &'a Team {
+        // The fill ratio of a team is count / weight. Ratios are compared as
+        // count_a * weight_b < count_b * weight_a to stay in integers.
+        let candidates = ctx
+            .teams
+            .iter()
+            .filter_map(|team| {
+                let weight = self.weights.get(&team.id).copied().unwrap_or_default();
+                (weight > 0).then_some((team, ctx.count(team) as u64, u64::from(weight)))
+            })
+            .collect::<Vec<_>>();
+        let Some(&(_, min_count, min_weight)) = candidates
+            .iter()
+            .min_by(|(_, ca, wa), (_, cb, wb)| (ca * wb).cmp(&(cb * wa)))
+        else {
+            // No team has a weight: fall back to the even split
+            return Balanced.assign(ctx);
+        };
+        let is_least_filled =
+            |&(_, count, weight): &(&Team, u64, u64)| count * min_weight == min_count * weight;
+        // Among the least filled teams, take the preferred one if possible, else the first one
+        candidates
+            .iter()
+            .filter(|c| is_least_filled(c))
+            .find(|(team, _, _)| Some(team.id.as_str()) == ctx.preferred_team)
+            .or_else(|| candidates.iter().find(|c| is_least_filled(c)))
+            .map(|(team, _, _)| *team)
+            .expect("there is at least one candidate")
+    }
+}
+
+/// Puts the player in the team they asked for, or in the team with the fewest players
+/// if they did not ask for an existing one
+pub struct PlayerChosen;
+
+impl TeamAssignmentStrategy for PlayerChosen {
+    fn assign<'a>(&self, ctx: &AssignmentContext<'a>) -> &'a Team {
+        ctx.preferred_team
+            .and_then(|team_id| Team::find(ctx.teams, team_id))
+            .unwrap_or_else(|| Balanced.assign(ctx))
+    }
+}
+
+impl GameConfig {
+    /// The configuration of a game that has never been configured
+    pub fn default_for(game_id: ID) -> Self {
+        GameConfig {
+            game_id,
+            team_assignment: TeamAssignment::Balanced,
+            seed: None,
+            team_weights: vec![],
+        }
+    }
+
+    /// Returns the team assignment strategy of the game
+    pub fn strategy(&self) -> Box<dyn TeamAssignmentStrategy + Send + Sync> {
+        match self.team_assignment {
+            TeamAssignment::Balanced => Box::new(Balanced),
+            TeamAssignment::RoundRobin => Box::new(RoundRobin),
+            TeamAssignment::Random => Box::new(SeededRandom {
+                seed: self.seed.unwrap_or_default() as u64,
+            }),
+            TeamAssignment::Weighted => Box::new(Weighted::new(&self.team_weights)),
+            TeamAssignment::PlayerChosen => Box::new(PlayerChosen),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Backend;
+
+    fn teams() -> Vec<Team> {
+        Team::builtin()
+    }
+
+    fn counts(counts: &[(&str, usize)]) -> HashMap<String, usize> {
+        counts
+            .iter()
+            .map(|(team, count)| (team.to_string(), *count))
+            .collect()
+    }
+
+    fn assign(
+        strategy: &dyn TeamAssignmentStrategy,
+        teams: &[Team],
+        counts: &HashMap<String, usize>,
+        preferred_team: Option<&str>,
+    ) -> String {
+        strategy
+            .assign(&AssignmentContext {
+                teams,
+                teams_player_count: counts,
+                preferred_team,
+            })
+            .id
+            .clone()
+    }
+
+    #[test]
+    fn test_balanced() {
+        let t = teams();
+        let c = counts(&[("RUST", 2), ("PYTHON", 1), ("JS", 2)]);
+        assert_eq!(assign(&Balanced, &t, &c, None), "VTL");
+        let c = counts(&[("RUST", 2), ("PYTHON", 1), ("JS", 1), ("VTL", 1)]);
+        assert_eq!(assign(&Balanced, &t, &c, None), "PYTHON");
+        // The preferred team is honoured only if it keeps the teams balanced
+        assert_eq!(assign(&Balanced, &t, &c, Some("JS")), "JS");
+        assert_eq!(assign(&Balanced, &t, &c, Some("RUST")), "PYTHON");
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let t = teams();
+        let mut c = HashMap::new();
+        for expected in t.iter().chain(&t) {
+            let team = assign(&RoundRobin, &t, &c, Some("VTL"));
+            assert_eq!(team, expected.id);
+            *c.entry(team).or_default() += 1;
+        }
+    }
+
+    #[test]
+    fn test_seeded_random_is_reproducible() {
+        let t = teams();
+        let play = |seed| {
+            let strategy = SeededRandom { seed };
+            let mut c = HashMap::new();
+            (0..20)
+                .map(|_| {
+                    let team = assign(&strategy, &t, &c, None);
+                    *c.entry(team.clone()).or_default() += 1;
+                    team
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(play(42), play(42));
+        assert_ne!(play(42), play(43));
+    }
+
+    #[test]
+    fn test_weighted() {
+        let t = teams();
+        let strategy = Weighted::new(&[
+            TeamWeight {
+                team: "RUST".to_owned(),
+                weight: 2,
+            },
+            TeamWeight {
+                team: "JS".to_owned(),
+                weight: 1,
+            },
+        ]);
+        let mut c = HashMap::new();
+        for _ in 0..9 {
+            let team = assign(&strategy, &t, &c, None);
+            *c.entry(team).or_default() += 1;
+        }
+        assert_eq!(c, counts(&[("RUST", 6), ("JS", 3)]));
+        // Teams without weight never get a player, even if preferred
+        assert_eq!(assign(&strategy, &t, &c, Some("VTL")), "RUST");
+        // No weight at all falls back to the even split
+        assert_eq!(assign(&Weighted::new(&[]), &t, &c, None), "PYTHON");
+    }
+
+    #[test]
+    fn test_player_chosen() {
+        let t = teams();
+        let c = counts(&[("RUST", 5)]);
+        assert_eq!(assign(&PlayerChosen, &t, &c, Some("RUST")), "RUST");
+        assert_eq!(assign(&PlayerChosen, &t, &c, None), "PYTHON");
+        // A team that does not exist cannot be chosen
+        assert_eq!(assign(&PlayerChosen, &t, &c, Some("GO")), "PYTHON");
+    }
+
+    #[test]
+    fn test_custom_teams() {
+        let t = vec![
+            Team {
+                id: "GO".to_owned(),
+                name: "Team Go".to_owned(),
+                backend: Backend::Rust,
+            },
+            Team {
+                id: "JAVA".to_owned(),
+                name: "Team Java".to_owned(),
+                backend: Backend::Python,
+            },
+        ];
+        // Counters of teams that are not available anymore are ignored
+        let c = counts(&[("RUST", 0), ("GO", 1)]);
+        assert_eq!(assign(&Balanced, &t, &c, None), "JAVA");
+        assert_eq!(assign(&RoundRobin, &t, &c, None), "JAVA");
+    }
+}
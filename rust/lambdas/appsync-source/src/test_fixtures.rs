@@ -0,0 +1,22 @@
+//! Fixtures shared by the tests of the crate
+
+use lambda_appsync::ID;
+
+use crate::Player;
+
+/// A player of `team`, with their `clicks` and optionally their average latency along with the
+/// number of clicks it was measured on
+pub fn player(name: &str, team: &str, clicks: Option<i32>, latency: Option<(f64, i32)>) -> Player {
+    Player {
+        id: ID::new(),
+        game_id: ID::new(),
+        name: name.to_owned(),
+        team: team.to_owned(),
+        clicks,
+        avg_latency: latency.map(|(avg_latency, _)| avg_latency),
+        avg_latency_clicks: latency.map(|(_, clicks)| clicks),
+        rate_limited_clicks: None,
+        latency_percentiles: None,
+        server_timing: None,
+    }
+}
@@ -0,0 +1,58 @@
+//! The teams every game has, and how each backend stores the secrets of its players.
+//!
+//! Both lambdas generate their own `Team` and `Backend` types from the GraphQL schema, so
+//! backends are designated here by their name in the schema (e.g. `RUST`).
+
+/// A team always available, served by the backend it is named after unless an admin stores
+/// another team with the same ID
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuiltinTeam {
+    /// ID of the team, also the name of its backend
+    pub id: &'static str,
+    /// Display name of the team
+    pub name: &'static str,
+}
+
+/// The builtin teams: one per backend, with the same ID
+pub const BUILTIN_TEAMS: [BuiltinTeam; 4] = [
+    BuiltinTeam {
+        id: "RUST",
+        name: "Team Rust",
+    },
+    BuiltinTeam {
+        id: "PYTHON",
+        name: "Team Python",
+    },
+    BuiltinTeam {
+        id: "JS",
+        name: "Team Javascript",
+    },
+    BuiltinTeam {
+        id: "VTL",
+        name: "Team Velocity",
+    },
+];
+
+/// Returns whether the players of the teams served by `backend` have their secret stored
+/// hashed
+///
+/// Verifying a hashed secret requires running the hash function, which the Rust and Python
+/// lambdas do. The JS and VTL resolvers run in the AppSync runtime, which offers no key
+/// derivation function: they check the secret directly in a DynamoDB condition expression, so
+/// their players keep a plaintext secret.
+pub fn hashes_secret(backend: &str) -> bool {
+    matches!(backend, "RUST" | "PYTHON")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hashes_secret() {
+        assert!(hashes_secret("RUST"));
+        assert!(hashes_secret("PYTHON"));
+        assert!(!hashes_secret("JS"));
+        assert!(!hashes_secret("VTL"));
+    }
+}
@@ -21,6 +21,20 @@ pub static PK: &'static str = "PK";
 /// Name of the type discriminator attribute
 pub static TYPE: &'static str = "_TYPE";
 
+/// Name of the attribute marking a player item as banned by an admin
+pub static BANNED_ATTRIBUTE: &str = "banned";
+
+/// Name of the attribute of a player item holding the time from which their game accepts
+/// clicks, missing when it does not
+///
+/// The appsync-source lambda copies it from the state of the game to the player items each time
+/// the state changes, and the appsync-bench-source lambda checks clicks against it.
+pub static CLICKS_OPEN_AT_ATTRIBUTE: &str = "clicks_open_at";
+
+/// Name of the attribute of game state items holding the version of the state, also copied to
+/// the player items along with [CLICKS_OPEN_AT_ATTRIBUTE]
+pub static STATE_VERSION_ATTRIBUTE: &str = "state_version";
+
 /// Type alias for a DynamoDB item represented as a HashMap
 pub type DynamoItem = HashMap<String, aws_sdk_dynamodb::types::AttributeValue>;
 
@@ -0,0 +1,548 @@
+//! Utility module for the telemetry of the AppSync lambdas: cold starts, duration of the init
+//! phase, memory used and duration of each operation.
+//!
+//! Metrics are written to the standard output as CloudWatch Embedded Metric Format (EMF) lines,
+//! which CloudWatch Logs turns into metrics of the [NAMESPACE] namespace without any API call.
+//! Each invocation writes:
+//! - one line with the `Function` dimension: `ColdStart` (1 for the first invocation of an
+//!   instance, 0 otherwise), `InitDuration` (first invocation only), `MaxMemoryUsed` and
+//!   `BatchSize`;
+//! - one line per operation of the batch, with the `Function` and `Operation` dimensions:
+//!   `Duration` (one value per event) and `Errors`.
+//!
+//! The lambdas run their own handler instead of the one generated by `appsync_lambda_main!`, so
+//! that the init phase, including the loading of the AWS config and the creation of the clients,
+//! can be measured: [init_started] and [init_finished] delimit it, and [handle_batch] runs the
+//! events of each invocation. [appsync_lambda_handler] generates that handler and the `main`
+//! function of a lambda.
+//!
+//! The logs are structured as well, see [init_logger]: each record is stamped with the request ID
+//! of the invocation and with the operation, player and outcome of the event it is logged for.
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        OnceLock,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use lambda_appsync::{
+    AppsyncEvent, AppsyncResponse, lambda_runtime, log,
+    serde::de::DeserializeOwned,
+    serde_json::{self, Map, Value, json},
+    tokio,
+};
+
+mod logger;
+use logger::{LOG_CONTEXT, set_outcome};
+pub use logger::{
+    LogContext, LogFormat, format_record, init_logger, redact_message, redact_secrets,
+};
+
+/// Namespace of the metrics of the lambdas
+pub const NAMESPACE: &str = "BenchmarkGame";
+
+/// Time the init phase of this instance started, see [init_started]
+static INIT_START: OnceLock<Instant> = OnceLock::new();
+/// Duration of the init phase of this instance, see [init_finished]
+static INIT_DURATION: OnceLock<Duration> = OnceLock::new();
+/// Whether the next invocation is the first one of this instance
+static FIRST_INVOCATION: AtomicBool = AtomicBool::new(true);
+
+/// Marks the start of the init phase, to be called first thing in `main`
+pub fn init_started() {
+    INIT_START.get_or_init(Instant::now);
+}
+
+/// Marks the end of the init phase, once the AWS config is loaded and the clients created
+pub fn init_finished() {
+    let start = INIT_START.get().expect("init_started was called");
+    let duration = start.elapsed();
+    log::debug!("Init took {duration:?}");
+    INIT_DURATION.get_or_init(|| duration);
+}
+
+/// Unit of a metric
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Milliseconds,
+    Megabytes,
+    Count,
+}
+
+impl Unit {
+    fn as_str(self) -> &'static str {
+        match self {
+            Unit::Milliseconds => "Milliseconds",
+            Unit::Megabytes => "Megabytes",
+            Unit::Count => "Count",
+        }
+    }
+}
+
+/// Metrics sharing the same dimensions, written as a single EMF line
+#[derive(Debug, Clone, Default)]
+pub struct MetricsLine {
+    dimensions: Vec<(&'static str, String)>,
+    metrics: Vec<(&'static str, Unit, Vec<f64>)>,
+}
+
+impl MetricsLine {
+    /// Adds a dimension, shared by all the metrics of the line
+    pub fn dimension(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.dimensions.push((name, value.into()));
+        self
+    }
+
+    /// Adds a metric, with one value per sample
+    pub fn metric(mut self, name: &'static str, unit: Unit, values: Vec<f64>) -> Self {
+        self.metrics.push((name, unit, values));
+        self
+    }
+
+    /// Returns the EMF line, stamped with `timestamp` (in milliseconds since the epoch)
+    pub fn to_json(&self, timestamp: u64) -> String {
+        let mut line = Map::new();
+        line.insert(
+            "_aws".to_owned(),
+            json!({
+                "Timestamp": timestamp,
+                "CloudWatchMetrics": [{
+                    "Namespace": NAMESPACE,
+                    "Dimensions": [self.dimensions.iter().map(|(name, _)| *name).collect::<Vec<_>>()],
+                    "Metrics": self.metrics.iter().map(|(name, unit, _)| json!({
+                        "Name": name,
+                        "Unit": unit.as_str(),
+                    })).collect::<Vec<_>>(),
+                }],
+            }),
+        );
+        for (name, value) in &self.dimensions {
+            line.insert((*name).to_owned(), json!(value));
+        }
+        for (name, _, values) in &self.metrics {
+            let value = match values.as_slice() {
+                [value] => json!(value),
+                values => json!(values),
+            };
+            line.insert((*name).to_owned(), value);
+        }
+        Value::Object(line).to_string()
+    }
+}
+
+/// Outcome of an event of a batch, for the metrics of the invocation
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventOutcome {
+    /// GraphQL operation of the event, as `<parent type>.<field>` (e.g. `Mutation.click`)
+    pub operation: String,
+    /// Time spent executing the event
+    pub duration: Duration,
+    /// Whether the response is an error
+    pub error: bool,
+}
+
+/// Returns the EMF lines of an invocation handling the events whose outcomes are given
+///
+/// `init_duration` is only provided for the first invocation of an instance, and `memory_mb` if
+/// it could be measured.
+pub fn invocation_metrics(
+    function: &str,
+    init_duration: Option<Duration>,
+    memory_mb: Option<f64>,
+    outcomes: &[EventOutcome],
+    timestamp: u64,
+) -> Vec<String> {
+    let mut invocation = MetricsLine::default()
+        .dimension("Function", function)
+        .metric(
+            "ColdStart",
+            Unit::Count,
+            vec![if init_duration.is_some() { 1.0 } else { 0.0 }],
+        );
+    if let Some(init_duration) = init_duration {
+        invocation = invocation.metric(
+            "InitDuration",
+            Unit::Milliseconds,
+            vec![init_duration.as_secs_f64() * 1000.0],
+        );
+    }
+    if let Some(memory_mb) = memory_mb {
+        invocation = invocation.metric("MaxMemoryUsed", Unit::Megabytes, vec![memory_mb]);
+    }
+    invocation = invocation.metric("BatchSize", Unit::Count, vec![outcomes.len() as f64]);
+
+    let mut operations = BTreeMap::<&str, Vec<&EventOutcome>>::new();
+    for outcome in outcomes {
+        operations
+            .entry(outcome.operation.as_str())
+            .or_default()
+            .push(outcome);
+    }
+    std::iter::once(invocation.to_json(timestamp))
+        .chain(operations.into_iter().map(|(operation, outcomes)| {
+            MetricsLine::default()
+                .dimension("Function", function)
+                .dimension("Operation", operation)
+                .metric(
+                    "Duration",
+                    Unit::Milliseconds,
+                    outcomes
+                        .iter()
+                        .map(|outcome| outcome.duration.as_secs_f64() * 1000.0)
+                        .collect(),
+                )
+                .metric(
+                    "Errors",
+                    Unit::Count,
+                    vec![outcomes.iter().filter(|outcome| outcome.error).count() as f64],
+                )
+                .to_json(timestamp)
+        }))
+        .collect()
+}
+
+/// Returns the GraphQL operation of a raw AppSync event, as `<parent type>.<field>`
+pub fn operation_name(event: &Value) -> String {
+    let info = &event["info"];
+    format!(
+        "{}.{}",
+        info["parentTypeName"].as_str().unwrap_or("Unknown"),
+        info["fieldName"].as_str().unwrap_or("unknown")
+    )
+}
+
+/// Returns the type of the error of an AppSync response, if it is an error
+pub fn error_type(response: &AppsyncResponse) -> Option<String> {
+    serde_json::to_value(response)
+        .ok()?
+        .get("errorType")?
+        .as_str()
+        .map(str::to_owned)
+}
+
+/// Returns whether an AppSync response is an error
+pub fn is_error(response: &AppsyncResponse) -> bool {
+    error_type(response).is_some()
+}
+
+/// Extracts the peak resident memory, in megabytes, from the content of `/proc/self/status`
+fn parse_peak_memory_mb(status: &str) -> Option<f64> {
+    let kilobytes = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<f64>()
+        .ok()?;
+    Some(kilobytes / 1024.0)
+}
+
+/// Returns the peak resident memory of this instance since it started, in megabytes, which is
+/// what Lambda reports as the max memory used
+fn peak_memory_mb() -> Option<f64> {
+    parse_peak_memory_mb(&std::fs::read_to_string("/proc/self/status").ok()?)
+}
+
+/// Returns the current time in milliseconds since the epoch
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time flows forward")
+        .as_millis() as u64
+}
+
+/// Runs the batch of AppSync events of an invocation of `function` concurrently with `execute`,
+/// then writes the metrics of the invocation
+///
+/// Records logged while handling the invocation carry its request ID, and those logged while
+/// handling an event carry the operation and player of the event, see [LogContext]. The outcome
+/// of each event is logged once it is handled.
+pub async fn handle_batch<O, F, Fut>(
+    function: &str,
+    event: lambda_runtime::LambdaEvent<Value>,
+    execute: F,
+) -> Result<Vec<AppsyncResponse>, lambda_runtime::Error>
+where
+    O: DeserializeOwned,
+    F: Fn(AppsyncEvent<O>) -> Fut,
+    Fut: Future<Output = AppsyncResponse> + Send + 'static,
+{
+    let (payload, context) = event.into_parts();
+    let invocation = LogContext {
+        request_id: context.request_id,
+        ..Default::default()
+    };
+    LOG_CONTEXT
+        .scope(invocation, handle_events(function, payload, execute))
+        .await
+}
+
+/// Runs the events of an invocation, see [handle_batch]
+async fn handle_events<O, F, Fut>(
+    function: &str,
+    payload: Value,
+    execute: F,
+) -> Result<Vec<AppsyncResponse>, lambda_runtime::Error>
+where
+    O: DeserializeOwned,
+    F: Fn(AppsyncEvent<O>) -> Fut,
+    Fut: Future<Output = AppsyncResponse> + Send + 'static,
+{
+    let init_duration = FIRST_INVOCATION
+        .swap(false, Ordering::Relaxed)
+        .then(|| INIT_DURATION.get().copied())
+        .flatten();
+    let request_id = LOG_CONTEXT.with(|context| context.request_id.clone());
+    log::debug!("payload={}", redact_secrets(&payload));
+
+    let events = serde_json::from_value::<Vec<Value>>(payload)?;
+    let mut handles = Vec::with_capacity(events.len());
+    for event in events {
+        let operation = operation_name(&event);
+        let context = LogContext::for_event(&request_id, &event);
+        let response = LOG_CONTEXT.sync_scope(context.clone(), || {
+            log::info!("event={}", redact_secrets(&event));
+            serde_json::from_value(event).map(&execute)
+        })?;
+        handles.push(tokio::spawn(LOG_CONTEXT.scope(context, async move {
+            let start = Instant::now();
+            let response = response.await;
+            let duration = start.elapsed();
+            let error_type = error_type(&response);
+            set_outcome(error_type.as_deref().unwrap_or("ok"));
+            log::info!("Handled in {:.3}ms", duration.as_secs_f64() * 1000.0);
+            (operation, duration, error_type.is_some(), response)
+        })));
+    }
+
+    let mut responses = Vec::with_capacity(handles.len());
+    let mut outcomes = Vec::with_capacity(handles.len());
+    for handle in handles {
+        let (operation, duration, error, response) = handle.await?;
+        outcomes.push(EventOutcome {
+            operation,
+            duration,
+            error,
+        });
+        responses.push(response);
+    }
+
+    for line in invocation_metrics(
+        function,
+        init_duration,
+        peak_memory_mb(),
+        &outcomes,
+        now_millis(),
+    ) {
+        println!("{line}");
+    }
+    Ok(responses)
+}
+
+/// Generates the DynamoDB client, the handler and the `main` function of an AppSync lambda, to be
+/// invoked at the root of its crate, after `appsync_lambda_main!` with
+/// `exclude_lambda_handler = true`
+///
+/// The generated code is:
+/// - a `dynamodb()` function returning the DynamoDB client, created during the init phase;
+/// - a handler running each invocation with [handle_batch], which executes the events with the
+///   `Operation` type generated by `appsync_lambda_main!`;
+/// - a `main` function delimiting the init phase with [init_started] and [init_finished], then
+///   running the handler.
+///
+/// The crate must depend on `aws-sdk-dynamodb`.
+#[macro_export]
+macro_rules! appsync_lambda_handler {
+    () => {
+        /// The DynamoDB client, created during the init phase
+        static DYNAMODB: ::std::sync::OnceLock<::aws_sdk_dynamodb::Client> =
+            ::std::sync::OnceLock::new();
+
+        /// Returns the DynamoDB client
+        pub fn dynamodb() -> ::aws_sdk_dynamodb::Client {
+            DYNAMODB.get().expect("created during init").clone()
+        }
+
+        async fn function_handler(
+            event: ::lambda_appsync::lambda_runtime::LambdaEvent<
+                ::lambda_appsync::serde_json::Value,
+            >,
+        ) -> Result<Vec<::lambda_appsync::AppsyncResponse>, ::lambda_appsync::lambda_runtime::Error>
+        {
+            // The events are logged by `handle_batch`, with their secrets redacted
+            $crate::handle_batch(
+                env!("CARGO_PKG_NAME"),
+                event,
+                |event: ::lambda_appsync::AppsyncEvent<Operation>| {
+                    event.info.operation.execute(event)
+                },
+            )
+            .await
+        }
+
+        #[::lambda_appsync::tokio::main(crate = "::lambda_appsync::tokio")]
+        async fn main() -> Result<(), ::lambda_appsync::lambda_runtime::Error> {
+            $crate::init_started();
+            $crate::init_logger();
+
+            let aws_sdk_config = ::lambda_appsync::aws_config::load_from_env().await;
+            DYNAMODB
+                .set(::aws_sdk_dynamodb::Client::new(&aws_sdk_config))
+                .expect("main runs once");
+            $crate::init_finished();
+
+            ::lambda_appsync::lambda_runtime::run(::lambda_appsync::lambda_runtime::service_fn(
+                function_handler,
+            ))
+            .await
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use lambda_appsync::AppsyncError;
+
+    use super::*;
+
+    fn outcome(operation: &str, duration_ms: u64, error: bool) -> EventOutcome {
+        EventOutcome {
+            operation: operation.to_owned(),
+            duration: Duration::from_millis(duration_ms),
+            error,
+        }
+    }
+
+    fn parse(line: &str) -> Value {
+        serde_json::from_str(line).expect("EMF lines are JSON")
+    }
+
+    /// Returns the names of the dimensions and of the metrics declared by an EMF line
+    fn declared(line: &Value) -> (Vec<&str>, Vec<(&str, &str)>) {
+        let directive = &line["_aws"]["CloudWatchMetrics"][0];
+        assert_eq!(directive["Namespace"], NAMESPACE);
+        let dimensions = directive["Dimensions"][0]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|name| name.as_str().unwrap())
+            .collect();
+        let metrics = directive["Metrics"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|metric| {
+                (
+                    metric["Name"].as_str().unwrap(),
+                    metric["Unit"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        (dimensions, metrics)
+    }
+
+    #[test]
+    fn test_cold_start_metrics() {
+        let lines = invocation_metrics(
+            "appsync-source",
+            Some(Duration::from_micros(152_500)),
+            Some(24.5),
+            &[outcome("Query.gameState", 12, false)],
+            1_700_000_000_000,
+        );
+        assert_eq!(lines.len(), 2);
+        let invocation = parse(&lines[0]);
+        assert_eq!(invocation["_aws"]["Timestamp"], 1_700_000_000_000u64);
+        assert_eq!(
+            declared(&invocation),
+            (
+                vec!["Function"],
+                vec![
+                    ("ColdStart", "Count"),
+                    ("InitDuration", "Milliseconds"),
+                    ("MaxMemoryUsed", "Megabytes"),
+                    ("BatchSize", "Count"),
+                ]
+            )
+        );
+        assert_eq!(invocation["Function"], "appsync-source");
+        assert_eq!(invocation["ColdStart"], 1.0);
+        assert_eq!(invocation["InitDuration"], 152.5);
+        assert_eq!(invocation["MaxMemoryUsed"], 24.5);
+        assert_eq!(invocation["BatchSize"], 1.0);
+    }
+
+    #[test]
+    fn test_warm_invocation_metrics() {
+        let lines = invocation_metrics(
+            "appsync-bench-source",
+            None,
+            None,
+            &[
+                outcome("Mutation.click", 10, false),
+                outcome("Mutation.reportLatency", 30, true),
+                outcome("Mutation.click", 20, true),
+            ],
+            1_700_000_000_000,
+        );
+        assert_eq!(lines.len(), 3);
+        let invocation = parse(&lines[0]);
+        assert_eq!(
+            declared(&invocation).1,
+            vec![("ColdStart", "Count"), ("BatchSize", "Count")]
+        );
+        assert_eq!(invocation["ColdStart"], 0.0);
+        assert!(invocation.get("InitDuration").is_none());
+        assert_eq!(invocation["BatchSize"], 3.0);
+
+        // One line per operation, with a duration per event
+        let click = parse(&lines[1]);
+        assert_eq!(
+            declared(&click),
+            (
+                vec!["Function", "Operation"],
+                vec![("Duration", "Milliseconds"), ("Errors", "Count")]
+            )
+        );
+        assert_eq!(click["Function"], "appsync-bench-source");
+        assert_eq!(click["Operation"], "Mutation.click");
+        assert_eq!(click["Duration"], json!([10.0, 20.0]));
+        assert_eq!(click["Errors"], 1.0);
+        let report = parse(&lines[2]);
+        assert_eq!(report["Operation"], "Mutation.reportLatency");
+        assert_eq!(report["Duration"], 30.0);
+        assert_eq!(report["Errors"], 1.0);
+    }
+
+    #[test]
+    fn test_operation_name_and_outcome() {
+        let event = json!({
+            "info": {"parentTypeName": "Mutation", "fieldName": "clickRust"},
+            "arguments": {},
+        });
+        assert_eq!(operation_name(&event), "Mutation.clickRust");
+        assert_eq!(operation_name(&json!({})), "Unknown.unknown");
+
+        assert!(!is_error(&json!({"id": 1}).into()));
+        assert!(is_error(
+            &AppsyncError::new("PlayerNotFound", "Player does not exist").into()
+        ));
+        assert_eq!(
+            error_type(&AppsyncError::new("PlayerNotFound", "Player does not exist").into()),
+            Some("PlayerNotFound".to_owned())
+        );
+        assert_eq!(error_type(&json!({"id": 1}).into()), None);
+    }
+
+    #[test]
+    fn test_parse_peak_memory() {
+        let status =
+            "Name:\tbootstrap\nVmPeak:\t  20480 kB\nVmHWM:\t   25088 kB\nVmRSS:\t 20000 kB\n";
+        assert_eq!(parse_peak_memory_mb(status), Some(24.5));
+        assert_eq!(parse_peak_memory_mb("Name:\tbootstrap\n"), None);
+    }
+}
@@ -0,0 +1,390 @@
+//! Structured logging of the lambdas.
+//!
+//! Each log record is written as a single JSON object, so the logs can be queried with
+//! CloudWatch Logs Insights:
+//!
+//! ```json
+//! {"timestamp":"2025-01-01T12:00:00.000000Z","level":"INFO","target":"appsync_source::operations",
+//!  "message":"...","request_id":"...","operation":"Mutation.click","player_id":"...","outcome":"ok"}
+//! ```
+//!
+//! `request_id` is the Lambda request ID of the invocation; `operation` (`<parent type>.<field>`)
+//! and `player_id` are those of the AppSync event being handled, if any; `outcome` is `ok` or the
+//! type of the error returned for the event, once known. Secrets are redacted, whether they appear
+//! in the messages or in the logged payloads (see [redact_message] and [redact_secrets]).
+
+use std::{borrow::Cow, io::Write, sync::OnceLock};
+
+use lambda_appsync::{
+    env_logger,
+    log::Record,
+    serde_json::{Map, Value, json},
+    tokio,
+};
+
+/// Replacement of the redacted secrets
+const REDACTED: &str = "[REDACTED]";
+
+tokio::task_local! {
+    /// Context of the invocation, or of the event, being handled by the current task
+    pub(crate) static LOG_CONTEXT: LogContext;
+}
+
+/// Context stamped on the log records
+#[derive(Debug, Default, Clone)]
+pub struct LogContext {
+    /// Lambda request ID of the invocation
+    pub request_id: String,
+    /// GraphQL operation of the event, as `<parent type>.<field>`
+    pub operation: Option<String>,
+    /// `player_id` argument of the event
+    pub player_id: Option<String>,
+    /// `ok`, or the type of the error returned for the event
+    pub outcome: OnceLock<String>,
+}
+
+impl LogContext {
+    /// Context of an AppSync `event` of the invocation `request_id`
+    pub fn for_event(request_id: &str, event: &Value) -> Self {
+        LogContext {
+            request_id: request_id.to_owned(),
+            operation: Some(crate::operation_name(event)),
+            player_id: event["arguments"]["player_id"].as_str().map(str::to_owned),
+            outcome: OnceLock::new(),
+        }
+    }
+}
+
+/// Sets the outcome of the event being handled by the current task
+pub(crate) fn set_outcome(outcome: &str) {
+    let _ = LOG_CONTEXT.try_with(|context| context.outcome.set(outcome.to_owned()));
+}
+
+/// Format of the log records
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// One JSON object per record
+    Json,
+    /// Human readable lines, for local runs
+    Text,
+}
+
+impl LogFormat {
+    /// Reads the format from the `LOG_FORMAT` environment variable: `json` (default) or `text`
+    pub fn from_env() -> Self {
+        match std::env::var("LOG_FORMAT").as_deref() {
+            Ok("json") | Err(_) => LogFormat::Json,
+            Ok("text") => LogFormat::Text,
+            Ok(other) => panic!("`LOG_FORMAT` must be `json` or `text`, got `{other}`"),
+        }
+    }
+}
+
+/// Installs the logger of the lambda
+///
+/// The format is read from `LOG_FORMAT` (see [LogFormat::from_env]) and the levels from
+/// `RUST_LOG` (default: `info`).
+pub fn init_logger() {
+    let format = LogFormat::from_env();
+    env_logger::Builder::from_env(
+        env_logger::Env::default()
+            .default_filter_or("info,tracing::span=warn")
+            .default_write_style_or("never"),
+    )
+    .format(move |buf, record| {
+        let timestamp = buf.timestamp_micros().to_string();
+        let line = LOG_CONTEXT
+            .try_with(|context| format_record(format, &timestamp, record, Some(context)))
+            .unwrap_or_else(|_| format_record(format, &timestamp, record, None));
+        writeln!(buf, "{line}")
+    })
+    .init();
+}
+
+/// Formats a log `record` in the given `format`, along with the `context` it was logged in
+pub fn format_record(
+    format: LogFormat,
+    timestamp: &str,
+    record: &Record,
+    context: Option<&LogContext>,
+) -> String {
+    let message = record.args().to_string();
+    let message = redact_message(&message);
+    match format {
+        LogFormat::Json => {
+            let mut line = Map::new();
+            line.insert("timestamp".to_owned(), json!(timestamp));
+            line.insert("level".to_owned(), json!(record.level().as_str()));
+            line.insert("target".to_owned(), json!(record.target()));
+            line.insert("message".to_owned(), json!(message));
+            if let Some(context) = context {
+                line.insert("request_id".to_owned(), json!(context.request_id));
+                if let Some(operation) = &context.operation {
+                    line.insert("operation".to_owned(), json!(operation));
+                }
+                if let Some(player_id) = &context.player_id {
+                    line.insert("player_id".to_owned(), json!(player_id));
+                }
+                if let Some(outcome) = context.outcome.get() {
+                    line.insert("outcome".to_owned(), json!(outcome));
+                }
+            }
+            Value::Object(line).to_string()
+        }
+        LogFormat::Text => {
+            let mut line = format!(
+                "[{timestamp} {:<5} {}]",
+                record.level().as_str(),
+                record.target()
+            );
+            if let Some(context) = context {
+                line.push_str(&format!(" request_id={}", context.request_id));
+                if let Some(operation) = &context.operation {
+                    line.push_str(&format!(" operation={operation}"));
+                }
+                if let Some(player_id) = &context.player_id {
+                    line.push_str(&format!(" player_id={player_id}"));
+                }
+                if let Some(outcome) = context.outcome.get() {
+                    line.push_str(&format!(" outcome={outcome}"));
+                }
+            }
+            format!("{line} {message}")
+        }
+    }
+}
+
+/// Request headers carrying credentials, such as the API key or the token of an admin
+const CREDENTIAL_HEADERS: [&str; 5] = [
+    "authorization",
+    "x-api-key",
+    "x-amz-security-token",
+    "cookie",
+    "set-cookie",
+];
+
+/// Returns whether a field name designates a secret (e.g. `secret`, `old_secret`, `secret_hash`)
+/// or a credential header (e.g. `authorization`, `x-api-key`)
+fn is_secret_name(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    name.contains("secret") || CREDENTIAL_HEADERS.contains(&name.as_str())
+}
+
+/// Returns a copy of a JSON `value` (e.g. the payload of an invocation) whose fields designating
+/// a secret are redacted
+pub fn redact_secrets(value: &Value) -> Value {
+    match value {
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(name, value)| {
+                    let value = if is_secret_name(name) {
+                        json!(REDACTED)
+                    } else {
+                        redact_secrets(value)
+                    };
+                    (name.clone(), value)
+                })
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(values.iter().map(redact_secrets).collect()),
+        value => value.clone(),
+    }
+}
+
+/// Redacts the values assigned to the names designating a secret in a log message
+///
+/// Handles the `name=value` style of the messages of the lambdas, as well as the `Debug`
+/// (`name: "value"`) and JSON (`"name":"value"`) representations. Other mentions of secrets, such
+/// as "Player secret is invalid", are kept.
+pub fn redact_message(message: &str) -> Cow<'_, str> {
+    let bytes = message.as_bytes();
+    let lowercase = message.to_ascii_lowercase();
+    let mut redacted = String::new();
+    let mut copied = 0;
+    let mut search = 0;
+    while let Some(found) = lowercase[search..].find("secret") {
+        // Skip the rest of the name, then the separator
+        let mut i = search + found + "secret".len();
+        while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+            i += 1;
+        }
+        search = i;
+        if i < bytes.len() && bytes[i] == b'"' {
+            i += 1;
+        }
+        while i < bytes.len() && bytes[i] == b' ' {
+            i += 1;
+        }
+        if i >= bytes.len() || !matches!(bytes[i], b'=' | b':') {
+            continue;
+        }
+        i += 1;
+        while i < bytes.len() && bytes[i] == b' ' {
+            i += 1;
+        }
+        let start = i;
+        let quoted = i < bytes.len() && bytes[i] == b'"';
+        let mut end = start + usize::from(quoted);
+        while end < bytes.len() {
+            match bytes[end] {
+                b'\\' if quoted => end += 1,
+                b'"' if quoted => {
+                    end += 1;
+                    break;
+                }
+                b',' | b'}' | b')' | b']' if !quoted => break,
+                byte if !quoted && byte.is_ascii_whitespace() => break,
+                _ => (),
+            }
+            end += 1;
+        }
+        let end = end.min(bytes.len());
+        if start == end {
+            continue;
+        }
+        redacted.push_str(&message[copied..start]);
+        if quoted {
+            redacted.push_str(&format!("\"{REDACTED}\""));
+        } else {
+            redacted.push_str(REDACTED);
+        }
+        copied = end;
+        search = end;
+    }
+    if copied == 0 {
+        return Cow::Borrowed(message);
+    }
+    redacted.push_str(&message[copied..]);
+    Cow::Owned(redacted)
+}
+
+#[cfg(test)]
+mod tests {
+    use lambda_appsync::log::Level;
+
+    use super::*;
+
+    fn context() -> LogContext {
+        let context = LogContext::for_event(
+            "f0c1f4b2-3c1e-4d1b-9a8e-0123456789ab",
+            &json!({
+                "info": {"parentTypeName": "Mutation", "fieldName": "click"},
+                "arguments": {"player_id": "c5a0f1d2", "secret": "hunter2"},
+            }),
+        );
+        context.outcome.set("PlayerBanned".to_owned()).unwrap();
+        context
+    }
+
+    fn format(format: LogFormat, message: &str, context: Option<&LogContext>) -> String {
+        format_record(
+            format,
+            "2025-01-01T12:00:00.000000Z",
+            &Record::builder()
+                .args(format_args!("{message}"))
+                .level(Level::Info)
+                .target("appsync_bench_source::operations")
+                .build(),
+            context,
+        )
+    }
+
+    #[test]
+    fn test_json_format() {
+        let line = format(LogFormat::Json, "Player clicked", Some(&context()));
+        let record: Value = lambda_appsync::serde_json::from_str(&line).unwrap();
+        assert_eq!(
+            record,
+            json!({
+                "timestamp": "2025-01-01T12:00:00.000000Z",
+                "level": "INFO",
+                "target": "appsync_bench_source::operations",
+                "message": "Player clicked",
+                "request_id": "f0c1f4b2-3c1e-4d1b-9a8e-0123456789ab",
+                "operation": "Mutation.click",
+                "player_id": "c5a0f1d2",
+                "outcome": "PlayerBanned",
+            })
+        );
+        assert!(!line.contains('\n'));
+
+        // Outside of an invocation, or before the outcome is known, fields are omitted
+        let line = format(LogFormat::Json, "Init took 120ms", None);
+        let record: Value = lambda_appsync::serde_json::from_str(&line).unwrap();
+        assert_eq!(
+            record.as_object().unwrap().keys().collect::<Vec<_>>(),
+            ["level", "message", "target", "timestamp"]
+        );
+        let context = LogContext {
+            request_id: "f0c1f4b2".to_owned(),
+            ..Default::default()
+        };
+        let line = format(LogFormat::Json, "Invocation", Some(&context));
+        let record: Value = lambda_appsync::serde_json::from_str(&line).unwrap();
+        assert_eq!(record["request_id"], "f0c1f4b2");
+        assert!(record.get("operation").is_none());
+        assert!(record.get("outcome").is_none());
+    }
+
+    #[test]
+    fn test_text_format() {
+        assert_eq!(
+            format(LogFormat::Text, "Player clicked", Some(&context())),
+            "[2025-01-01T12:00:00.000000Z INFO  appsync_bench_source::operations] \
+            request_id=f0c1f4b2-3c1e-4d1b-9a8e-0123456789ab operation=Mutation.click \
+            player_id=c5a0f1d2 outcome=PlayerBanned Player clicked"
+        );
+    }
+
+    #[test]
+    fn test_messages_are_redacted() {
+        let line = format(
+            LogFormat::Json,
+            "ENTER rotate - player_id=c5a0f1d2 old_secret=hunter2 new_secret=hunter3",
+            None,
+        );
+        let record: Value = lambda_appsync::serde_json::from_str(&line).unwrap();
+        assert_eq!(
+            record["message"],
+            "ENTER rotate - player_id=c5a0f1d2 old_secret=[REDACTED] new_secret=[REDACTED]"
+        );
+
+        assert_eq!(
+            redact_message(r#"Args { player_id: "c5a0f1d2", secret: "hun\"ter2" }"#),
+            r#"Args { player_id: "c5a0f1d2", secret: "[REDACTED]" }"#
+        );
+        assert_eq!(
+            redact_message(r#"{"secret_hash":"$pbkdf2$i=1$AA$AA","name":"Jean"}"#),
+            r#"{"secret_hash":"[REDACTED]","name":"Jean"}"#
+        );
+        assert!(matches!(
+            redact_message("Player secret is invalid"),
+            Cow::Borrowed("Player secret is invalid")
+        ));
+        assert_eq!(redact_message("secret="), "secret=");
+    }
+
+    #[test]
+    fn test_payloads_are_redacted() {
+        let payload = json!([{
+            "arguments": {"player_id": "c5a0f1d2", "old_secret": "hunter2", "new_secret": "hunter3"},
+            "info": {"parentTypeName": "Mutation", "fieldName": "rotatePlayerSecret"},
+            "request": {"headers": {
+                "x-api-key": "da2-abc",
+                "Authorization": "eyJraWQiOi",
+                "host": "example.appsync-api.eu-west-1.amazonaws.com",
+            }},
+        }]);
+        let redacted = redact_secrets(&payload);
+        assert_eq!(redacted[0]["arguments"]["player_id"], "c5a0f1d2");
+        assert_eq!(redacted[0]["arguments"]["old_secret"], REDACTED);
+        assert_eq!(redacted[0]["arguments"]["new_secret"], REDACTED);
+        assert_eq!(redacted[0]["info"], payload[0]["info"]);
+        assert!(!redacted.to_string().contains("hunter"));
+        let headers = &redacted[0]["request"]["headers"];
+        assert_eq!(headers["x-api-key"], REDACTED);
+        assert_eq!(headers["Authorization"], REDACTED);
+        assert_eq!(headers["host"], payload[0]["request"]["headers"]["host"]);
+    }
+}
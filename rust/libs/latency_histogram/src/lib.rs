@@ -0,0 +1,392 @@
+//! Utility module for the latency statistics of players and teams: latency totals and
+//! histograms.
+//!
+//! The latency of the clicks of a player is stored as counters updated with `ADD`, so that
+//! concurrent reports never conflict: the sum of the reported latencies and the number of
+//! reported clicks, the average latency being derived on read (see [LatencyTotals]).
+//!
+//! Latencies are counted in fixed log-scale buckets, four per doubling of the latency: bucket
+//! `i` counts the latencies in `[2^(i/4), 2^((i+1)/4))` milliseconds, bucket 0 also counting the
+//! latencies below 1 ms and the last bucket all the latencies above its lower bound. Adjacent
+//! bounds are 19% apart, which is plenty to compare the tail latencies of the backends.
+//!
+//! Clients send the histogram of the clicks of each latency report, as a list of counts indexed
+//! by bucket. The backend merges it into the histogram of the player, which is stored in DynamoDB
+//! as one number attribute per non-empty bucket, so that merging is a conflict-free `ADD`. The
+//! histogram of a team is the sum of the histograms of its players, computed on read.
+
+use std::collections::HashMap;
+
+#[cfg(feature = "dynamodb")]
+use aws_sdk_dynamodb::types::AttributeValue;
+
+/// Number of buckets per doubling of the latency
+pub const BUCKETS_PER_DOUBLING: u32 = 4;
+
+/// Number of buckets of a histogram, the last one starting at 2^15.75 ms (about 55 seconds)
+pub const BUCKET_COUNT: usize = 64;
+
+/// Prefix of the name of the attributes storing the count of each bucket
+pub static ATTRIBUTE_PREFIX: &str = "latency_hist_";
+
+/// Name of the attribute storing the sum of the latencies reported by a player, in milliseconds
+pub static TOTAL_ATTRIBUTE: &str = "total_latency_ms";
+
+/// Name of the attribute storing the number of clicks whose latency a player reported
+pub static SAMPLES_ATTRIBUTE: &str = "latency_samples";
+
+/// Name of the attribute storing the number of clicks of a player whose latency is not
+/// reported yet, so that reports cannot cover more clicks than the player made
+pub static UNREPORTED_ATTRIBUTE: &str = "latency_unreported_clicks";
+
+/// Names of the legacy attributes storing the average latency of a player and the number of
+/// clicks it is based on, still updated in place by the backends other than Rust
+static LEGACY_AVG_ATTRIBUTE: &str = "avg_latency";
+static LEGACY_CLICKS_ATTRIBUTE: &str = "avg_latency_clicks";
+
+/// The sum and the count of the latencies reported by a player
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LatencyTotals {
+    /// Sum of the latencies, in milliseconds
+    pub total_ms: f64,
+    /// Number of latencies
+    pub samples: i64,
+}
+
+impl LatencyTotals {
+    /// Reads the latency totals of a player item
+    ///
+    /// The legacy average latency of the item is accounted for as well, whether the player
+    /// is served by a backend still storing it or reported latencies before the counters
+    /// were introduced.
+    #[cfg(feature = "dynamodb")]
+    pub fn from_item(item: &HashMap<String, AttributeValue>) -> Self {
+        let number = |name: &str| {
+            item.get(name).map(|value| {
+                value
+                    .as_n()
+                    .ok()
+                    .and_then(|n| n.parse::<f64>().ok())
+                    .expect("valid schema")
+            })
+        };
+        let mut totals = LatencyTotals {
+            total_ms: number(TOTAL_ATTRIBUTE).unwrap_or_default(),
+            samples: number(SAMPLES_ATTRIBUTE).unwrap_or_default() as i64,
+        };
+        if let (Some(avg), Some(clicks)) = (
+            number(LEGACY_AVG_ATTRIBUTE),
+            number(LEGACY_CLICKS_ATTRIBUTE),
+        ) {
+            totals.total_ms += avg * clicks;
+            totals.samples += clicks as i64;
+        }
+        totals
+    }
+
+    /// Returns the average latency, in milliseconds
+    ///
+    /// Returns [None] if there is no latency
+    pub fn avg(&self) -> Option<f64> {
+        (self.samples > 0).then(|| self.total_ms / self.samples as f64)
+    }
+}
+
+/// Counts of latencies by log-scale bucket
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    counts: HashMap<usize, u64>,
+}
+
+impl LatencyHistogram {
+    /// Returns the bucket counting `latency_ms`
+    pub fn bucket_of(latency_ms: f64) -> usize {
+        if latency_ms.is_nan() || latency_ms < 1.0 {
+            return 0;
+        }
+        let bucket = (latency_ms.log2() * BUCKETS_PER_DOUBLING as f64).floor() as usize;
+        bucket.min(BUCKET_COUNT - 1)
+    }
+
+    /// Returns the upper bound of `bucket`, in milliseconds
+    pub fn upper_bound(bucket: usize) -> f64 {
+        2f64.powf((bucket + 1) as f64 / BUCKETS_PER_DOUBLING as f64)
+    }
+
+    /// Creates a histogram from a list of counts indexed by bucket, as sent by clients
+    ///
+    /// Returns the reason the counts are invalid, if any
+    pub fn from_counts(counts: &[i32]) -> Result<Self, String> {
+        if counts.len() > BUCKET_COUNT {
+            return Err(format!(
+                "Latency histogram cannot have more than {BUCKET_COUNT} buckets, got {}",
+                counts.len()
+            ));
+        }
+        let mut histogram = LatencyHistogram::default();
+        for (bucket, &count) in counts.iter().enumerate() {
+            let count = u64::try_from(count)
+                .map_err(|_| format!("Latency histogram counts cannot be negative, got {count}"))?;
+            histogram.add(bucket, count);
+        }
+        Ok(histogram)
+    }
+
+    /// Returns the list of counts indexed by bucket, as sent by clients
+    ///
+    /// The list stops at the last non-empty bucket.
+    pub fn to_counts(&self) -> Vec<u64> {
+        let len = self.counts.keys().max().map_or(0, |bucket| bucket + 1);
+        (0..len)
+            .map(|bucket| self.counts.get(&bucket).copied().unwrap_or_default())
+            .collect()
+    }
+
+    /// Creates a histogram from the bucket attributes of a DynamoDB item
+    ///
+    /// Returns an empty histogram if the item has none
+    #[cfg(feature = "dynamodb")]
+    pub fn from_item(item: &HashMap<String, AttributeValue>) -> Self {
+        let mut histogram = LatencyHistogram::default();
+        for (name, value) in item {
+            let Some(bucket) = name
+                .strip_prefix(ATTRIBUTE_PREFIX)
+                .and_then(|bucket| bucket.parse::<usize>().ok())
+                .filter(|bucket| *bucket < BUCKET_COUNT)
+            else {
+                continue;
+            };
+            let count = value
+                .as_n()
+                .ok()
+                .and_then(|count| count.parse::<u64>().ok())
+                .expect("valid schema");
+            histogram.add(bucket, count);
+        }
+        histogram
+    }
+
+    /// Returns the name of the attribute storing the count of `bucket`
+    pub fn attribute_name(bucket: usize) -> String {
+        format!("{ATTRIBUTE_PREFIX}{bucket}")
+    }
+
+    /// Returns whether `name` is the name of an attribute storing the count of a bucket
+    pub fn is_attribute_name(name: &str) -> bool {
+        name.starts_with(ATTRIBUTE_PREFIX)
+    }
+
+    /// Adds `count` latencies to `bucket`
+    pub fn add(&mut self, bucket: usize, count: u64) {
+        assert!(bucket < BUCKET_COUNT, "bucket {bucket} out of range");
+        if count > 0 {
+            *self.counts.entry(bucket).or_default() += count;
+        }
+    }
+
+    /// Adds all the latencies of `other` to this histogram
+    pub fn merge(&mut self, other: &LatencyHistogram) {
+        for (&bucket, &count) in &other.counts {
+            self.add(bucket, count);
+        }
+    }
+
+    /// Returns the non-empty buckets along with their count, by increasing latency
+    pub fn buckets(&self) -> Vec<(usize, u64)> {
+        let mut buckets = self
+            .counts
+            .iter()
+            .map(|(&bucket, &count)| (bucket, count))
+            .collect::<Vec<_>>();
+        buckets.sort_unstable();
+        buckets
+    }
+
+    /// Returns the number of latencies in the histogram
+    pub fn total(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    /// Returns whether the histogram is empty
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    /// Returns the latency, in milliseconds, below which `quantile` (between 0 and 1) of the
+    /// latencies are
+    ///
+    /// The latency is the upper bound of the bucket holding the quantile, so it overestimates
+    /// the exact quantile by at most the width of a bucket.
+    ///
+    /// Returns [None] if the histogram is empty
+    pub fn quantile(&self, quantile: f64) -> Option<f64> {
+        let total = self.total();
+        if total == 0 {
+            return None;
+        }
+        let rank = ((quantile.clamp(0.0, 1.0) * total as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        self.buckets().into_iter().find_map(|(bucket, count)| {
+            seen += count;
+            (seen >= rank).then(|| Self::upper_bound(bucket))
+        })
+    }
+
+    /// Returns the 50th, 90th and 99th percentiles of the latencies, in milliseconds
+    ///
+    /// Returns [None] if the histogram is empty
+    pub fn percentiles(&self) -> Option<[f64; 3]> {
+        Some([
+            self.quantile(0.5)?,
+            self.quantile(0.9)?,
+            self.quantile(0.99)?,
+        ])
+    }
+}
+
+/// Implements the latency statistics of the `Player` and `LatencyPercentiles` types that a
+/// lambda generates from the GraphQL schema
+///
+/// Generates `LatencyPercentiles::from_histogram`, and `Player::from_item_with_latency` which
+/// reads a player item, computing its `avg_latency`, `avg_latency_clicks` and
+/// `latency_percentiles` from the latency counters and histogram of the item.
+///
+/// The lambda must depend on `aws-sdk-dynamodb` and `serde_dynamo`.
+#[cfg(feature = "dynamodb")]
+#[macro_export]
+macro_rules! impl_latency_statistics {
+    ($player:ty, $percentiles:ident) => {
+        impl $percentiles {
+            /// Computes the percentiles of the latencies counted by `histogram`
+            ///
+            /// Returns [None] if the histogram is empty
+            pub fn from_histogram(histogram: &$crate::LatencyHistogram) -> Option<Self> {
+                let [p50, p90, p99] = histogram.percentiles()?;
+                Some($percentiles { p50, p90, p99 })
+            }
+        }
+
+        impl $player {
+            /// Reads a player item along with its latency histogram
+            ///
+            /// The latency statistics are not stored, but computed from the latency counters
+            /// and histogram of the item.
+            pub fn from_item_with_latency(
+                item: ::std::collections::HashMap<
+                    String,
+                    ::aws_sdk_dynamodb::types::AttributeValue,
+                >,
+            ) -> (Self, $crate::LatencyHistogram) {
+                let totals = $crate::LatencyTotals::from_item(&item);
+                let histogram = $crate::LatencyHistogram::from_item(&item);
+                let mut player: Self = ::serde_dynamo::from_item(item).expect("valid schema");
+                player.avg_latency = totals.avg();
+                player.avg_latency_clicks = (totals.samples > 0).then_some(totals.samples as i32);
+                player.latency_percentiles = $percentiles::from_histogram(&histogram);
+                (player, histogram)
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "dynamodb")]
+    fn test_latency_totals() {
+        let n = |n: &str| AttributeValue::N(n.to_owned());
+        let item = |attributes: &[(&str, &str)]| {
+            attributes
+                .iter()
+                .map(|(name, value)| (name.to_string(), n(value)))
+                .collect::<HashMap<_, _>>()
+        };
+
+        let totals = LatencyTotals::from_item(&item(&[]));
+        assert_eq!(totals, LatencyTotals::default());
+        assert_eq!(totals.avg(), None);
+
+        let totals = LatencyTotals::from_item(&item(&[
+            (TOTAL_ATTRIBUTE, "420.5"),
+            (SAMPLES_ATTRIBUTE, "10"),
+        ]));
+        assert_eq!(totals.samples, 10);
+        assert_eq!(totals.avg(), Some(42.05));
+
+        // Legacy averages are combined with the counters
+        let totals = LatencyTotals::from_item(&item(&[
+            (TOTAL_ATTRIBUTE, "100"),
+            (SAMPLES_ATTRIBUTE, "2"),
+            ("avg_latency", "20"),
+            ("avg_latency_clicks", "3"),
+        ]));
+        assert_eq!(totals.samples, 5);
+        assert_eq!(totals.avg(), Some(32.0));
+    }
+
+    #[test]
+    fn test_bucket_of() {
+        assert_eq!(LatencyHistogram::bucket_of(0.2), 0);
+        assert_eq!(LatencyHistogram::bucket_of(1.0), 0);
+        assert_eq!(LatencyHistogram::bucket_of(2.0), 4);
+        assert_eq!(LatencyHistogram::bucket_of(100.0), 26);
+        assert_eq!(LatencyHistogram::bucket_of(f64::NAN), 0);
+        assert_eq!(LatencyHistogram::bucket_of(1e9), BUCKET_COUNT - 1);
+        for latency in [1.5, 42.0, 120.0, 999.0] {
+            let bucket = LatencyHistogram::bucket_of(latency);
+            assert!(latency < LatencyHistogram::upper_bound(bucket));
+            assert!(latency >= LatencyHistogram::upper_bound(bucket - 1));
+        }
+    }
+
+    #[test]
+    fn test_from_counts() {
+        let histogram = LatencyHistogram::from_counts(&[0, 2, 0, 3]).unwrap();
+        assert_eq!(histogram.buckets(), [(1, 2), (3, 3)]);
+        assert_eq!(histogram.total(), 5);
+        assert_eq!(histogram.to_counts(), [0, 2, 0, 3]);
+        assert!(LatencyHistogram::from_counts(&[]).unwrap().is_empty());
+        assert!(LatencyHistogram::from_counts(&[1, -1]).is_err());
+        assert!(LatencyHistogram::from_counts(&[0; BUCKET_COUNT + 1]).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "dynamodb")]
+    fn test_item_round_trip() {
+        let histogram = LatencyHistogram::from_counts(&[0, 2, 0, 3]).unwrap();
+        let mut item = histogram
+            .buckets()
+            .into_iter()
+            .map(|(bucket, count)| {
+                (
+                    LatencyHistogram::attribute_name(bucket),
+                    AttributeValue::N(count.to_string()),
+                )
+            })
+            .collect::<HashMap<_, _>>();
+        item.insert("clicks".to_owned(), AttributeValue::N("12".to_owned()));
+        assert_eq!(LatencyHistogram::from_item(&item), histogram);
+    }
+
+    #[test]
+    fn test_percentiles() {
+        assert_eq!(LatencyHistogram::default().percentiles(), None);
+
+        // 90 fast latencies, 9 slower and 1 very slow
+        let mut histogram = LatencyHistogram::default();
+        histogram.add(LatencyHistogram::bucket_of(10.0), 90);
+        histogram.add(LatencyHistogram::bucket_of(100.0), 9);
+        let mut slow = LatencyHistogram::default();
+        slow.add(LatencyHistogram::bucket_of(1000.0), 1);
+        histogram.merge(&slow);
+
+        let [p50, p90, p99] = histogram.percentiles().unwrap();
+        assert!((10.0..10.0 * 1.19).contains(&p50));
+        assert_eq!(p90, p50);
+        assert!((100.0..100.0 * 1.19).contains(&p99));
+        let p100 = histogram.quantile(1.0).unwrap();
+        assert!((1000.0..1000.0 * 1.19).contains(&p100));
+    }
+}
@@ -0,0 +1,145 @@
+//! A map holding at most a given number of entries, for the caches of the lambda instances.
+//!
+//! When the map is full, inserting a new entry evicts the least recently used one, rather than
+//! dropping the whole cache at once: the hot entries (e.g. the players clicking right now) stay
+//! cached however many others come and go.
+
+use std::{
+    borrow::Borrow,
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+};
+
+/// A map evicting its least recently used entry when it holds `capacity` entries
+#[derive(Debug)]
+pub struct LruCache<K, V> {
+    capacity: usize,
+    /// The entries, along with the tick of their last use
+    entries: HashMap<K, (V, u64)>,
+    /// The keys of the entries, by tick of their last use
+    recency: BTreeMap<u64, K>,
+    /// Incremented on each use of an entry
+    tick: u64,
+}
+
+impl<K: Hash + Eq + Clone, V> LruCache<K, V> {
+    /// Creates an empty cache holding at most `capacity` entries
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` is 0
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "the capacity of a cache cannot be 0");
+        LruCache {
+            capacity,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    /// Number of entries in the cache
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the cache has no entry
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the value of `key`, if cached, and marks it as the most recently used
+    pub fn get<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.tick += 1;
+        let (value, used_at) = self.entries.get_mut(key)?;
+        let key = self
+            .recency
+            .remove(used_at)
+            .expect("every entry has a tick");
+        *used_at = self.tick;
+        self.recency.insert(self.tick, key);
+        Some(value)
+    }
+
+    /// Caches `value` for `key` as the most recently used entry, evicting the least recently
+    /// used one if the cache is full
+    ///
+    /// Returns the previous value of `key`, if any
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.tick += 1;
+        if let Some((previous, used_at)) = self.entries.get_mut(&key) {
+            self.recency.remove(used_at);
+            *used_at = self.tick;
+            self.recency.insert(self.tick, key);
+            return Some(std::mem::replace(previous, value));
+        }
+        if self.entries.len() >= self.capacity {
+            let (_, evicted) = self.recency.pop_first().expect("the cache is full");
+            self.entries.remove(&evicted);
+        }
+        self.recency.insert(self.tick, key.clone());
+        self.entries.insert(key, (value, self.tick));
+        None
+    }
+
+    /// Removes `key` from the cache, returning its value if it was cached
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (value, used_at) = self.entries.remove(key)?;
+        self.recency.remove(&used_at);
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let mut cache = LruCache::new(2);
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        // "a" is used, so "b" is evicted first
+        assert_eq!(cache.get("a"), Some(&1));
+        cache.insert("c", 3);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get("b"), None);
+        assert_eq!(cache.get("a"), Some(&1));
+        assert_eq!(cache.get("c"), Some(&3));
+    }
+
+    #[test]
+    fn test_insert_replaces() {
+        let mut cache = LruCache::new(2);
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        // Replacing a value does not evict anything, and marks it as used
+        assert_eq!(cache.insert("a", 10), Some(1));
+        assert_eq!(cache.len(), 2);
+        cache.insert("c", 3);
+        assert_eq!(cache.get("a"), Some(&10));
+        assert_eq!(cache.get("b"), None);
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut cache = LruCache::new(2);
+        cache.insert("a", 1);
+        assert_eq!(cache.remove("a"), Some(1));
+        assert_eq!(cache.remove("a"), None);
+        assert!(cache.is_empty());
+        // The removed entry does not take a slot anymore
+        cache.insert("b", 2);
+        cache.insert("c", 3);
+        assert_eq!(cache.get("b"), Some(&2));
+        assert_eq!(cache.get("c"), Some(&3));
+    }
+}
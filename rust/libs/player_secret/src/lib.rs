@@ -0,0 +1,237 @@
+//! Utility module for storing and verifying player secrets.
+//!
+//! Player secrets are never stored in plaintext: they are hashed with PBKDF2-HMAC-SHA256,
+//! using a random salt per secret and a high iteration count so that a leaked table cannot
+//! be used to impersonate players. The hash is stored in the PHC string format, e.g.
+//! `$pbkdf2-sha256$i=<iterations>,l=32$<salt>$<hash>` (salt and hash being unpadded base64), as
+//! produced and verified by the `pbkdf2` crate.
+//!
+//! Players registered before hashing was introduced still have their plaintext secret in the
+//! `secret` attribute. [StoredSecret] handles both, so that callers can migrate a plaintext
+//! secret to its hash on the first successful use.
+
+use std::collections::HashMap;
+
+use aws_sdk_dynamodb::types::AttributeValue;
+use pbkdf2::{
+    Algorithm, Params, Pbkdf2,
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+/// Name of the attribute storing the hashed secret of a player
+pub static HASH_ATTRIBUTE: &str = "secret_hash";
+
+/// Name of the legacy attribute storing the plaintext secret of a player
+pub static PLAINTEXT_ATTRIBUTE: &str = "secret";
+
+/// Number of PBKDF2 iterations for newly hashed secrets
+const ITERATIONS: u32 = 100_000;
+
+/// Length of the random salt, in bytes
+const SALT_LEN: usize = 16;
+
+/// Hashes `secret` with the given `salt` and `iterations`, returning the PHC string
+fn hash_secret_with(secret: &str, salt: &[u8], iterations: u32) -> String {
+    let salt = SaltString::encode_b64(salt).expect("the salt length is valid");
+    Pbkdf2
+        .hash_password_customized(
+            secret.as_bytes(),
+            Some(Algorithm::Pbkdf2Sha256.ident()),
+            None,
+            Params {
+                rounds: iterations,
+                ..Params::default()
+            },
+            &salt,
+        )
+        .expect("the parameters are valid")
+        .to_string()
+}
+
+/// Hashes `secret` with a new random salt
+///
+/// Returns the PHC string to store in the [HASH_ATTRIBUTE] attribute
+pub fn hash_secret(secret: &str) -> String {
+    let mut salt = [0u8; SALT_LEN];
+    getrandom::fill(&mut salt).expect("the system random generator is available");
+    hash_secret_with(secret, &salt, ITERATIONS)
+}
+
+/// Verifies `secret` against a PHC string produced by [hash_secret]
+///
+/// Returns `false` if the PHC string is malformed, or uses another algorithm than
+/// PBKDF2-HMAC-SHA256
+pub fn verify_hashed_secret(secret: &str, phc: &str) -> bool {
+    let Ok(hash) = PasswordHash::new(phc) else {
+        return false;
+    };
+    if hash.algorithm != Algorithm::Pbkdf2Sha256.ident()
+        || Params::try_from(&hash).is_ok_and(|params| params.rounds == 0)
+    {
+        return false;
+    }
+    Pbkdf2.verify_password(secret.as_bytes(), &hash).is_ok()
+}
+
+/// Returns a digest of `secret` that is the same for every use within `scope`
+///
+/// It is a SHA-256 of the scope and the secret, so that it can be stored and looked up, e.g. to
+/// recognize the secret of a banned player, without slowing down every registration as
+/// [hash_secret] would. Returned as lowercase hexadecimal.
+pub fn scoped_digest(secret: &str, scope: &str) -> String {
+    let digest = Sha256::new()
+        // Prefixed with its length, so that no other scope and secret give the same input
+        .chain_update((scope.len() as u64).to_be_bytes())
+        .chain_update(scope.as_bytes())
+        .chain_update(secret.as_bytes())
+        .finalize();
+    format!("{digest:x}")
+}
+
+/// Runs `f`, which hashes or verifies secrets, on a thread dedicated to blocking work
+///
+/// Hashing is deliberately slow, so it must be kept off the threads of the async runtime, which
+/// would otherwise stop serving the other requests meanwhile.
+pub async fn run_blocking<T, F>(f: F) -> T
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .expect("hashing does not panic")
+}
+
+/// Returns a fast, unsalted digest of `secret`
+///
+/// Only meant to recognize a secret that was already verified, without keeping it in memory.
+/// It must never be stored.
+pub fn fingerprint(secret: &str) -> [u8; 32] {
+    Sha256::digest(secret.as_bytes()).into()
+}
+
+/// The secret of a player, as stored in DynamoDB
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StoredSecret {
+    /// PHC string of the hashed secret, stored in [HASH_ATTRIBUTE]
+    Hashed(String),
+    /// Legacy plaintext secret, stored in [PLAINTEXT_ATTRIBUTE]
+    Plaintext(String),
+}
+
+impl StoredSecret {
+    /// Extracts the stored secret from a player item
+    ///
+    /// The hashed secret takes precedence if both attributes are present
+    pub fn from_item(item: &HashMap<String, AttributeValue>) -> Option<Self> {
+        if let Some(Ok(phc)) = item.get(HASH_ATTRIBUTE).map(AttributeValue::as_s) {
+            Some(Self::Hashed(phc.to_owned()))
+        } else if let Some(Ok(secret)) = item.get(PLAINTEXT_ATTRIBUTE).map(AttributeValue::as_s) {
+            Some(Self::Plaintext(secret.to_owned()))
+        } else {
+            None
+        }
+    }
+
+    /// Verifies that `secret` matches the stored secret
+    pub fn verify(&self, secret: &str) -> bool {
+        match self {
+            Self::Hashed(phc) => verify_hashed_secret(secret, phc),
+            Self::Plaintext(stored) => stored.as_bytes().ct_eq(secret.as_bytes()).into(),
+        }
+    }
+
+    /// Returns whether the secret is still stored in plaintext and should be migrated
+    pub fn is_plaintext(&self) -> bool {
+        matches!(self, Self::Plaintext(_))
+    }
+
+    /// Returns the name of the attribute holding the stored secret
+    pub fn attribute_name(&self) -> &'static str {
+        match self {
+            Self::Hashed(_) => HASH_ATTRIBUTE,
+            Self::Plaintext(_) => PLAINTEXT_ATTRIBUTE,
+        }
+    }
+
+    /// Returns the stored value, to be used in a condition expression ensuring
+    /// the secret did not change since it was verified
+    pub fn to_attribute_value(&self) -> AttributeValue {
+        match self {
+            Self::Hashed(value) | Self::Plaintext(value) => AttributeValue::S(value.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_hashed_secret() {
+        let phc = hash_secret_with("my-secret", b"0123456789abcdef", 10);
+        assert!(phc.starts_with("$pbkdf2-sha256$i=10,l=32$"));
+        assert!(verify_hashed_secret("my-secret", &phc));
+        assert!(!verify_hashed_secret("not-my-secret", &phc));
+        assert!(!verify_hashed_secret("my-secret", "my-secret"));
+        assert!(!verify_hashed_secret(
+            "my-secret",
+            "$pbkdf2-sha256$i=0$c2FsdA$AA"
+        ));
+        assert!(!verify_hashed_secret(
+            "my-secret",
+            &phc.replace("pbkdf2-sha256", "pbkdf2")
+        ));
+    }
+
+    #[test]
+    fn test_verify_hashed_secret_without_length() {
+        // PBKDF2-HMAC-SHA256 of "password" salted with "salt" in 2 iterations, without the output
+        // length in the PHC string, as secrets were first hashed
+        let phc = "$pbkdf2-sha256$i=2$c2FsdA$rk0Mla9rRtMtCt/5KPBt0CowP47zwlHf1uLYWpVHTEM";
+        assert!(verify_hashed_secret("password", phc));
+        assert!(!verify_hashed_secret("Password", phc));
+    }
+
+    #[test]
+    fn test_scoped_digest() {
+        let digest = scoped_digest("my-secret", "game");
+        assert_eq!(digest, scoped_digest("my-secret", "game"));
+        assert_ne!(digest, scoped_digest("my-secret", "other-game"));
+        assert_ne!(digest, scoped_digest("not-my-secret", "game"));
+        assert_ne!(digest, scoped_digest("secret", "gamemy-"));
+        // 32 bytes in hexadecimal
+        assert_eq!(digest.len(), 64);
+    }
+
+    #[test]
+    fn test_hash_secret_is_salted() {
+        let phc1 = hash_secret("my-secret");
+        let phc2 = hash_secret("my-secret");
+        assert_ne!(phc1, phc2);
+        assert!(verify_hashed_secret("my-secret", &phc1));
+    }
+
+    #[test]
+    fn test_stored_secret_from_item() {
+        let phc = hash_secret_with("my-secret", b"salt", 10);
+        let mut item = HashMap::from([(
+            PLAINTEXT_ATTRIBUTE.to_owned(),
+            AttributeValue::S("my-secret".to_owned()),
+        )]);
+        let plaintext = StoredSecret::from_item(&item).unwrap();
+        assert!(plaintext.is_plaintext());
+        assert!(plaintext.verify("my-secret"));
+        assert!(!plaintext.verify("my-secre"));
+
+        item.insert(HASH_ATTRIBUTE.to_owned(), AttributeValue::S(phc.clone()));
+        let hashed = StoredSecret::from_item(&item).unwrap();
+        assert_eq!(hashed, StoredSecret::Hashed(phc));
+        assert_eq!(hashed.attribute_name(), HASH_ATTRIBUTE);
+        assert!(hashed.verify("my-secret"));
+
+        assert_eq!(StoredSecret::from_item(&HashMap::new()), None);
+    }
+}
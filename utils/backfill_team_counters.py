@@ -0,0 +1,110 @@
+import argparse
+from collections import Counter
+
+import boto3
+
+# Number of passes over the counters that changed while they were being backfilled
+MAX_PASSES = 5
+
+def is_conditional_check_failed(e):
+    return e.response['Error']['Code'] == 'ConditionalCheckFailedException'
+
+def scan(filter_expression, projection, names, values):
+    paginator = dynamodb.get_paginator('scan')
+    for page in paginator.paginate(
+        TableName=table_name,
+        ConsistentRead=True,
+        FilterExpression=filter_expression,
+        ProjectionExpression=projection,
+        ExpressionAttributeNames=names,
+        ExpressionAttributeValues=values,
+    ):
+        yield from page['Items']
+
+def get_counters():
+    # Returns the value of each existing counter, by (game_id, team)
+    return {
+        (item['game_id']['S'], item['team']['S']): int(item['players']['N'])
+        for item in scan(
+            '#type = :counter_type',
+            'game_id, team, players',
+            {'#type': '_TYPE'},
+            {':counter_type': {'S': 'TEAM_COUNT'}},
+        )
+    }
+
+def count_players():
+    # Returns the number of players of each team of each game, by (game_id, team)
+    return Counter(
+        (item['game_id']['S'], item['team']['S'])
+        for item in scan(
+            '#type = :player_type AND attribute_exists(game_id)',
+            'game_id, team',
+            {'#type': '_TYPE'},
+            {':player_type': {'S': 'PLAYER'}},
+        )
+    )
+
+def set_counter(game_id, team, count, expected):
+    # Same item as the Rust backend, conditioned on the counter not changing since it was read
+    condition = 'attribute_not_exists(players)' if expected is None else 'players = :expected'
+    values = {
+        ':counter_type': {'S': 'TEAM_COUNT'},
+        ':game_id': {'S': game_id},
+        ':team': {'S': team},
+        ':count': {'N': str(count)},
+    }
+    if expected is not None:
+        values[':expected'] = {'N': str(expected)}
+    try:
+        dynamodb.update_item(
+            TableName=table_name,
+            Key={'PK': {'S': f"TEAM_COUNT#{game_id}#{team}"}},
+            UpdateExpression='SET #type = :counter_type, game_id = :game_id, team = :team, players = :count',
+            ConditionExpression=condition,
+            ExpressionAttributeNames={'#type': '_TYPE'},
+            ExpressionAttributeValues=values,
+        )
+        print(f"Game {game_id}, team {team}: {expected} -> {count} players")
+        return True
+    except dynamodb.exceptions.ClientError as e:
+        if not is_conditional_check_failed(e):
+            raise
+        return False
+
+def backfill():
+    # Returns whether every counter matches the players of its team
+    # The counters are read before the players: a player who registers, leaves or switches team
+    # after that changes a counter, whose update then fails and is retried on the next pass
+    counters = get_counters()
+    counts = count_players()
+    up_to_date = True
+    for game_id, team in sorted(set(counters) | set(counts)):
+        expected = counters.get((game_id, team))
+        count = counts[(game_id, team)]
+        if expected != count and not set_counter(game_id, team, count, expected):
+            print(f"Game {game_id}, team {team}: players changed meanwhile, retrying")
+            up_to_date = False
+    return up_to_date
+
+
+if __name__ == "__main__":
+    parser = argparse.ArgumentParser(
+        prog='backfill_team_counters',
+        description='Set the player counter of each team of each game to its number of players, for the games that had players before the counters were introduced. It can be run again safely, including while players register.',
+    )
+
+    parser.add_argument('--table-name', required=True)
+    args = parser.parse_args()
+
+    global dynamodb
+    dynamodb = boto3.client('dynamodb')
+    global table_name
+    table_name = args.table_name
+
+    for _ in range(MAX_PASSES):
+        if backfill():
+            print("Done, every team counter is up to date")
+            exit(0)
+    print(f"Some counters kept changing after {MAX_PASSES} passes, run the script again")
+    exit(1)
@@ -0,0 +1,171 @@
+import argparse
+import time
+import uuid
+
+import boto3
+
+# Key of the game status item from before games were introduced
+LEGACY_GAME_STATUS_PK = 'GAME_STATUS'
+
+def now_seconds():
+    return int(time.time())
+
+def new_state_version():
+    # Same as the Rust backend: the current time in milliseconds since the epoch
+    return time.time_ns() // 1000000
+
+def is_conditional_check_failed(e):
+    return e.response['Error']['Code'] == 'ConditionalCheckFailedException'
+
+def get_legacy_game_status():
+    item = dynamodb.get_item(
+        TableName=table_name,
+        Key={'PK': {'S': LEGACY_GAME_STATUS_PK}},
+        ConsistentRead=True,
+    ).get('Item')
+    return item['game_status']['S'] if item is not None else None
+
+def list_legacy_player_keys():
+    keys = []
+    paginator = dynamodb.get_paginator('scan')
+    for page in paginator.paginate(
+        TableName=table_name,
+        ConsistentRead=True,
+        FilterExpression='#type = :player_type AND attribute_not_exists(game_id)',
+        ProjectionExpression='PK',
+        ExpressionAttributeNames={'#type': '_TYPE'},
+        ExpressionAttributeValues={':player_type': {'S': 'PLAYER'}},
+    ):
+        keys.extend(page['Items'])
+    return keys
+
+def create_game(game_id, name):
+    try:
+        dynamodb.put_item(
+            TableName=table_name,
+            Item={
+                'PK': {'S': f"GAME#{game_id}"},
+                '_TYPE': {'S': 'GAME'},
+                'id': {'S': game_id},
+                'name': {'S': name},
+            },
+            ConditionExpression='attribute_not_exists(PK)',
+        )
+        print(f"Created the game {name} ({game_id})")
+    except dynamodb.exceptions.ClientError as e:
+        if not is_conditional_check_failed(e):
+            raise
+        print(f"The game {game_id} already exists, reusing it")
+
+def put_game_state(game_id, status):
+    # Returns the state of the game, which is left untouched if the game already has one
+    state = {
+        'PK': {'S': f"GAME_STATUS#{game_id}"},
+        '_TYPE': {'S': 'GAME_STATUS'},
+        'game_id': {'S': game_id},
+        'game_status': {'S': status},
+        'state_version': {'N': str(new_state_version())},
+    }
+    if status == 'STARTED':
+        # The start time of the legacy round is unknown, count from the migration
+        state['started_at'] = {'N': str(now_seconds())}
+    try:
+        dynamodb.put_item(
+            TableName=table_name,
+            Item=state,
+            ConditionExpression='attribute_not_exists(PK)',
+        )
+        print(f"Game {game_id} is {status}")
+        return state
+    except dynamodb.exceptions.ClientError as e:
+        if not is_conditional_check_failed(e):
+            raise
+        state = dynamodb.get_item(
+            TableName=table_name,
+            Key={'PK': {'S': f"GAME_STATUS#{game_id}"}},
+            ConsistentRead=True,
+        )['Item']
+        print(f"Game {game_id} already has a state, keeping it: {state['game_status']['S']}")
+        return state
+
+def clicks_open_at(state):
+    # Same as `GameState::clicks_open_at` of the Rust backend
+    status = state['game_status']['S']
+    if status == 'STARTED':
+        return {'N': '0'}
+    if status == 'COUNTDOWN':
+        return state.get('starts_at')
+    return None
+
+def assign_player(player_key, game_id, state):
+    # The state of the game is copied to the player, as each change of the state does
+    update_expression = 'SET game_id = :game_id, state_version = :state_version'
+    values = {
+        ':game_id': {'S': game_id},
+        ':state_version': state['state_version'],
+    }
+    open_at = clicks_open_at(state)
+    if open_at is not None:
+        update_expression += ', clicks_open_at = :clicks_open_at'
+        values[':clicks_open_at'] = open_at
+    try:
+        dynamodb.update_item(
+            TableName=table_name,
+            Key=player_key,
+            UpdateExpression=update_expression,
+            # The player may have been removed or migrated meanwhile
+            ConditionExpression='attribute_exists(PK) AND attribute_not_exists(game_id)',
+            ExpressionAttributeValues=values,
+        )
+    except dynamodb.exceptions.ClientError as e:
+        if not is_conditional_check_failed(e):
+            raise
+
+def delete_legacy_game_status(status):
+    try:
+        dynamodb.delete_item(
+            TableName=table_name,
+            Key={'PK': {'S': LEGACY_GAME_STATUS_PK}},
+            ConditionExpression='game_status = :status',
+            ExpressionAttributeValues={':status': {'S': status}},
+        )
+        print("Deleted the legacy game status")
+    except dynamodb.exceptions.ClientError as e:
+        if not is_conditional_check_failed(e):
+            raise
+        print("The legacy game status changed during the migration, run it again")
+
+
+if __name__ == "__main__":
+    parser = argparse.ArgumentParser(
+        prog='migrate_legacy_game',
+        description='Move the players and the game status from before games were introduced to a default game. The migration can be run again safely, for instance if it was interrupted.',
+    )
+
+    parser.add_argument('--table-name', required=True)
+    parser.add_argument('--game-id', help='Existing game to move the legacy players to, by default a new game is created')
+    parser.add_argument('--game-name', default='Legacy game')
+    args = parser.parse_args()
+
+    global dynamodb
+    dynamodb = boto3.client('dynamodb')
+    global table_name
+    table_name = args.table_name
+
+    legacy_status = get_legacy_game_status()
+    player_keys = list_legacy_player_keys()
+    if legacy_status is None and len(player_keys) == 0:
+        print("Nothing to migrate")
+        exit(0)
+
+    game_id = args.game_id or str(uuid.uuid4())
+    create_game(game_id, args.game_name)
+    state = put_game_state(game_id, legacy_status or 'RESET')
+
+    print(f"Moving {len(player_keys)} players to the game {game_id}...")
+    for player_key in player_keys:
+        assign_player(player_key, game_id, state)
+
+    if legacy_status is not None:
+        delete_legacy_game_status(legacy_status)
+    print(f"Done, the legacy game is at ?game={game_id}")
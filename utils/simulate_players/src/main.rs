@@ -1,8 +1,8 @@
 //! This binary simulates multiple players participating in a GraphQL API benchmark game.
 //!
 //! The game mechanics are simple - players click a button as fast as they can and the API
-//! measures response times. Each player is randomly assigned to one of four teams, where
-//! each team uses a different backend implementation (Rust, Python, JavaScript, or VTL).
+//! measures response times. Each player is assigned to one of the teams of the game, where
+//! each team is served by one of the backend implementations (Rust, Python, JavaScript, or VTL).
 //!
 //! # Usage
 //!
@@ -11,6 +11,7 @@
 //! ./simulate_players \
 //!     --api-endpoint "https://xxxxx.appsync-api.region.amazonaws.com/graphql" \
 //!     --api-key "da2-xxxxxxxxxxxxxxxxxxxx" \
+//!     --game-id "00000000-0000-0000-0000-000000000000" \
 //!     --players 10 \
 //!     --register-only
 //! ```
@@ -20,6 +21,7 @@
 //! ./simulate_players \
 //!     --api-endpoint "https://xxxxx.appsync-api.region.amazonaws.com/graphql" \
 //!     --api-key "da2-xxxxxxxxxxxxxxxxxxxx" \
+//!     --game-id "00000000-0000-0000-0000-000000000000" \
 //!     --players 10 \        # Number of concurrent players to simulate
 //!     --frequency 7 \       # How many clicks per second per player
 //!     --duration 20         # How long to run the simulation in seconds
@@ -27,15 +29,17 @@
 //!
 //! The simulation will:
 //! 1. Load previously registered players from the config file (or register new ones if needed)
-//! 2. Start a task for each simulated player that will:
+//! 2. Read the list of teams to learn which backend serves each player
+//! 3. Start a task for each simulated player that will:
 //!    - Click at the specified frequency by calling the appropriate GraphQL mutation
 //!    - Measure response time for each click
-//!    - Report average latency statistics every second
-//! 3. Run until the specified duration has elapsed
+//!    - Report average latency statistics, along with a latency histogram, every second
+//! 4. Run until the specified duration has elapsed
 //!
 //! # Notes
 //!
 //! - Players are persisted in `simulate_players.config.txt` (override with --config)
+//! - Players are enrolled in the game given by --game-id, use a different config file for each game
 //! - Each player has a unique ID and secret key used to authenticate their actions
 //! - The simulation tries to maintain consistent click timing to match the requested frequency
 //! - Failed API calls and GraphQL errors are logged but don't stop the simulation
@@ -43,10 +47,12 @@
 
 /// Imports required for this binary which simulates multiple players in the AppSync GraphQL Benchmark Game
 use clap::Parser;
+use latency_histogram::LatencyHistogram;
 use reqwest::Client;
 use serde::Deserialize;
 use serde_json::json;
 use std::{
+    collections::HashMap,
     error::Error,
     sync::Arc,
     time::{Duration, Instant},
@@ -71,18 +77,21 @@ struct ApiCaller {
     url: Arc<str>,
     /// The API key used for authentication
     key: Arc<str>,
+    /// The ID of the game (room) the simulated players are enrolled in
+    game_id: Arc<str>,
 }
 impl ApiCaller {
     /// Creates a new ApiCaller instance for the given API endpoint
     ///
     /// Returns:
     ///   A new ApiCaller ready to make requests
-    fn new(url: String, key: String) -> Self {
+    fn new(url: String, key: String, game_id: String) -> Self {
         ApiCaller {
             client: reqwest::Client::new(),
             // Use Arc<str> to efficiently share these strings between async tasks
             url: Arc::from(url),
             key: Arc::from(key),
+            game_id: Arc::from(game_id),
         }
     }
 }
@@ -94,7 +103,7 @@ struct PlayerConfig {
     idx: usize,
     /// Player's display name
     name: String,
-    /// The team assigned to this player (RUST, PYTHON, JS or VTL)
+    /// The ID of the team assigned to this player
     team: String,
     /// Unique identifier for this player
     id: String,
@@ -175,13 +184,14 @@ async fn call_api(api: ApiCaller, api_req: &str) -> Result<serde_json::Value, St
 ///
 /// # Notes
 ///
-/// The player will be randomly assigned to one of the teams (RUST, PYTHON, JS, VTL)
+/// The player will be assigned to one of the teams by the game
 async fn register_player(api: ApiCaller, idx: usize) -> Result<PlayerConfig, String> {
     let player_name = format!("Player{idx}");
     let player_secret = uuid::Uuid::new_v4().to_string();
+    let game_id = api.game_id.clone();
     // Send the registerNewPlayer mutation
     let req = format!(
-        "mutation{{registerNewPlayer(name:\"{player_name}\",secret:\"{player_secret}\"){{id name team}}}}"
+        "mutation{{registerNewPlayer(game_id:\"{game_id}\",name:\"{player_name}\",secret:\"{player_secret}\"){{id game_id name team}}}}"
     );
     let new_player = call_api(api.clone(), &req)
         .await?
@@ -250,33 +260,65 @@ async fn get_players(
     Ok(players)
 }
 
-/// Returns the appropriate click mutation name for a given team
+/// Reads the teams and returns the backend serving each of them, by team ID
+///
+/// Returns:
+///   A map from team ID to backend name (RUST, PYTHON, JS or VTL)
+async fn get_team_backends(api: ApiCaller) -> Result<HashMap<String, String>, String> {
+    let mut teams = call_api(api, "query{teams{id name backend}}").await?;
+    let teams = teams
+        .get_mut("teams")
+        .and_then(|teams| teams.as_array_mut())
+        .ok_or_else(|| "Could not read the teams".to_owned())?;
+    Ok(teams
+        .iter()
+        .filter_map(|team| {
+            let id = team.get("id")?.as_str()?;
+            let backend = team.get("backend")?.as_str()?;
+            log::debug!("Team {id} is served by the {backend} backend");
+            Some((id.to_owned(), backend.to_owned()))
+        })
+        .collect())
+}
+
+/// Returns the appropriate click mutation name for a given backend
 ///
 /// # Panics
 ///
-/// Panics if the team name is not one of: RUST, PYTHON, JS, VTL
-fn click_mutation_for_team(team: &str) -> &'static str {
-    match team {
-        "RUST" => "clickRust",
+/// Panics if the backend is not one of: RUST, PYTHON, JS, VTL
+fn click_mutation_for_backend(backend: &str) -> &'static str {
+    match backend {
+        "RUST" => "click",
         "PYTHON" => "clickPython",
         "JS" => "clickJs",
         "VTL" => "clickVtl",
-        _ => panic!("Unknown team: {team}"),
+        _ => panic!("Unknown backend: {backend}"),
     }
 }
 
-/// Returns the appropriate latency report mutation name for a given team
+/// Returns the appropriate latency report mutation name for a given backend
 ///
 /// # Panics
 ///
-/// Panics if the team name is not one of: RUST, PYTHON, JS, VTL
-fn report_mutation_for_team(team: &str) -> &'static str {
-    match team {
-        "RUST" => "reportLatencyRust",
+/// Panics if the backend is not one of: RUST, PYTHON, JS, VTL
+fn report_mutation_for_backend(backend: &str) -> &'static str {
+    match backend {
+        "RUST" => "reportLatency",
         "PYTHON" => "reportLatencyPython",
         "JS" => "reportLatencyJs",
         "VTL" => "reportLatencyVtl",
-        _ => panic!("Unknown team: {team}"),
+        _ => panic!("Unknown backend: {backend}"),
+    }
+}
+
+/// Returns the team argument expected by the mutations of a given backend
+///
+/// Only the generic mutations served by the Rust backend take the team of the player,
+/// the mutations of the other backends are dedicated to them.
+fn team_argument_for_backend(backend: &str, team: &str) -> String {
+    match backend {
+        "RUST" => format!("team_id:\"{team}\","),
+        _ => String::new(),
     }
 }
 
@@ -287,21 +329,48 @@ fn report_mutation_for_team(team: &str) -> &'static str {
 ///
 /// # Notes
 ///
-/// The measured latency is sent through the report_pipe channel
+/// The measured latency of a successful click is sent through the report_pipe channel, unless
+/// the backend spent time verifying the hashed secret of the player, which is not part of the
+/// benchmark
 async fn metered_click(
     api: ApiCaller,
     req: Arc<str>,
     report_pipe: tokio::sync::mpsc::UnboundedSender<u128>,
 ) -> Result<(), String> {
     let start = Instant::now();
-    call_api(api, &req).await?;
+    let response = call_api(api, &req).await?;
     let end = Instant::now();
     let duration_milli = (end - start).as_millis();
     log::debug!("Call took {duration_milli}ms");
-    report_pipe.send(duration_milli).map_err(e_to_s)?;
+    // Only recorded clicks can be reported, a rejected one (e.g. rate limited) is not timed
+    if response.get("errors").is_some() {
+        return Err("Click rejected".to_owned());
+    }
+    let secret_ms = response
+        .as_object()
+        .and_then(|data| data.values().next())
+        .and_then(|player| player["server_timing"]["secret_ms"].as_f64())
+        .unwrap_or_default();
+    if secret_ms == 0.0 {
+        report_pipe.send(duration_milli).map_err(e_to_s)?;
+    }
     Ok(())
 }
 
+/// Returns the latency histogram of a report, as a GraphQL list of counts by bucket
+fn histogram_argument(latencies: &[u128]) -> String {
+    let mut histogram = LatencyHistogram::default();
+    for &latency in latencies {
+        histogram.add(LatencyHistogram::bucket_of(latency as f64), 1);
+    }
+    let counts = histogram
+        .to_counts()
+        .iter()
+        .map(u64::to_string)
+        .collect::<Vec<_>>();
+    format!("[{}]", counts.join(","))
+}
+
 /// Reports accumulated latency statistics to the API
 ///
 /// Returns:
@@ -309,6 +378,7 @@ async fn metered_click(
 async fn report(
     api: ApiCaller,
     report_mutation: &str,
+    team_argument: &str,
     id: &str,
     secret: &str,
     latencies: &mut Vec<u128>,
@@ -317,9 +387,11 @@ async fn report(
     if clicks > 0 {
         // Calculate average latency
         let avg = latencies.iter().sum::<u128>() as f64 / clicks as f64;
+        let histogram = histogram_argument(latencies);
+        let game_id = api.game_id.clone();
         // Send the report mutation
         let click_req = format!(
-            "mutation{{{report_mutation}(player_id:\"{id}\",report:{{clicks:{clicks},avg_latency:{avg}}},secret:\"{secret}\"){{id name team clicks avg_latency avg_latency_clicks}}}}"
+            "mutation{{{report_mutation}(game_id:\"{game_id}\",{team_argument}player_id:\"{id}\",report:{{clicks:{clicks},avg_latency:{avg},histogram:{histogram}}},secret:\"{secret}\"){{id game_id name team clicks avg_latency avg_latency_clicks}}}}"
         );
         if call_api(api.clone(), &click_req)
             .await?
@@ -345,6 +417,7 @@ async fn report(
 async fn latency_reporter(
     api: ApiCaller,
     player: PlayerConfig,
+    backend: String,
     mut report_pipe: tokio::sync::mpsc::UnboundedReceiver<u128>,
 ) -> Result<(), String> {
     let PlayerConfig {
@@ -355,7 +428,8 @@ async fn latency_reporter(
         secret,
     } = player;
     log::info!("Starting latency reporter for {name}({id})");
-    let report_mutation = report_mutation_for_team(&team);
+    let report_mutation = report_mutation_for_backend(&backend);
+    let team_argument = team_argument_for_backend(&backend, &team);
     let mut latencies: Vec<u128> = Vec::new();
     // Schedule next report in 1 second
     let mut next_report = Instant::now() + Duration::from_secs(1);
@@ -367,12 +441,28 @@ async fn latency_reporter(
 
         // Send report if it's time
         if now > next_report {
-            report(api.clone(), report_mutation, &id, &secret, &mut latencies).await?;
+            report(
+                api.clone(),
+                report_mutation,
+                &team_argument,
+                &id,
+                &secret,
+                &mut latencies,
+            )
+            .await?;
             next_report = now + Duration::from_secs(1);
         }
     }
     // Send final report
-    report(api, report_mutation, &id, &secret, &mut latencies).await?;
+    report(
+        api,
+        report_mutation,
+        &team_argument,
+        &id,
+        &secret,
+        &mut latencies,
+    )
+    .await?;
     log::info!("Stopped latency reporter for {name}({id})");
     Ok(())
 }
@@ -389,6 +479,7 @@ async fn latency_reporter(
 async fn player_play(
     api: ApiCaller,
     player: PlayerConfig,
+    backend: String,
     click_freq: u64,
     duration: u64,
 ) -> Result<(), String> {
@@ -403,12 +494,19 @@ async fn player_play(
 
     // Setup channel for latency reporting
     let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
-    let latency_reporter = tokio::spawn(latency_reporter(api.clone(), player, receiver));
+    let latency_reporter = tokio::spawn(latency_reporter(
+        api.clone(),
+        player,
+        backend.clone(),
+        receiver,
+    ));
 
     // Prepare click mutation
-    let click_mutation = click_mutation_for_team(&team);
+    let click_mutation = click_mutation_for_backend(&backend);
+    let team_argument = team_argument_for_backend(&backend, &team);
+    let game_id = api.game_id.clone();
     let click_req: Arc<str> = Arc::from(format!(
-        "mutation{{{click_mutation}(player_id:\"{id}\",secret:\"{secret}\"){{id name team clicks avg_latency avg_latency_clicks}}}}"
+        "mutation{{{click_mutation}(game_id:\"{game_id}\",{team_argument}player_id:\"{id}\",secret:\"{secret}\"){{id game_id name team clicks avg_latency avg_latency_clicks server_timing{{secret_ms}}}}}}"
     ));
 
     // Calculate when to stop clicking
@@ -452,6 +550,9 @@ pub struct CliParser {
     /// API key for authentication
     #[arg(long)]
     pub api_key: String,
+    /// ID of the game (room) to enrol the players in
+    #[arg(long)]
+    pub game_id: String,
     /// Number of players to simulate (default: 100)
     #[arg(short, long, default_value_t = 100)]
     pub players: usize,
@@ -482,7 +583,11 @@ async fn main() {
     // Parse command line arguments
     let cli_parser = CliParser::parse();
     log::debug!("Processing {:?}", cli_parser);
-    let api = ApiCaller::new(cli_parser.api_endpoint, cli_parser.api_key);
+    let api = ApiCaller::new(
+        cli_parser.api_endpoint,
+        cli_parser.api_key,
+        cli_parser.game_id,
+    );
 
     // Get or create required players
     let Ok(players) = get_players(api.clone(), cli_parser.players, cli_parser.config).await else {
@@ -492,16 +597,26 @@ async fn main() {
         return;
     }
 
+    // Learn which backend serves each team
+    let Ok(team_backends) = get_team_backends(api.clone()).await else {
+        return;
+    };
+
     // Start all player tasks
     let player_tasks = players
         .into_iter()
-        .map(|p| {
-            tokio::spawn(player_play(
+        .filter_map(|p| {
+            let Some(backend) = team_backends.get(&p.team) else {
+                log::error!("Team {} of {}({}) does not exist", p.team, p.name, p.id);
+                return None;
+            };
+            Some(tokio::spawn(player_play(
                 api.clone(),
                 p,
+                backend.clone(),
                 cli_parser.frequency,
                 cli_parser.duration,
-            ))
+            )))
         })
         .collect::<Vec<_>>();
     // Wait for all players to finish
@@ -543,30 +658,46 @@ mod tests {
     }
 
     #[test]
-    fn test_click_mutation_for_team() {
-        assert_eq!(click_mutation_for_team("RUST"), "clickRust");
-        assert_eq!(click_mutation_for_team("PYTHON"), "clickPython");
-        assert_eq!(click_mutation_for_team("JS"), "clickJs");
-        assert_eq!(click_mutation_for_team("VTL"), "clickVtl");
+    fn test_click_mutation_for_backend() {
+        assert_eq!(click_mutation_for_backend("RUST"), "click");
+        assert_eq!(click_mutation_for_backend("PYTHON"), "clickPython");
+        assert_eq!(click_mutation_for_backend("JS"), "clickJs");
+        assert_eq!(click_mutation_for_backend("VTL"), "clickVtl");
     }
 
     #[test]
-    #[should_panic(expected = "Unknown team: INVALID")]
-    fn test_click_mutation_for_invalid_team() {
-        click_mutation_for_team("INVALID");
+    #[should_panic(expected = "Unknown backend: INVALID")]
+    fn test_click_mutation_for_invalid_backend() {
+        click_mutation_for_backend("INVALID");
     }
 
     #[test]
-    fn test_report_mutation_for_team() {
-        assert_eq!(report_mutation_for_team("RUST"), "reportLatencyRust");
-        assert_eq!(report_mutation_for_team("PYTHON"), "reportLatencyPython");
-        assert_eq!(report_mutation_for_team("JS"), "reportLatencyJs");
-        assert_eq!(report_mutation_for_team("VTL"), "reportLatencyVtl");
+    fn test_report_mutation_for_backend() {
+        assert_eq!(report_mutation_for_backend("RUST"), "reportLatency");
+        assert_eq!(report_mutation_for_backend("PYTHON"), "reportLatencyPython");
+        assert_eq!(report_mutation_for_backend("JS"), "reportLatencyJs");
+        assert_eq!(report_mutation_for_backend("VTL"), "reportLatencyVtl");
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown backend: INVALID")]
+    fn test_report_mutation_for_invalid_backend() {
+        report_mutation_for_backend("INVALID");
+    }
+
+    #[test]
+    fn test_team_argument_for_backend() {
+        assert_eq!(
+            team_argument_for_backend("RUST", "RUST_2"),
+            "team_id:\"RUST_2\","
+        );
+        assert_eq!(team_argument_for_backend("PYTHON", "PYTHON"), "");
     }
 
     #[test]
-    #[should_panic(expected = "Unknown team: INVALID")]
-    fn test_report_mutation_for_invalid_team() {
-        report_mutation_for_team("INVALID");
+    fn test_histogram_argument() {
+        assert_eq!(histogram_argument(&[]), "[]");
+        // 1 ms and below go to the first bucket, 2 ms to the first bucket of the next doubling
+        assert_eq!(histogram_argument(&[0, 1, 2, 1]), "[3,0,0,0,1]");
     }
 }